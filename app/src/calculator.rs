@@ -1,37 +1,67 @@
-use crate::parser::evaluations::calculate_with_vars;
-use crate::parser::symbolic::{num_to_letter_vec, shunting_yard};
-use crate::{ComplexApp, Message};
+use crate::parser::ast::{Constant, Expr, Real};
+use crate::parser::definitions::Definitions;
+use crate::parser::error::Error;
+use crate::parser::evaluations::{calculate_branches, calculate_with_vars};
+use crate::parser::exact::{self, Gaussian, Value};
+use crate::parser::matrix::{self, Matrix};
+use crate::parser::precise::{BigComplex, Precise, DEFAULT_DIGITS};
+use crate::parser::symbolic::{check_name, parse_statement, Statement, ANSWER};
+use crate::{error_message, ComplexApp, Message};
 use iced::{
-    button, scrollable, text_input, Button, Checkbox, Column, Container,
-    Element, Length, Row, Scrollable, Text, TextInput,
+    button, pick_list, scrollable, text_input, Button, Checkbox, Column,
+    Container, Element, Length, PickList, Row, Scrollable, Subscription, Text,
+    TextInput,
 };
+use iced_native::keyboard::{self, KeyCode};
+use iced_native::{event, subscription, Event};
 use num_complex::Complex;
-use std::collections::HashMap;
+use num_rational::BigRational;
+use num_traits::Zero;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Default)]
 pub struct CalculatorState {
     // Variables
     is_polar: bool,
+    name_input: String,
+    name_input_state: text_input::State,
     real_input: String,
     real_input_state: text_input::State,
     i_input: String,
     i_input_state: text_input::State,
     var_button: button::State,
+    var_error: Option<Error>,
+    /// Orders the variables, the last saved is listed first.
     var_counter: i32,
     var_scroll: scrollable::State,
-    variables: HashMap<String, (i32, Complex<f32>)>,
+    variables: HashMap<String, (i32, Complex<f64>)>,
+    /// Variables saved exactly or in arbitrary precision, also kept in
+    /// `variables` rounded to double precision.
+    stored: HashMap<String, Stored>,
+    matrices: HashMap<String, (i32, Matrix)>,
+    definitions: Definitions,
     // Calculations
     calc_input: String,
     calc_input_state: text_input::State,
     calc_button: button::State,
     calculation_scroll: scrollable::State,
-    calculations: Vec<(String, Complex<f32>)>,
+    calculations: Vec<Calculation>,
+    calc_error: Option<Error>,
+    /// The history entry recalled with the arrow keys, the newest is `0`.
+    recalled: Option<usize>,
+    /// The input typed before recalling, restored going past the newest
+    /// entry.
+    draft: String,
+    precision_list: pick_list::State<Precision>,
+    precision: Precision,
+    digits_input: String,
+    digits_input_state: text_input::State,
     // Delete variable
     delete_input: String,
     delete_input_state: text_input::State,
     delete_button: button::State,
     // Checkbox
-    save_calcs: bool,
+    all_branches: bool,
     // Clearing
     clear_vars_button: button::State,
     clear_calcs_button: button::State,
@@ -41,37 +71,383 @@ pub struct CalculatorState {
 pub enum CalcMessage {
     TogglePolar(bool),
     ChangeCalcInput(String),
+    ChangeNameInput(String),
     ChangeRealInput(String),
     ChangeImaginaryInput(String),
     ChangeDeleteInput(String),
-    SaveCalculations(bool),
+    ShowAllBranches(bool),
+    PrecisionSelected(Precision),
+    ChangeDigits(String),
     DeleteVariable(String),
+    /// Shows the calculation at an index of the history in its next form.
+    ToggleForm(usize),
+    /// Puts the input of the calculation at an index of the history back in
+    /// the calculation input.
+    LoadCalculation(usize),
+    /// Recalls the input of the calculation before the one recalled.
+    RecallPrevious,
+    /// Recalls the input of the calculation after the one recalled.
+    RecallNext,
     Calculate,
     Save,
     ClearVars,
     ClearCalcs,
 }
 
+impl CalculatorState {
+    /// The saved variables, for other pages to evaluate with.
+    pub fn variable_values(&self) -> HashMap<String, Complex<f64>> {
+        self.variables
+            .iter()
+            .map(|(k, (_, z))| (k.clone(), *z))
+            .collect()
+    }
+
+    pub fn definitions(&self) -> &Definitions {
+        &self.definitions
+    }
+
+    /// Significant digits of the arbitrary precision mode.
+    fn digits(&self) -> usize {
+        self.digits_input.trim().parse().unwrap_or(DEFAULT_DIGITS)
+    }
+
+    /// The saved variables to evaluate with in arbitrary precision, the ones
+    /// saved in single or double precision are read as their shortest
+    /// decimal.
+    fn precise_values(
+        &self,
+        precise: &Precise,
+    ) -> Result<HashMap<String, BigComplex>, Error> {
+        self.variables
+            .iter()
+            .map(|(k, (_, value))| {
+                let z = match self.stored.get(k) {
+                    Some(Stored::Precise(z)) => z.clone(),
+                    Some(Stored::Exact(z)) => {
+                        let part = |x: &BigRational| {
+                            let numerator =
+                                precise.decimal(&x.numer().to_string());
+                            let denominator =
+                                precise.decimal(&x.denom().to_string());
+                            numerator.zip(denominator).map(|(n, d)| n / d)
+                        };
+                        match part(&z.re).zip(part(&z.im)) {
+                            Some((re, im)) => BigComplex::new(re, im),
+                            None => precise.from_f64(*value)?,
+                        }
+                    }
+                    None => precise.from_f64(*value)?,
+                };
+                Ok((k.clone(), z))
+            })
+            .collect()
+    }
+
+    /// The saved variables to evaluate with exactly, the ones saved in
+    /// floating point stay approximate.
+    fn exact_values(&self) -> HashMap<String, Value> {
+        self.variables
+            .iter()
+            .map(|(k, (_, z))| {
+                let z = match self.stored.get(k) {
+                    Some(Stored::Exact(z)) => Value::Exact(z.clone()),
+                    _ => Value::Approximate(*z),
+                };
+                (k.clone(), z)
+            })
+            .collect()
+    }
+
+    /// The saved numbers and matrices, to evaluate with matrices.
+    fn matrix_values(&self) -> HashMap<String, matrix::Value> {
+        let numbers = self
+            .variables
+            .iter()
+            .map(|(k, (_, z))| (k.clone(), matrix::Value::Scalar(*z)));
+        let matrices = self
+            .matrices
+            .iter()
+            .map(|(k, (_, m))| (k.clone(), matrix::Value::Matrix(m.clone())));
+        numbers.chain(matrices).collect()
+    }
+
+    /// Saves `z` as the variable `name`, replacing an earlier value.
+    fn save_variable(
+        &mut self,
+        name: &str,
+        z: Complex<f64>,
+        stored: Option<Stored>,
+    ) {
+        let n = self.var_counter;
+        match stored {
+            Some(stored) => self.stored.insert(name.to_string(), stored),
+            None => self.stored.remove(name),
+        };
+        self.matrices.remove(name);
+        self.variables.insert(name.to_string(), (n, z));
+        self.var_counter += 1;
+    }
+
+    /// Saves a result as the variable `name`, replacing an earlier value.
+    fn save(&mut self, name: &str, value: Calculated) {
+        match value {
+            Calculated::Number(z, stored, _) => {
+                self.save_variable(name, z, stored)
+            }
+            Calculated::Matrix(m) => {
+                self.variables.remove(name);
+                self.stored.remove(name);
+                self.matrices
+                    .insert(name.to_string(), (self.var_counter, m));
+                self.var_counter += 1;
+            }
+        }
+    }
+
+    /// Saves the number in the variable fields, in the precision selected,
+    /// under the name typed, which is returned.
+    fn save_input(&mut self) -> Result<String, Error> {
+        let name = self.name_input.trim().to_string();
+        check_name(&name, &self.definitions)?;
+        match self.precision {
+            Precision::Arbitrary => {
+                let precise = Precise::new(self.digits());
+                let part = |input: &str| {
+                    precise
+                        .decimal(input)
+                        .unwrap_or_else(|| precise.decimal("0").unwrap())
+                };
+                let real = part(&self.real_input);
+                let im = part(&self.i_input);
+                let res = if self.is_polar {
+                    precise.from_polar(&real, &im)
+                } else {
+                    BigComplex::new(real, im)
+                };
+                self.save_variable(
+                    &name,
+                    res.to_f64(),
+                    Some(Stored::Precise(res)),
+                );
+            }
+            Precision::Exact if !self.is_polar => {
+                // Read with every digit typed, as literals in expressions
+                let part = |input: &str| {
+                    exact::from_literal(input.trim())
+                        .map_or_else(BigRational::zero, |z| z.re)
+                };
+                let res =
+                    Complex::new(part(&self.real_input), part(&self.i_input));
+                let z = Value::Exact(res.clone()).to_f64();
+                self.save_variable(&name, z, Some(Stored::Exact(res)));
+            }
+            _ => {
+                let part =
+                    |input: &str| input.trim().parse::<f64>().unwrap_or(0.0);
+                let real = part(&self.real_input);
+                let im = part(&self.i_input);
+                let res = if self.is_polar {
+                    Complex::from_polar(real, im)
+                } else {
+                    Complex::new(real, im)
+                };
+                self.save_variable(&name, res, None);
+            }
+        }
+        Ok(name)
+    }
+
+    /// The values of `expr` in `precision`, or every root it has when
+    /// `all_branches` is set.
+    fn evaluate(
+        &self,
+        expr: &Expr,
+        precision: Precision,
+        digits: usize,
+        all_branches: bool,
+    ) -> Result<Vec<Calculated>, Error> {
+        let variables = &self.variables;
+        let definitions = &self.definitions;
+        // Matrices are computed in double precision, on the principal branch
+        let matrices = self.matrix_values();
+        if matrix::uses_matrices(expr, &matrices, definitions) {
+            return Ok(vec![
+                match matrix::eval(expr, &matrices, definitions)? {
+                    matrix::Value::Scalar(z) => {
+                        Calculated::Number(z, None, z.to_string())
+                    }
+                    matrix::Value::Matrix(m) => Calculated::Matrix(m),
+                },
+            ]);
+        }
+        match precision {
+            Precision::Single => {
+                calculate::<f32>(expr, variables, definitions, all_branches)
+                    .map(double_values)
+            }
+            Precision::Double => {
+                calculate::<f64>(expr, variables, definitions, all_branches)
+                    .map(double_values)
+            }
+            Precision::Arbitrary => {
+                calculate_precisely(self, expr, digits, all_branches)
+            }
+            Precision::Exact => calculate_exactly(self, expr, all_branches),
+        }
+    }
+
+    /// Stops the history entries assigning `name` from saving it again when
+    /// they are computed again, as it was saved since.
+    fn release(&mut self, name: &str) {
+        for calculation in self.calculations.iter_mut() {
+            if calculation.name.as_deref() == Some(name) {
+                calculation.name = None;
+            }
+        }
+    }
+
+    /// Computes again, oldest first, the history entries using one of the
+    /// variables or functions `changed`. Entries still assigning a variable
+    /// save its new value, so the entries after them using it follow.
+    ///
+    /// Entries using `ans` are left as they are, it stood for the result
+    /// before them.
+    fn recalculate(&mut self, changed: &[String]) {
+        let mut changed: HashSet<String> = changed.iter().cloned().collect();
+        for i in (0..self.calculations.len()).rev() {
+            let calculation = &self.calculations[i];
+            if calculation.dependencies.contains(ANSWER)
+                || calculation.dependencies.is_disjoint(&changed)
+            {
+                continue;
+            }
+            let values = self.evaluate(
+                &calculation.expr,
+                calculation.precision,
+                calculation.digits,
+                calculation.all_branches,
+            );
+            let calculation = &mut self.calculations[i];
+            // A function redefined may use other variables
+            let dependencies = calculation.expr.dependencies(&self.definitions);
+            calculation.dependencies.extend(dependencies);
+            match values {
+                Ok(mut values) => {
+                    calculation.values = Ok(shown(&values, calculation.digits));
+                    if let Some(name) = calculation.name.clone() {
+                        self.save(&name, values.swap_remove(0));
+                        changed.insert(name);
+                    }
+                }
+                // Its variable keeps the last value it had
+                Err(e) => calculation.values = Err(e),
+            }
+        }
+    }
+
+    /// Shows the input of the history entry at `index` in the calculation
+    /// input, ready to be edited.
+    fn recall(&mut self, index: usize) {
+        if let Some(calculation) = self.calculations.get(index) {
+            if self.recalled.is_none() {
+                self.draft = self.calc_input.clone();
+            }
+            self.calc_input = calculation.source.clone();
+            self.calc_error = None;
+            self.recalled = Some(index);
+            self.calc_input_state.focus();
+            self.calc_input_state.move_cursor_to_end();
+        }
+    }
+}
+
+/// Up and Down, when no widget used them, go through the inputs of the
+/// history.
+pub fn calculator_subscription() -> Subscription<Message> {
+    subscription::events_with(|event, status| match (event, status) {
+        (
+            Event::Keyboard(keyboard::Event::KeyPressed { key_code, .. }),
+            event::Status::Ignored,
+        ) => match key_code {
+            KeyCode::Up => Some(CalcMessage::RecallPrevious),
+            KeyCode::Down => Some(CalcMessage::RecallNext),
+            _ => None,
+        }
+        .map(Message::Calculator),
+        _ => None,
+    })
+}
+
 pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
-    let mut hash_vec: Vec<(&String, &(i32, Complex<f32>))> =
-        app.calculator.variables.iter().collect();
-    hash_vec.sort_by(|(_, (a, _)), (_, (b, _))| b.cmp(a));
+    let precise = match app.calculator.precision {
+        Precision::Arbitrary => Some(Precise::new(app.calculator.digits())),
+        _ => None,
+    };
+    let mut saved: Vec<(i32, String)> = Vec::new();
+    for (k, (n, v)) in app.calculator.variables.iter() {
+        let text = match (&precise, app.calculator.stored.get(k)) {
+            (_, Some(Stored::Exact(z))) => {
+                format!("{}:  {}", k, exact::format(z))
+            }
+            (Some(precise), Some(Stored::Precise(z))) => {
+                format!("{}:  {}", k, precise.format(z))
+            }
+            _ => format!("{}:  {} - polar {:?}", k, v, v.to_polar()),
+        };
+        saved.push((*n, text));
+    }
+    for (k, (n, m)) in app.calculator.matrices.iter() {
+        saved.push((*n, format!("{}:  {}", k, m)));
+    }
+    saved.sort_by(|(a, _), (b, _)| b.cmp(a));
 
     let mut variables: Column<Message> = Column::new();
-    for (k, (_, v)) in hash_vec.iter() {
-        variables = variables.push(Text::new(format!(
-            "{}:  {} - polar {:?}",
-            k,
-            v,
-            v.to_polar()
-        )));
+    for (_, text) in saved {
+        variables = variables.push(Text::new(text));
+    }
+    for definition in app.calculator.definitions.sorted() {
+        variables = variables.push(Text::new(definition.source.clone()));
     }
 
+    let mut constants: Column<Message> =
+        Column::new().push(Text::new("Constants").size(16));
+    for constant in Constant::ALL.iter() {
+        constants = constants.push(
+            Text::new(format!(
+                "{}:  {}",
+                constant.name(),
+                constant.value::<f64>().re
+            ))
+            .size(14),
+        );
+    }
+    constants = constants.push(Text::new("i:  0+1i").size(14));
+
     let mut calculations: Column<Message> = Column::new();
 
-    for (left, right) in app.calculator.calculations.iter() {
-        calculations =
-            calculations.push(Text::new(format!("{} = {}", left, right)));
+    for (i, calculation) in app.calculator.calculations.iter_mut().enumerate() {
+        let mut lines: Column<Message> = Column::new();
+        for line in calculation.lines() {
+            lines = lines.push(Text::new(line));
+        }
+        calculations = calculations.push(
+            Row::new()
+                .push(
+                    Button::new(&mut calculation.load_button, lines)
+                        .width(Length::Fill)
+                        .on_press(Message::Calculator(
+                            CalcMessage::LoadCalculation(i),
+                        )),
+                )
+                .push(
+                    Button::new(
+                        &mut calculation.form_button,
+                        Text::new(calculation.form.to_string()).size(14),
+                    )
+                    .on_press(Message::Calculator(CalcMessage::ToggleForm(i))),
+                ),
+        );
     }
 
     let real = if app.calculator.is_polar { "r" } else { "x" };
@@ -86,6 +462,19 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
         "+ i "
     };
 
+    // Only arbitrary precision takes a number of digits
+    let digits_input: Element<_> = match app.calculator.precision {
+        Precision::Arbitrary => TextInput::new(
+            &mut app.calculator.digits_input_state,
+            &format!("Digits ({})", DEFAULT_DIGITS),
+            &app.calculator.digits_input,
+            |v| Message::Calculator(CalcMessage::ChangeDigits(v)),
+        )
+        .width(Length::from(120))
+        .into(),
+        _ => Text::new("").into(),
+    };
+
     let row: Element<_> = Row::new()
         .push(
             Column::new()
@@ -97,18 +486,25 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
                             .push(Text::new("Calculations"))
                             .push(
                                 Row::new()
-                                    .push(TextInput::new(
-                                        &mut app.calculator.calc_input_state,
-                                        "Enter vars",
-                                        &app.calculator.calc_input,
-                                        |v| {
-                                            Message::Calculator(
+                                    .push(
+                                        TextInput::new(
+                                            &mut app
+                                                .calculator
+                                                .calc_input_state,
+                                            "Enter a calculation",
+                                            &app.calculator.calc_input,
+                                            |v| {
+                                                Message::Calculator(
                                                 CalcMessage::ChangeCalcInput(
                                                     String::from(v),
                                                 ),
                                             )
-                                        },
-                                    ))
+                                            },
+                                        )
+                                        .on_submit(Message::Calculator(
+                                            CalcMessage::Calculate,
+                                        )),
+                                    )
                                     .push(
                                         Button::new(
                                             &mut app.calculator.calc_button,
@@ -119,6 +515,7 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
                                         )),
                                     ),
                             )
+                            .push(error_message(&app.calculator.calc_error))
                             .push(
                                 Scrollable::new(
                                     &mut app.calculator.calculation_scroll,
@@ -129,15 +526,29 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
                     .height(Length::FillPortion(1)),
                 )
                 .push(
-                    Container::new(Checkbox::new(
-                        app.calculator.save_calcs,
-                        "Save calculations to variables",
-                        |checked| {
-                            Message::Calculator(CalcMessage::SaveCalculations(
-                                checked,
+                    Container::new(
+                        Row::new()
+                            .push(Checkbox::new(
+                                app.calculator.all_branches,
+                                "Show every root",
+                                |checked| {
+                                    Message::Calculator(
+                                        CalcMessage::ShowAllBranches(checked),
+                                    )
+                                },
                             ))
-                        },
-                    ))
+                            .push(PickList::new(
+                                &mut app.calculator.precision_list,
+                                &Precision::ALL[..],
+                                Some(app.calculator.precision),
+                                |p| {
+                                    Message::Calculator(
+                                        CalcMessage::PrecisionSelected(p),
+                                    )
+                                },
+                            ))
+                            .push(digits_input),
+                    )
                     .height(Length::from(32)),
                 )
                 .push(
@@ -184,6 +595,20 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
                 .push(Container::new(Text::new("")).height(Length::from(16)))
                 .push(
                     Row::new()
+                        .push(
+                            TextInput::new(
+                                &mut app.calculator.name_input_state,
+                                "name",
+                                &app.calculator.name_input,
+                                |v| {
+                                    Message::Calculator(
+                                        CalcMessage::ChangeNameInput(v),
+                                    )
+                                },
+                            )
+                            .width(Length::from(80)),
+                        )
+                        .push(Text::new(" = "))
                         .push(TextInput::new(
                             &mut app.calculator.real_input_state,
                             real,
@@ -213,16 +638,22 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
                             .on_press(Message::Calculator(CalcMessage::Save)),
                         ),
                 )
+                .push(error_message(&app.calculator.var_error))
                 .push(
                     Scrollable::new(&mut app.calculator.var_scroll)
                         .push(variables)
+                        .push(
+                            Container::new(Text::new(""))
+                                .height(Length::from(16)),
+                        )
+                        .push(constants)
                         .height(Length::FillPortion(1)),
                 )
                 .push(
                     Row::new()
                         .push(TextInput::new(
                             &mut app.calculator.delete_input_state,
-                            "Delete variables - (a, b, ...)",
+                            "Delete variables or functions - (a, f, ...)",
                             &app.calculator.delete_input,
                             |v| {
                                 Message::Calculator(
@@ -251,7 +682,18 @@ pub fn render_calculator(app: &mut ComplexApp) -> Element<Message> {
 
 pub fn process_calculator_message(app: &mut ComplexApp, message: CalcMessage) {
     match message {
-        CalcMessage::SaveCalculations(b) => app.calculator.save_calcs = b,
+        CalcMessage::ChangeNameInput(v) => {
+            app.calculator.name_input = v;
+            app.calculator.var_error = None;
+        }
+        CalcMessage::ShowAllBranches(b) => app.calculator.all_branches = b,
+        CalcMessage::PrecisionSelected(p) => app.calculator.precision = p,
+        CalcMessage::ChangeDigits(v) => app.calculator.digits_input = v,
+        CalcMessage::ToggleForm(i) => {
+            if let Some(calculation) = app.calculator.calculations.get_mut(i) {
+                calculation.form = calculation.form.next();
+            }
+        }
         CalcMessage::TogglePolar(b) => app.calculator.is_polar = b,
         CalcMessage::ChangeRealInput(v) => {
             app.calculator.real_input = v;
@@ -259,8 +701,31 @@ pub fn process_calculator_message(app: &mut ComplexApp, message: CalcMessage) {
         CalcMessage::ChangeImaginaryInput(v) => {
             app.calculator.i_input = v;
         }
+        CalcMessage::LoadCalculation(i) => app.calculator.recall(i),
+        CalcMessage::RecallPrevious => {
+            if app.calculator.calc_input_state.is_focused() {
+                let i = app.calculator.recalled.map_or(0, |i| i + 1);
+                app.calculator.recall(i);
+            }
+        }
+        CalcMessage::RecallNext => {
+            if app.calculator.calc_input_state.is_focused() {
+                match app.calculator.recalled {
+                    Some(0) => {
+                        app.calculator.calc_input =
+                            std::mem::take(&mut app.calculator.draft);
+                        app.calculator.recalled = None;
+                        app.calculator.calc_input_state.move_cursor_to_end();
+                    }
+                    Some(i) => app.calculator.recall(i - 1),
+                    None => {}
+                }
+            }
+        }
         CalcMessage::ChangeCalcInput(v) => {
             app.calculator.calc_input = v;
+            app.calculator.calc_error = None;
+            app.calculator.recalled = None;
         }
 
         CalcMessage::ChangeDeleteInput(v) => {
@@ -271,56 +736,370 @@ pub fn process_calculator_message(app: &mut ComplexApp, message: CalcMessage) {
             let var: Vec<&str> = var.split(",").collect();
             for v in var.iter() {
                 app.calculator.variables.remove(&v.to_string());
+                app.calculator.stored.remove(&v.to_string());
+                app.calculator.matrices.remove(&v.to_string());
+                app.calculator.definitions.remove(v);
+                app.calculator.release(v);
             }
+            let var: Vec<String> = var.iter().map(|v| v.to_string()).collect();
+            app.calculator.recalculate(&var);
             app.calculator.delete_input = "".to_string();
         }
-        CalcMessage::Save => {
-            let r = app.calculator.real_input.clone().parse::<f32>();
-            let i = app.calculator.i_input.clone().parse::<f32>();
-            let real;
-            match r {
-                Ok(v) => real = v,
-                Err(_) => real = 0.0,
+        CalcMessage::Save => match app.calculator.save_input() {
+            Ok(name) => {
+                app.calculator.release(&name);
+                app.calculator.recalculate(&[name]);
+                app.calculator.name_input = "".to_string();
+                app.calculator.real_input = "".to_string();
+                app.calculator.i_input = "".to_string();
+                app.calculator.var_error = None;
             }
-            let im;
-            match i {
-                Ok(v) => im = v,
-                Err(_) => im = 0.0,
-            }
-            let res = if app.calculator.is_polar {
-                Complex::from_polar(real, im)
-            } else {
-                Complex::new(real, im)
-            };
-            let n = app.calculator.var_counter;
-            let id = num_to_letter_vec(n as usize).join("");
-            app.calculator.variables.insert(id, (n, res));
-            app.calculator.var_counter += 1;
-            app.calculator.real_input = "".to_string();
-            app.calculator.i_input = "".to_string();
-        }
+            Err(e) => app.calculator.var_error = Some(e),
+        },
         CalcMessage::Calculate => {
-            let input = app.calculator.calc_input.replace(" ", "");
-            let algorithm = shunting_yard(input.clone());
-            let z = calculate_with_vars(algorithm, &app.calculator.variables);
-
-            if let Some(z) = z {
+            let source = app.calculator.calc_input.clone();
+            let statement =
+                parse_statement(&source, &app.calculator.definitions);
+            let (name, expr) = match statement {
+                Ok(Statement::Definition(definition)) => {
+                    let name = definition.name.clone();
+                    app.calculator.definitions.insert(definition);
+                    app.calculator.recalculate(&[name]);
+                    app.calculator.calc_input = "".to_string();
+                    app.calculator.calc_error = None;
+                    app.calculator.recalled = None;
+                    return;
+                }
+                Ok(Statement::Expr(expr)) => (None, expr),
+                Ok(Statement::Assignment(name, expr)) => (Some(name), expr),
+                // Keep the input so it can be fixed
+                Err(e) => {
+                    app.calculator.calc_error = Some(e);
+                    return;
+                }
+            };
+            let precision = app.calculator.precision;
+            let digits = app.calculator.digits();
+            let all_branches = app.calculator.all_branches;
+            let values =
                 app.calculator
-                    .calculations
-                    .insert(0, (app.calculator.calc_input.clone(), z));
-                if app.calculator.save_calcs {
-                    let n = app.calculator.var_counter;
-                    let id = num_to_letter_vec(n as usize).join("");
-                    app.calculator.variables.insert(id, (n, z));
-                    app.calculator.var_counter += 1;
+                    .evaluate(&expr, precision, digits, all_branches);
+
+            match values {
+                Ok(mut values) => {
+                    let calculation = Calculation {
+                        source,
+                        input: match &name {
+                            Some(name) => format!("{} = {}", name, expr),
+                            None => expr.to_string(),
+                        },
+                        name: name.clone(),
+                        dependencies: expr
+                            .dependencies(&app.calculator.definitions),
+                        expr,
+                        precision,
+                        digits,
+                        all_branches,
+                        values: Ok(shown(&values, digits)),
+                        form: Form::default(),
+                        load_button: button::State::new(),
+                        form_button: button::State::new(),
+                    };
+                    // Only the principal value is saved
+                    let value = values.swap_remove(0);
+                    if let Some(name) = name {
+                        app.calculator.release(&name);
+                        app.calculator.save(&name, value.clone());
+                        app.calculator.recalculate(&[name]);
+                    }
+                    app.calculator.save(ANSWER, value);
+                    app.calculator.calculations.insert(0, calculation);
+                    app.calculator.calc_input = "".to_string();
+                    app.calculator.calc_error = None;
+                    app.calculator.recalled = None;
                 }
+                // Keep the input so it can be fixed
+                Err(e) => app.calculator.calc_error = Some(e),
             }
-            app.calculator.calc_input = "".to_string();
         }
-        CalcMessage::ClearCalcs => app.calculator.calculations = Vec::new(),
+        CalcMessage::ClearCalcs => {
+            app.calculator.calculations = Vec::new();
+            app.calculator.recalled = None;
+        }
         CalcMessage::ClearVars => {
+            let mut names: Vec<String> =
+                app.calculator.variables.keys().cloned().collect();
+            names.extend(app.calculator.matrices.keys().cloned());
+            for definition in app.calculator.definitions.sorted() {
+                names.push(definition.name.clone());
+            }
             app.calculator.variables = HashMap::new();
+            app.calculator.stored = HashMap::new();
+            app.calculator.matrices = HashMap::new();
+            app.calculator.definitions.clear();
             app.calculator.var_counter = 0;
+            for calculation in app.calculator.calculations.iter_mut() {
+                calculation.name = None;
+            }
+            app.calculator.recalculate(&names);
         }
     };
 }
+
+/// The value of `expr`, or every root it has when `all_branches` is set.
+fn calculate<T: Real>(
+    expr: &Expr,
+    variables: &HashMap<String, (i32, Complex<f64>)>,
+    definitions: &Definitions,
+    all_branches: bool,
+) -> Result<Vec<Complex<f64>>, Error> {
+    if all_branches {
+        calculate_branches::<T>(expr, variables, definitions)
+    } else {
+        calculate_with_vars::<T>(expr, variables, definitions).map(|z| vec![z])
+    }
+}
+
+/// An entry of the history, shown in the form picked for it, and kept
+/// with what it was computed from so it can be recalled and computed again.
+struct Calculation {
+    /// The input as typed.
+    source: String,
+    /// The input as parsed, `name = expr` for assignments.
+    input: String,
+    /// The variable the value is saved in, until it is saved otherwise.
+    name: Option<String>,
+    expr: Expr,
+    /// The variables and user functions the value depends on.
+    dependencies: HashSet<String>,
+    precision: Precision,
+    digits: usize,
+    all_branches: bool,
+    /// The value of every branch, or why it could not be computed again.
+    values: Result<Vec<Shown>, Error>,
+    form: Form,
+    load_button: button::State,
+    form_button: button::State,
+}
+
+impl Calculation {
+    /// The calculation as `input = value`, one line per branch, with the
+    /// value written so it can be typed back.
+    fn lines(&self) -> Vec<String> {
+        let values = match &self.values {
+            Ok(values) => values,
+            Err(e) => return vec![format!("{}: {}", self.input, e.kind)],
+        };
+        values
+            .iter()
+            .enumerate()
+            .map(|(k, shown)| {
+                let value = match self.form {
+                    Form::Rectangular => &shown.rectangular,
+                    Form::Polar => &shown.polar,
+                    Form::Exponential => &shown.exponential,
+                };
+                if values.len() > 1 {
+                    format!("{}, k = {} = {}", self.input, k, value)
+                } else {
+                    format!("{} = {}", self.input, value)
+                }
+            })
+            .collect()
+    }
+}
+
+/// A value of a calculation in each form, polar ones in the precision it
+/// was computed with.
+struct Shown {
+    rectangular: String,
+    polar: String,
+    exponential: String,
+}
+
+/// The values computed, as they are shown. Matrices are shown with each
+/// entry in the form.
+fn shown(values: &[Calculated], digits: usize) -> Vec<Shown> {
+    let polar = |(modulus, argument): (String, String)| {
+        format!("{}∠{}", modulus, argument)
+    };
+    let exponential = |(modulus, argument): (String, String)| {
+        format!("{}e^({}i)", modulus, argument)
+    };
+    let double = |z: &Complex<f64>| (z.norm().to_string(), z.arg().to_string());
+    values
+        .iter()
+        .map(|value| match value {
+            Calculated::Number(z, stored, text) => {
+                let parts = match stored {
+                    Some(Stored::Precise(z)) => {
+                        Precise::new(digits).format_polar(z)
+                    }
+                    _ => double(z),
+                };
+                Shown {
+                    rectangular: text.clone(),
+                    polar: polar(parts.clone()),
+                    exponential: exponential(parts),
+                }
+            }
+            Calculated::Matrix(m) => Shown {
+                rectangular: m.to_string(),
+                polar: m.format(|z| polar(double(z))),
+                exponential: m.format(|z| exponential(double(z))),
+            },
+        })
+        .collect()
+}
+
+/// How a result is written, `a + bi`, `r∠θ` or `re^(θi)`, with the angle in
+/// radians.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    Rectangular,
+    Polar,
+    Exponential,
+}
+
+impl Form {
+    fn next(self) -> Form {
+        match self {
+            Form::Rectangular => Form::Polar,
+            Form::Polar => Form::Exponential,
+            Form::Exponential => Form::Rectangular,
+        }
+    }
+}
+
+impl Default for Form {
+    fn default() -> Form {
+        Form::Rectangular
+    }
+}
+
+impl std::fmt::Display for Form {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Form::Rectangular => "Rectangular",
+                Form::Polar => "Polar",
+                Form::Exponential => "Exponential",
+            }
+        )
+    }
+}
+
+/// A saved variable in the precision it was computed with.
+#[derive(Debug, Clone)]
+enum Stored {
+    Precise(BigComplex),
+    Exact(Gaussian),
+}
+
+/// A result, a number rounded to double precision as it is saved and its
+/// text, or a matrix.
+#[derive(Debug, Clone)]
+enum Calculated {
+    Number(Complex<f64>, Option<Stored>, String),
+    Matrix(Matrix),
+}
+
+/// A value computed in single or double precision, with its text.
+fn double_values(values: Vec<Complex<f64>>) -> Vec<Calculated> {
+    values
+        .into_iter()
+        .map(|z| Calculated::Number(z, None, z.to_string()))
+        .collect()
+}
+
+/// The values of `expr` with the digits asked for, also rounded to double
+/// precision.
+fn calculate_precisely(
+    calculator: &CalculatorState,
+    expr: &Expr,
+    digits: usize,
+    all_branches: bool,
+) -> Result<Vec<Calculated>, Error> {
+    let precise = Precise::new(digits);
+    let variables = calculator.precise_values(&precise)?;
+    let values = if all_branches {
+        precise.eval_branches(expr, &variables, &calculator.definitions)?
+    } else {
+        vec![precise.eval(expr, &variables, &calculator.definitions)?]
+    };
+    Ok(values
+        .into_iter()
+        .map(|z| {
+            let text = precise.format(&z);
+            Calculated::Number(z.to_f64(), Some(Stored::Precise(z)), text)
+        })
+        .collect())
+}
+
+/// The value of `expr` kept exact while it only takes rational operations,
+/// every root in double precision when there are several.
+fn calculate_exactly(
+    calculator: &CalculatorState,
+    expr: &Expr,
+    all_branches: bool,
+) -> Result<Vec<Calculated>, Error> {
+    if all_branches {
+        let values = calculate_branches::<f64>(
+            expr,
+            &calculator.variables,
+            &calculator.definitions,
+        )?;
+        if values.len() > 1 {
+            return Ok(double_values(values));
+        }
+    }
+    let value =
+        exact::eval(expr, &calculator.exact_values(), &calculator.definitions)?;
+    let text = value.to_string();
+    let stored = match &value {
+        Value::Exact(z) => Some(Stored::Exact(z.clone())),
+        Value::Approximate(_) => None,
+    };
+    Ok(vec![Calculated::Number(value.to_f64(), stored, text)])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    Double,
+    /// As many significant digits as asked for.
+    Arbitrary,
+    /// Gaussian rationals, falling back to double precision.
+    Exact,
+}
+
+impl Precision {
+    const ALL: [Precision; 4] = [
+        Precision::Single,
+        Precision::Double,
+        Precision::Arbitrary,
+        Precision::Exact,
+    ];
+}
+
+impl Default for Precision {
+    fn default() -> Precision {
+        Precision::Double
+    }
+}
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Precision::Single => "Single precision",
+                Precision::Double => "Double precision",
+                Precision::Arbitrary => "Arbitrary precision",
+                Precision::Exact => "Exact",
+            }
+        )
+    }
+}
@@ -1,25 +1,618 @@
-use crate::{ComplexApp, Message};
-use iced::{Container, Element, Length, Text};
+use crate::parser::ast::{Context, Expr};
+use crate::parser::derivatives::{cauchy_riemann, derivative, CauchyRiemann};
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::evaluations::Progress;
+use crate::parser::functions::Arity;
+use crate::parser::integrals::{contour_integral, Contour, Integral};
+use crate::parser::lexer::Span;
+use crate::parser::printing::latex;
+use crate::parser::residues::{
+    laurent, residue_sum, symbolic_residue, Laurent, ResidueSum, Singularity,
+    MAX_TERMS, RADIUS,
+};
+use crate::parser::symbolic::{parse_list, parse_with};
+use crate::{error_message, ComplexApp, Message};
+use iced::{
+    button, futures::channel::oneshot, pick_list, scrollable, text_input, time,
+    Button, Column, Command, Container, Element, Length, PickList, ProgressBar,
+    Row, Scrollable, Subscription, Text, TextInput,
+};
+use num_complex::Complex;
+use num_traits::Zero;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Laurent coefficients listed when no number of terms is given.
+const TERMS: usize = 6;
 
 #[derive(Default)]
-pub struct CalculusState {}
+pub struct CalculusState {
+    function_input: String,
+    function_input_state: text_input::State,
+    function_error: Option<Error>,
+    point_input: String,
+    point_input_state: text_input::State,
+    point_error: Option<Error>,
+    differentiate_button: button::State,
+    // Contour integral
+    contour_kind: ContourKind,
+    contour_kind_list: pick_list::State<ContourKind>,
+    contour_input: String,
+    contour_input_state: text_input::State,
+    contour_error: Option<Error>,
+    integrate_button: button::State,
+    // The search for the poles inside the contour, on the thread pool
+    residue_search: Option<Arc<Progress>>,
+    progress: f32,
+    cancel_button: button::State,
+    // Residue, at the point of the derivative
+    radius_input: String,
+    radius_input_state: text_input::State,
+    terms_input: String,
+    terms_input_state: text_input::State,
+    residue_button: button::State,
+    scroll: scrollable::State,
+    // Results
+    derivative: Option<Result<Expr, Error>>,
+    value: Option<Result<Complex<f64>, Error>>,
+    cauchy_riemann: Option<(Complex<f64>, CauchyRiemann)>,
+    integral: Option<Result<Integral, Error>>,
+    residue_sum: Option<Result<ResidueSum, Error>>,
+    laurent: Option<(Complex<f64>, Result<Laurent, Error>)>,
+    symbolic_residue: Option<(Expr, Complex<f64>)>,
+}
 
 #[derive(Debug, Clone)]
 pub enum CalculusMessage {
-    M,
+    ChangeFunction(String),
+    ChangePoint(String),
+    Differentiate,
+    ChangeContour(String),
+    ContourKindSelected(ContourKind),
+    Integrate,
+    ResiduesSummed(Arc<Progress>, Result<Option<ResidueSum>, Error>),
+    SearchProgress,
+    CancelSearch,
+    ChangeRadius(String),
+    ChangeTerms(String),
+    Residue,
+}
+
+/// The contours integrals are taken along, each written as a list in the
+/// contour input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContourKind {
+    Circle,
+    Segment,
+    Polyline,
+    Parametric,
+}
+
+impl ContourKind {
+    const ALL: [ContourKind; 4] = [
+        ContourKind::Circle,
+        ContourKind::Segment,
+        ContourKind::Polyline,
+        ContourKind::Parametric,
+    ];
+
+    fn placeholder(&self) -> &'static str {
+        match self {
+            ContourKind::Circle => "Center, radius",
+            ContourKind::Segment => "Start, end",
+            ContourKind::Polyline => "Points, like 1, i, -1, -i, 1",
+            ContourKind::Parametric => "γ(t), start, end, like e^(i t), 0, tau",
+        }
+    }
+}
+
+impl Default for ContourKind {
+    fn default() -> ContourKind {
+        ContourKind::Circle
+    }
+}
+
+impl std::fmt::Display for ContourKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ContourKind::Circle => "Circle",
+                ContourKind::Segment => "Segment",
+                ContourKind::Polyline => "Polyline",
+                ContourKind::Parametric => "Parametric",
+            }
+        )
+    }
 }
 
 pub fn render_calculus(app: &mut ComplexApp) -> Element<Message> {
-    Container::new(Text::new("Calculus"))
+    let state = &mut app.calculus;
+
+    let mut results: Column<Message> = Column::new().spacing(8);
+    match &state.derivative {
+        Some(Ok(derivative)) => {
+            results = results
+                .push(Text::new(format!("f'(z) = {}", derivative)))
+                .push(Text::new(format!("LaTeX: {}", latex(derivative))))
+        }
+        Some(Err(e)) => results = results.push(error_message(&Some(e.clone()))),
+        None => {}
+    }
+    match &state.value {
+        Some(Ok(value)) => {
+            results = results.push(Text::new(format!(
+                "f'({}) = {}",
+                state.point_input.trim(),
+                value
+            )))
+        }
+        Some(Err(e)) => results = results.push(error_message(&Some(e.clone()))),
+        None => {}
+    }
+    if let Some((point, cr)) = &state.cauchy_riemann {
+        results = results
+            .push(Text::new(format!("Cauchy-Riemann at {}", point)))
+            .push(Text::new(format!(
+                "u_x = {:.6}, v_y = {:.6}",
+                cr.u_x, cr.v_y
+            )))
+            .push(Text::new(format!(
+                "u_y = {:.6}, -v_x = {:.6}",
+                cr.u_y, -cr.v_x
+            )))
+            .push(Text::new(if cr.holds() {
+                format!("The equations hold, f'(z) = {:.6}", cr.derivative())
+            } else {
+                format!("Not holomorphic here, off by {:.3e}", cr.residual())
+            }));
+    }
+
+    let mut integral: Column<Message> = Column::new().spacing(8);
+    match &state.integral {
+        Some(Ok(result)) => {
+            // Only the circle is always closed
+            let sign = match state.contour_kind {
+                ContourKind::Circle => "∮",
+                _ => "∫",
+            };
+            integral = integral
+                .push(Text::new(format!("{} f(z) dz = {}", sign, result.value)))
+                .push(Text::new(format!(
+                    "Estimated error: {:.3e}",
+                    result.error
+                )));
+        }
+        Some(Err(e)) => {
+            integral = integral.push(error_message(&Some(e.clone())))
+        }
+        None => {}
+    }
+    match (&state.integral, &state.residue_sum) {
+        (Some(Ok(result)), Some(Ok(sum))) => {
+            integral = integral
+                .push(Text::new(format!("2πi Σ Res = {}", sum.value)))
+                .push(Text::new(format!(
+                    "Off the integral by {:.3e}",
+                    (result.value - sum.value).norm()
+                )));
+            for pole in &sum.poles {
+                integral = integral.push(Text::new(format!(
+                    "Res = {} at the pole of order {} at {}, winding {}",
+                    pole.residue, pole.order, pole.z, pole.winding
+                )));
+            }
+        }
+        (Some(Ok(_)), Some(Err(e))) => {
+            integral = integral.push(error_message(&Some(e.clone())))
+        }
+        _ => {}
+    }
+    if state.residue_search.is_some() {
+        integral = integral.push(
+            Row::new()
+                .spacing(10)
+                .push(Text::new("Summing the residues inside"))
+                .push(ProgressBar::new(0.0..=1.0, state.progress))
+                .push(
+                    Button::new(&mut state.cancel_button, Text::new("Cancel"))
+                        .on_press(Message::Calculus(
+                            CalculusMessage::CancelSearch,
+                        )),
+                ),
+        );
+    }
+
+    let mut residue: Column<Message> = Column::new().spacing(8);
+    match &state.laurent {
+        Some((point, Ok(laurent))) => {
+            residue = residue
+                .push(Text::new(match laurent.singularity {
+                    Singularity::Regular => {
+                        format!("f is regular at {}", point)
+                    }
+                    Singularity::Pole(1) => format!("Simple pole at {}", point),
+                    Singularity::Pole(m) => {
+                        format!("Pole of order {} at {}", m, point)
+                    }
+                    Singularity::Essential => {
+                        format!("Essential singularity at {}", point)
+                    }
+                }))
+                .push(Text::new(format!(
+                    "Res = {}, estimated error {:.3e}",
+                    laurent.residue.value, laurent.residue.error
+                )));
+            if let Some((expr, value)) = &state.symbolic_residue {
+                residue = residue
+                    .push(Text::new(format!("Res = {} = {}", expr, value)));
+            }
+            residue = residue.push(Text::new("Laurent coefficients"));
+            for (n, c) in (laurent.start..).zip(&laurent.coefficients) {
+                residue = residue.push(Text::new(format!("c_{} = {}", n, c)));
+            }
+        }
+        Some((_, Err(e))) => {
+            residue = residue.push(error_message(&Some(e.clone())))
+        }
+        None => {}
+    }
+
+    let content = Column::new()
+        .padding(20)
+        .spacing(10)
+        .push(Text::new("Derivative"))
+        .push(
+            Row::new()
+                .push(Text::new("f(z) = "))
+                .push(TextInput::new(
+                    &mut state.function_input_state,
+                    "Function of z",
+                    &state.function_input,
+                    |v| Message::Calculus(CalculusMessage::ChangeFunction(v)),
+                ))
+                .push(Text::new(" at "))
+                .push(TextInput::new(
+                    &mut state.point_input_state,
+                    "Point",
+                    &state.point_input,
+                    |v| Message::Calculus(CalculusMessage::ChangePoint(v)),
+                ))
+                .push(
+                    Button::new(
+                        &mut state.differentiate_button,
+                        Text::new("Differentiate"),
+                    )
+                    .on_press(Message::Calculus(
+                        CalculusMessage::Differentiate,
+                    )),
+                ),
+        )
+        .push(error_message(&state.function_error))
+        .push(error_message(&state.point_error))
+        .push(results)
+        .push(Text::new("Contour integral"))
+        .push(
+            Row::new()
+                .push(PickList::new(
+                    &mut state.contour_kind_list,
+                    &ContourKind::ALL[..],
+                    Some(state.contour_kind),
+                    |kind| {
+                        Message::Calculus(CalculusMessage::ContourKindSelected(
+                            kind,
+                        ))
+                    },
+                ))
+                .push(TextInput::new(
+                    &mut state.contour_input_state,
+                    state.contour_kind.placeholder(),
+                    &state.contour_input,
+                    |v| Message::Calculus(CalculusMessage::ChangeContour(v)),
+                ))
+                .push(
+                    Button::new(
+                        &mut state.integrate_button,
+                        Text::new("Integrate"),
+                    )
+                    .on_press(Message::Calculus(CalculusMessage::Integrate)),
+                ),
+        )
+        .push(error_message(&state.contour_error))
+        .push(integral)
+        .push(Text::new("Residue and Laurent series, at the point"))
+        .push(
+            Row::new()
+                .push(TextInput::new(
+                    &mut state.radius_input_state,
+                    "Radius of the circle, 0.1 by default",
+                    &state.radius_input,
+                    |v| Message::Calculus(CalculusMessage::ChangeRadius(v)),
+                ))
+                .push(TextInput::new(
+                    &mut state.terms_input_state,
+                    "Laurent terms, 6 by default",
+                    &state.terms_input,
+                    |v| Message::Calculus(CalculusMessage::ChangeTerms(v)),
+                ))
+                .push(
+                    Button::new(
+                        &mut state.residue_button,
+                        Text::new("Residue"),
+                    )
+                    .on_press(Message::Calculus(CalculusMessage::Residue)),
+                ),
+        )
+        .push(residue);
+
+    Container::new(Scrollable::new(&mut state.scroll).push(content))
         .height(Length::Fill)
         .into()
 }
 
+/// Ticks while the residues inside the contour are summed, to update the
+/// progress of the search for the poles.
+pub fn calculus_subscription(app: &ComplexApp) -> Subscription<Message> {
+    match app.calculus.residue_search {
+        Some(_) => time::every(Duration::from_millis(100))
+            .map(|_| Message::Calculus(CalculusMessage::SearchProgress)),
+        None => Subscription::none(),
+    }
+}
+
+fn cancel_search(app: &mut ComplexApp) {
+    if let Some(progress) = app.calculus.residue_search.take() {
+        progress.cancel();
+    }
+}
+
 pub fn process_calculus_message(
     app: &mut ComplexApp,
     message: CalculusMessage,
-) {
+) -> Command<Message> {
     match message {
-        CalculusMessage::M => {}
+        CalculusMessage::ChangeFunction(v) => {
+            app.calculus.function_input = v;
+            app.calculus.function_error = None;
+        }
+        CalculusMessage::ChangePoint(v) => {
+            app.calculus.point_input = v;
+            app.calculus.point_error = None;
+        }
+        CalculusMessage::Differentiate => {
+            let definitions = app.calculator.definitions();
+            let context = Context::new(definitions);
+            let mut variables = app.calculator.variable_values();
+            let state = &mut app.calculus;
+            state.derivative = None;
+            state.value = None;
+            state.cauchy_riemann = None;
+
+            let function = match parse_with(&state.function_input, definitions)
+            {
+                Ok(function) => function,
+                Err(e) => {
+                    state.function_error = Some(e);
+                    return Command::none();
+                }
+            };
+            let derivative = derivative(&function, "z", definitions);
+
+            // Without a point only the derivative is shown
+            if state.point_input.trim().is_empty() {
+                state.derivative = Some(derivative);
+                return Command::none();
+            }
+            let point = parse_with(&state.point_input, definitions)
+                .and_then(|point| point.eval_with(&variables, &context));
+            let point = match point {
+                Ok(point) => point,
+                Err(e) => {
+                    state.point_error = Some(e);
+                    state.derivative = Some(derivative);
+                    return Command::none();
+                }
+            };
+            state.cauchy_riemann =
+                cauchy_riemann(&function, "z", point, &variables, &context)
+                    .ok()
+                    .map(|cr| (point, cr));
+            if let Ok(derivative) = &derivative {
+                variables.insert("z".to_string(), point);
+                state.value = Some(derivative.eval_with(&variables, &context));
+            }
+            state.derivative = Some(derivative);
+        }
+        CalculusMessage::ChangeContour(v) => {
+            app.calculus.contour_input = v;
+            app.calculus.contour_error = None;
+        }
+        CalculusMessage::ContourKindSelected(kind) => {
+            cancel_search(app);
+            app.calculus.contour_kind = kind;
+            app.calculus.contour_error = None;
+            app.calculus.integral = None;
+        }
+        CalculusMessage::Integrate => {
+            cancel_search(app);
+            let definitions = app.calculator.definitions();
+            let context = Context::new(definitions);
+            let variables = app.calculator.variable_values();
+            let state = &mut app.calculus;
+            state.integral = None;
+            state.residue_sum = None;
+
+            let function = match parse_with(&state.function_input, definitions)
+            {
+                Ok(function) => function,
+                Err(e) => {
+                    state.function_error = Some(e);
+                    return Command::none();
+                }
+            };
+            let contour = match contour(
+                state.contour_kind,
+                &state.contour_input,
+                &variables,
+                &context,
+            ) {
+                Ok(contour) => contour,
+                Err(e) => {
+                    state.contour_error = Some(e);
+                    return Command::none();
+                }
+            };
+            state.integral = Some(
+                function
+                    .function_of("z", &variables, &context, Complex::zero())
+                    .and_then(|f| {
+                        contour_integral(&f, &contour, &variables, &context)
+                    }),
+            );
+            // Compared with the residue theorem when the contour is closed,
+            // searching for the poles on the thread pool like the grapher
+            if let Some(Ok(_)) = &state.integral {
+                let definitions = definitions.clone();
+                let progress = Arc::new(Progress::default());
+                state.residue_search = Some(progress.clone());
+                state.progress = 0.0;
+
+                let (sender, receiver) = oneshot::channel();
+                let job = progress.clone();
+                rayon::spawn(move || {
+                    let context = Context::new(&definitions);
+                    let _ = sender.send(residue_sum(
+                        &function, "z", &variables, &context, &contour, &job,
+                    ));
+                });
+                return Command::perform(receiver, move |sum| {
+                    // The sender is only dropped unsent if the search panics
+                    let sum = sum.unwrap_or_else(|_| {
+                        Err(Error::new(ErrorKind::Cancelled, Span::default()))
+                    });
+                    Message::Calculus(CalculusMessage::ResiduesSummed(
+                        progress.clone(),
+                        sum,
+                    ))
+                });
+            }
+        }
+        CalculusMessage::ResiduesSummed(progress, sum) => {
+            // Cancelled searches were replaced by a newer one, or not wanted
+            // anymore
+            if progress.is_cancelled() {
+                return Command::none();
+            }
+            app.calculus.residue_search = None;
+            app.calculus.residue_sum = sum.transpose();
+        }
+        CalculusMessage::SearchProgress => {
+            if let Some(progress) = &app.calculus.residue_search {
+                app.calculus.progress = progress.fraction();
+            }
+        }
+        CalculusMessage::CancelSearch => cancel_search(app),
+        CalculusMessage::ChangeRadius(v) => app.calculus.radius_input = v,
+        CalculusMessage::ChangeTerms(v) => app.calculus.terms_input = v,
+        CalculusMessage::Residue => {
+            let definitions = app.calculator.definitions();
+            let context = Context::new(definitions);
+            let variables = app.calculator.variable_values();
+            let state = &mut app.calculus;
+            state.laurent = None;
+            state.symbolic_residue = None;
+
+            let function = match parse_with(&state.function_input, definitions)
+            {
+                Ok(function) => function,
+                Err(e) => {
+                    state.function_error = Some(e);
+                    return Command::none();
+                }
+            };
+            let point_expr = match parse_with(&state.point_input, definitions) {
+                Ok(point) => point,
+                Err(e) => {
+                    state.point_error = Some(e);
+                    return Command::none();
+                }
+            };
+            let point = match point_expr.eval_with(&variables, &context) {
+                Ok(point) => point,
+                Err(e) => {
+                    state.point_error = Some(e);
+                    return Command::none();
+                }
+            };
+            let radius = match state.radius_input.trim().parse::<f64>() {
+                Ok(r) if r > 0.0 && r.is_finite() => r,
+                _ => RADIUS,
+            };
+            let terms = match state.terms_input.trim().parse::<usize>() {
+                Ok(n) => n.clamp(1, MAX_TERMS),
+                Err(_) => TERMS,
+            };
+
+            let expansion = function
+                .function_of("z", &variables, &context, point)
+                .and_then(|f| laurent(&f, point, radius, terms));
+            state.symbolic_residue = symbolic_residue(
+                &function,
+                "z",
+                &point_expr,
+                &variables,
+                &context,
+            )
+            .and_then(|expr| {
+                let value = expr.eval_with(&variables, &context).ok()?;
+                Some((expr, value))
+            });
+            state.laurent = Some((point, expansion));
+        }
+    };
+    Command::none()
+}
+
+/// The contour written in `input`, a list of values as `kind` takes them.
+/// The path of a parametric contour is kept as an expression in `t`.
+fn contour(
+    kind: ContourKind,
+    input: &str,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+) -> Result<Contour, Error> {
+    let items = parse_list(input, context.definitions)?;
+    let arity = match kind {
+        ContourKind::Circle | ContourKind::Segment => Arity::Exactly(2),
+        ContourKind::Polyline => Arity::AtLeast(2),
+        ContourKind::Parametric => Arity::Exactly(3),
     };
+    if !arity.accepts(items.len()) {
+        return Err(Error::new(
+            ErrorKind::WrongArgumentCount(
+                kind.to_string().to_lowercase(),
+                arity,
+                items.len(),
+            ),
+            Span::new(0, input.len()),
+        ));
+    }
+    let value = |item: &Expr| item.eval_with(variables, context);
+    let values =
+        |items: &[Expr]| items.iter().map(value).collect::<Result<Vec<_>, _>>();
+    Ok(match kind {
+        ContourKind::Circle => {
+            Contour::Circle(value(&items[0])?, value(&items[1])?.norm())
+        }
+        ContourKind::Segment | ContourKind::Polyline => {
+            Contour::Polyline(values(&items)?)
+        }
+        ContourKind::Parametric => Contour::Parametric(
+            items[0].clone(),
+            value(&items[1])?.re,
+            value(&items[2])?.re,
+        ),
+    })
 }
@@ -1,15 +1,26 @@
-use crate::parser::evaluations::{evaulate_points, evaulate_points_on_contour};
-use crate::parser::symbolic::shunting_yard;
-use crate::{ComplexApp, Message};
+use crate::parser::ast::Context;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::evaluations::{
+    evaulate_points, evaulate_points_on_contour, Progress,
+};
+use crate::parser::lexer::Span;
+use crate::parser::simplify::simplify;
+use crate::parser::symbolic::{parse, parse_with};
+use crate::parser::zeros::{locate_expr, Census, Region};
+use crate::{error_message, ComplexApp, Message};
 use iced::{
     button,
     canvas::{self, Canvas, Cursor, Geometry, Path, Stroke, Text as CText},
-    mouse, pick_list, text_input, Align, Button, Color, Column, Container,
-    Element, HorizontalAlignment, Length, PickList, Point, Rectangle, Row,
-    Text, TextInput, VerticalAlignment,
+    futures::channel::oneshot,
+    mouse, pick_list, text_input, time, Align, Button, Checkbox, Color, Column,
+    Command, Container, Element, HorizontalAlignment, Length, PickList, Point,
+    ProgressBar, Rectangle, Row, Subscription, Text, TextInput,
+    VerticalAlignment,
 };
 use num_complex::Complex;
 use std::default::Default;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Default)]
 pub struct GrapherState {
@@ -43,11 +54,21 @@ pub struct GrapherState {
     // Evaluation input function
     contour_input_state: text_input::State,
     contour_input: String,
+    contour_error: Option<Error>,
     clear_contour_button: button::State,
+    // Branch cut angle
+    branch_cut_state: text_input::State,
+    branch_cut_input: String,
+    branch_cut_error: Option<Error>,
     // Function Input
     function_input_state: text_input::State,
     function_input: String,
+    function_error: Option<Error>,
     function_button: button::State,
+    // Running evaluation
+    evaluation: Option<Arc<Progress>>,
+    progress: f32,
+    cancel_button: button::State,
     // Radius Input
     radius_input_state: text_input::State,
     radius_input: String,
@@ -56,6 +77,23 @@ pub struct GrapherState {
     clear_all_button: button::State,
     // Function options
     pick_list: pick_list::State<FOptions>,
+    // What the marked points are, like the roots of a polynomial
+    markers_source: Option<String>,
+    // Zeros and poles of the function in the intervals, or in the circle
+    // inside them
+    locate_button: button::State,
+    in_circle: bool,
+    located: Option<Result<Census, Error>>,
+}
+
+impl GrapherState {
+    /// Marks `markers` on the graph in place of the previous ones, listed
+    /// with the functions as `source`.
+    pub fn show_markers(&mut self, source: String, markers: Vec<Marker>) {
+        self.markers_source = Some(source);
+        self.graph.markers = markers;
+        self.graph.update();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -70,9 +108,21 @@ pub enum GrapherMessage {
     ChangeDivisions(String),
     ChangeRadius(String),
     ChangeEvaluationFunctionInput(String),
+    ChangeBranchCut(String),
     UpdateDivisions,
     UpdateRadius,
     GraphFunction,
+    /// The points of a graphed function, or why they could not be found.
+    FunctionEvaluated(Arc<Progress>, Result<Function, Error>),
+    /// Sent while an evaluation runs, to show how far along it is.
+    EvaluationProgress,
+    CancelEvaluation,
+    /// Finds the zeros and poles of the function in the intervals.
+    LocateZeros,
+    /// The zeros and poles found, listed with the functions as the given
+    /// source, or why they could not be found.
+    ZerosLocated(Arc<Progress>, String, Result<Census, Error>),
+    ToggleCircle(bool),
     ClearAll,
     ClearEvaluationFunction,
     OptionSelected(FOptions),
@@ -87,7 +137,42 @@ pub fn render_grapher<'a>(app: &'a mut ComplexApp) -> Element<Message> {
 
     let mut functions: Column<Message> = Column::new();
     for z in app.grapher.graph.functions.iter() {
-        functions = functions.push(Text::new(format!("F: {:?}", z.operation)));
+        functions = functions.push(Text::new(format!("F: {}", z.operation)));
+    }
+    if let Some(source) = &app.grapher.markers_source {
+        functions = functions.push(Text::new(format!("Marked: {}", source)));
+    }
+
+    let mut located: Column<Message> = Column::new();
+    match &app.grapher.located {
+        Some(Ok(census)) => {
+            located = located
+                .push(Text::new(format!("Zeros - poles: {}", census.count)));
+            for l in census.found.iter() {
+                let kind = if l.order > 0 { "Zero" } else { "Pole" };
+                located = located.push(Text::new(format!(
+                    "{} of order {} at {}",
+                    kind,
+                    l.order.abs(),
+                    l.z
+                )));
+            }
+        }
+        Some(Err(e)) => located = located.push(error_message(&Some(e.clone()))),
+        None => {}
+    }
+
+    let mut evaluation: Row<Message> = Row::new().spacing(10);
+    if app.grapher.evaluation.is_some() {
+        evaluation = evaluation
+            .push(ProgressBar::new(0.0..=1.0, app.grapher.progress))
+            .push(
+                Button::new(
+                    &mut app.grapher.cancel_button,
+                    Text::new("Cancel"),
+                )
+                .on_press(Message::Grapher(GrapherMessage::CancelEvaluation)),
+            );
     }
 
     let pick_list = PickList::new(
@@ -282,6 +367,33 @@ pub fn render_grapher<'a>(app: &'a mut ComplexApp) -> Element<Message> {
                                         )),
                                     ),
                             )
+                            .push(evaluation)
+                            .push(error_message(&app.grapher.function_error))
+                            .push(
+                                Row::new()
+                                    .spacing(10)
+                                    .push(
+                                        Button::new(
+                                            &mut app.grapher.locate_button,
+                                            Text::new("Zeros and poles"),
+                                        )
+                                        .on_press(Message::Grapher(
+                                            GrapherMessage::LocateZeros,
+                                        )),
+                                    )
+                                    .push(Checkbox::new(
+                                        app.grapher.in_circle,
+                                        "In circle",
+                                        |checked| {
+                                            Message::Grapher(
+                                                GrapherMessage::ToggleCircle(
+                                                    checked,
+                                                ),
+                                            )
+                                        },
+                                    )),
+                            )
+                            .push(located)
                             .push(Text::new("Evaluation function (?)"))
                             .push(
                                 Row::new()
@@ -305,6 +417,19 @@ pub fn render_grapher<'a>(app: &'a mut ComplexApp) -> Element<Message> {
                                         )),
                                     ),
                             )
+                            .push(error_message(&app.grapher.contour_error))
+                            .push(Text::new("Branch cut angle"))
+                            .push(TextInput::new(
+                                &mut app.grapher.branch_cut_state,
+                                "PI",
+                                &mut app.grapher.branch_cut_input,
+                                |v| {
+                                    Message::Grapher(
+                                        GrapherMessage::ChangeBranchCut(v),
+                                    )
+                                },
+                            ))
+                            .push(error_message(&app.grapher.branch_cut_error))
                             .push(functions.height(Length::FillPortion(1)))
                             .push(
                                 Row::new()
@@ -335,13 +460,149 @@ pub fn render_grapher<'a>(app: &'a mut ComplexApp) -> Element<Message> {
     .into()
 }
 
-pub fn process_grapher_message(app: &mut ComplexApp, message: GrapherMessage) {
+/// Ticks while a function is being evaluated, or its zeros and poles
+/// located, to update its progress.
+pub fn grapher_subscription(app: &ComplexApp) -> Subscription<Message> {
+    match app.grapher.evaluation {
+        Some(_) => time::every(Duration::from_millis(100))
+            .map(|_| Message::Grapher(GrapherMessage::EvaluationProgress)),
+        None => Subscription::none(),
+    }
+}
+
+/// The real and imaginary evaluation ranges of the interval inputs, from
+/// `-10` to `10` where they are empty, or `None` if one of them is empty
+/// after taking out its strict ends.
+fn intervals(grapher: &GrapherState) -> Option<((i32, i32), (i32, i32))> {
+    let left_x = grapher.interval_input_left_x.parse::<i32>();
+    let right_x = grapher.interval_input_right_x.parse::<i32>();
+    let left_y = grapher.interval_input_left_y.parse::<i32>();
+    let right_y = grapher.interval_input_right_y.parse::<i32>();
+
+    let l_x: i32;
+    match left_x {
+        Ok(v) => l_x = v,
+        Err(_) => l_x = -10,
+    }
+
+    let r_x: i32;
+    match right_x {
+        Ok(v) => r_x = v,
+        Err(_) => r_x = 10,
+    }
+    let l_y: i32;
+    match left_y {
+        Ok(v) => l_y = v,
+        Err(_) => l_y = -10,
+    }
+
+    let r_y: i32;
+    match right_y {
+        Ok(v) => r_y = v,
+        Err(_) => r_y = 10,
+    }
+
+    let mut x_interval = (-10, 10);
+    let mut y_interval = (-10, 10);
+
+    match grapher.selected_equality_left_x {
+        Some(v) => match v {
+            LeftEqualities::Less => {
+                x_interval.0 = l_x + 1;
+            }
+            LeftEqualities::LessEqual => {
+                x_interval.0 = l_x;
+            }
+        },
+        None => {
+            x_interval.0 = l_x;
+        }
+    }
+    match grapher.selected_equality_right_x {
+        Some(v) => match v {
+            LeftEqualities::Less => {
+                x_interval.1 = r_x - 1;
+            }
+            LeftEqualities::LessEqual => {
+                x_interval.1 = r_x;
+            }
+        },
+        None => {
+            x_interval.1 = r_x;
+        }
+    }
+    match grapher.selected_equality_left_y {
+        Some(v) => match v {
+            LeftEqualities::Less => {
+                y_interval.0 = l_y + 1;
+            }
+            LeftEqualities::LessEqual => {
+                y_interval.0 = l_y;
+            }
+        },
+        None => {
+            y_interval.0 = l_y;
+        }
+    }
+    match grapher.selected_equality_right_y {
+        Some(v) => match v {
+            LeftEqualities::Less => {
+                y_interval.1 = r_y - 1;
+            }
+            LeftEqualities::LessEqual => {
+                y_interval.1 = r_y;
+            }
+        },
+        None => {
+            y_interval.1 = r_y;
+        }
+    }
+
+    if x_interval.0 > x_interval.1 || y_interval.0 > y_interval.1 {
+        return None;
+    }
+    Some((x_interval, y_interval))
+}
+
+/// The angle of the branch cut input.
+fn branch_cut(grapher: &GrapherState) -> Result<f64, Error> {
+    // The cut of ln, roots and powers, the negative real axis unless
+    // another angle is given
+    let branch_cut = grapher.branch_cut_input.trim();
+    if branch_cut.is_empty() {
+        Ok(std::f64::consts::PI)
+    } else {
+        let env: [(&str, Complex<f64>); 0] = [];
+        parse(branch_cut)
+            .and_then(|angle| angle.eval::<f64, _>(&env[..]))
+            .map(|angle| angle.re)
+    }
+}
+
+/// Stops the running evaluation, if any, its points or zeros and poles are
+/// not shown.
+fn cancel_evaluation(app: &mut ComplexApp) {
+    if let Some(progress) = app.grapher.evaluation.take() {
+        progress.cancel();
+    }
+}
+
+pub fn process_grapher_message(
+    app: &mut ComplexApp,
+    message: GrapherMessage,
+) -> Command<Message> {
     match message {
         GrapherMessage::ClearEvaluationFunction => {
-            app.grapher.contour_input = "".to_string()
+            app.grapher.contour_input = "".to_string();
+            app.grapher.contour_error = None;
         }
         GrapherMessage::ChangeEvaluationFunctionInput(v) => {
-            app.grapher.contour_input = v
+            app.grapher.contour_input = v;
+            app.grapher.contour_error = None;
+        }
+        GrapherMessage::ChangeBranchCut(v) => {
+            app.grapher.branch_cut_input = v;
+            app.grapher.branch_cut_error = None;
         }
         GrapherMessage::ChangeRadius(v) => app.grapher.radius_input = v,
         GrapherMessage::UpdateRadius => {
@@ -390,105 +651,147 @@ pub fn process_grapher_message(app: &mut ComplexApp, message: GrapherMessage) {
             app.grapher.interval_input_right_y = v
         }
         GrapherMessage::ChangeFunctionInput(v) => {
-            app.grapher.function_input = v
+            app.grapher.function_input = v;
+            app.grapher.function_error = None;
         }
         GrapherMessage::OptionSelected(o) => {
             app.grapher.graph.selected_option = Some(o);
         }
+        GrapherMessage::EvaluationProgress => {
+            if let Some(progress) = &app.grapher.evaluation {
+                app.grapher.progress = progress.fraction();
+            }
+        }
+        GrapherMessage::CancelEvaluation => cancel_evaluation(app),
+        GrapherMessage::FunctionEvaluated(progress, function) => {
+            // Cancelled evaluations were replaced by a newer one, or not
+            // wanted anymore
+            if progress.is_cancelled() {
+                return Command::none();
+            }
+            app.grapher.evaluation = None;
+            match function {
+                Ok(function) => {
+                    app.grapher.graph.functions.push(function);
+                    app.grapher.function_input = "".to_string();
+                    app.grapher.function_error = None;
+                    app.grapher.graph.update();
+                }
+                Err(e) => app.grapher.function_error = Some(e),
+            }
+        }
         GrapherMessage::ClearAll => {
+            cancel_evaluation(app);
             app.grapher.function_input = "".to_string();
+            app.grapher.function_error = None;
             app.grapher.graph.points = vec![];
             app.grapher.graph.functions = vec![];
+            app.grapher.graph.markers = vec![];
+            app.grapher.markers_source = None;
+            app.grapher.located = None;
             app.grapher.graph.update();
         }
-        GrapherMessage::GraphFunction => {
-            let left_x = app.grapher.interval_input_left_x.parse::<i32>();
-            let right_x = app.grapher.interval_input_right_x.parse::<i32>();
-            let left_y = app.grapher.interval_input_left_y.parse::<i32>();
-            let right_y = app.grapher.interval_input_right_y.parse::<i32>();
-
-            let l_x: i32;
-            match left_x {
-                Ok(v) => l_x = v,
-                Err(_) => l_x = -10,
-            }
-
-            let r_x: i32;
-            match right_x {
-                Ok(v) => r_x = v,
-                Err(_) => r_x = 10,
-            }
-            let l_y: i32;
-            match left_y {
-                Ok(v) => l_y = v,
-                Err(_) => l_y = -10,
-            }
+        GrapherMessage::ToggleCircle(checked) => {
+            app.grapher.in_circle = checked
+        }
+        GrapherMessage::LocateZeros => {
+            let ((left, right), (bottom, top)) = match intervals(&app.grapher) {
+                Some(intervals) => intervals,
+                None => return Command::none(),
+            };
+            let branch_cut = match branch_cut(&app.grapher) {
+                Ok(angle) => angle,
+                Err(e) => {
+                    app.grapher.branch_cut_error = Some(e);
+                    return Command::none();
+                }
+            };
+            let context = Context {
+                definitions: app.calculator.definitions(),
+                branch_cut,
+            };
+            let variables = app.calculator.variable_values();
+            let function = match parse_with(
+                &app.grapher.function_input,
+                context.definitions,
+            ) {
+                Ok(function) => function,
+                Err(e) => {
+                    app.grapher.function_error = Some(e);
+                    return Command::none();
+                }
+            };
+            app.grapher.function_error = None;
 
-            let r_y: i32;
-            match right_y {
-                Ok(v) => r_y = v,
-                Err(_) => r_y = 10,
-            }
+            // The circle is the largest one inside the intervals
+            let min = Complex::new(left as f64, bottom as f64);
+            let max = Complex::new(right as f64, top as f64);
+            let region = if app.grapher.in_circle {
+                let radius = (right - left).min(top - bottom) as f64 / 2.0;
+                Region::Circle((min + max) / 2.0, radius)
+            } else {
+                Region::Rectangle(min, max)
+            };
 
-            let mut x_interval = (-10, 10);
-            let mut y_interval = (-10, 10);
+            // The search runs on the thread pool like the evaluations, and
+            // replaces the one still running
+            let definitions = context.definitions.clone();
+            let source = format!("zeros and poles of {}", simplify(&function));
+            cancel_evaluation(app);
+            let progress = Arc::new(Progress::default());
+            app.grapher.evaluation = Some(progress.clone());
+            app.grapher.progress = 0.0;
 
-            match app.grapher.selected_equality_left_x {
-                Some(v) => match v {
-                    LeftEqualities::Less => {
-                        x_interval.0 = l_x + 1;
-                    }
-                    LeftEqualities::LessEqual => {
-                        x_interval.0 = l_x;
-                    }
-                },
-                None => {
-                    x_interval.0 = l_x;
-                }
-            }
-            match app.grapher.selected_equality_right_x {
-                Some(v) => match v {
-                    LeftEqualities::Less => {
-                        x_interval.1 = r_x - 1;
-                    }
-                    LeftEqualities::LessEqual => {
-                        x_interval.1 = r_x;
-                    }
-                },
-                None => {
-                    x_interval.1 = r_x;
-                }
-            }
-            match app.grapher.selected_equality_left_y {
-                Some(v) => match v {
-                    LeftEqualities::Less => {
-                        y_interval.0 = l_y + 1;
-                    }
-                    LeftEqualities::LessEqual => {
-                        y_interval.0 = l_y;
-                    }
-                },
-                None => {
-                    y_interval.0 = l_y;
-                }
-            }
-            match app.grapher.selected_equality_right_y {
-                Some(v) => match v {
-                    LeftEqualities::Less => {
-                        y_interval.1 = r_y - 1;
-                    }
-                    LeftEqualities::LessEqual => {
-                        y_interval.1 = r_y;
-                    }
-                },
-                None => {
-                    y_interval.1 = r_y;
-                }
+            let (sender, receiver) = oneshot::channel();
+            let job = progress.clone();
+            rayon::spawn(move || {
+                let context = Context {
+                    definitions: &definitions,
+                    branch_cut,
+                };
+                let _ = sender.send(locate_expr(
+                    &function, "z", &variables, &context, region, &job,
+                ));
+            });
+            return Command::perform(receiver, move |located| {
+                // The sender is only dropped unsent if the search panics
+                let located = located.unwrap_or_else(|_| {
+                    Err(Error::new(ErrorKind::Cancelled, Span::default()))
+                });
+                Message::Grapher(GrapherMessage::ZerosLocated(
+                    progress.clone(),
+                    source.clone(),
+                    located,
+                ))
+            });
+        }
+        GrapherMessage::ZerosLocated(progress, source, located) => {
+            if progress.is_cancelled() {
+                return Command::none();
             }
-
-            if x_interval.0 > x_interval.1 || y_interval.0 > y_interval.1 {
-                return;
+            app.grapher.evaluation = None;
+            if let Ok(census) = &located {
+                let markers = census
+                    .found
+                    .iter()
+                    .map(|l| {
+                        let kind = if l.order > 0 { "zero" } else { "pole" };
+                        let label = match l.order.abs() {
+                            1 => kind.to_string(),
+                            m => format!("{} ×{}", kind, m),
+                        };
+                        Marker { z: l.z, label }
+                    })
+                    .collect();
+                app.grapher.show_markers(source, markers);
             }
+            app.grapher.located = Some(located);
+        }
+        GrapherMessage::GraphFunction => {
+            let (x_interval, y_interval) = match intervals(&app.grapher) {
+                Some(intervals) => intervals,
+                None => return Command::none(),
+            };
 
             let x_precision = app.grapher.precision_input_x.parse::<i32>();
             let y_precision = app.grapher.precision_input_y.parse::<i32>();
@@ -504,47 +807,106 @@ pub fn process_grapher_message(app: &mut ComplexApp, message: GrapherMessage) {
                 Err(_) => yp = 1,
             }
 
-            let input = app.grapher.function_input.replace(" ", "");
-            let algorithm = shunting_yard(input.clone());
+            let branch_cut = match branch_cut(&app.grapher) {
+                Ok(angle) => angle,
+                Err(e) => {
+                    app.grapher.branch_cut_error = Some(e);
+                    return Command::none();
+                }
+            };
 
-            let evaluation_function =
-                app.grapher.contour_input.replace(" ", "");
-            let ev_algorithm = shunting_yard(evaluation_function);
+            // Functions and variables of the calculator can be graphed too
+            let context = Context {
+                definitions: app.calculator.definitions(),
+                branch_cut,
+            };
+            let definitions = context.definitions;
+            let variables = app.calculator.variable_values();
 
-            if !(ev_algorithm.len() > 0) {
-                let points =
-                    evaulate_points(algorithm, x_interval, xp, y_interval, yp);
-                let mut f_points = vec![];
-                for (z, r, g) in points.iter() {
-                    let t = (*z, *r, *g);
-                    f_points.push(t);
+            let input = app.grapher.function_input.clone();
+            let function = match parse_with(&input, definitions) {
+                Ok(function) => function,
+                Err(e) => {
+                    app.grapher.function_error = Some(e);
+                    return Command::none();
                 }
-                app.grapher
-                    .graph
-                    .functions
-                    .push(Function::new(input, f_points));
+            };
+
+            let contour = if app.grapher.contour_input.trim().is_empty() {
+                None
             } else {
-                let points = evaulate_points_on_contour(
-                    algorithm,
-                    x_interval,
-                    xp,
-                    ev_algorithm,
-                );
-                let mut f_points = vec![];
-                for (z, r, g) in points.iter() {
-                    let t = (*z, *r, *g);
-                    f_points.push(t);
+                // Check the contour on its own first, so its errors are shown
+                // under its input
+                let contour_input = &app.grapher.contour_input;
+                let contour =
+                    parse_with(contour_input, definitions).and_then(|c| {
+                        let mut env = variables.clone();
+                        env.insert(
+                            "x".to_string(),
+                            Complex::new(x_interval.0 as f64, 0.0),
+                        );
+                        match c.eval_with(&env, &context) {
+                            Err(e) if e.kind != ErrorKind::DivisionByZero => {
+                                Err(e)
+                            }
+                            _ => Ok(c),
+                        }
+                    });
+                match contour {
+                    Ok(contour) => Some(contour),
+                    Err(e) => {
+                        app.grapher.contour_error = Some(e);
+                        return Command::none();
+                    }
                 }
-                app.grapher
-                    .graph
-                    .functions
-                    .push(Function::new(input, f_points));
-            }
+            };
 
-            app.grapher.function_input = "".to_string();
-            app.grapher.graph.update();
+            // The evaluation runs on the thread pool, with its own copy of
+            // the calculator functions
+            let definitions = definitions.clone();
+            let operation = simplify(&function).to_string();
+
+            // A new function replaces the one still being evaluated
+            cancel_evaluation(app);
+            let progress = Arc::new(Progress::default());
+            app.grapher.evaluation = Some(progress.clone());
+            app.grapher.progress = 0.0;
+            app.grapher.function_error = None;
+
+            let (sender, receiver) = oneshot::channel();
+            let job = progress.clone();
+            rayon::spawn(move || {
+                let context = Context {
+                    definitions: &definitions,
+                    branch_cut,
+                };
+                let points = match contour {
+                    None => evaulate_points::<f64>(
+                        &function, &context, &variables, x_interval, xp,
+                        y_interval, yp, &job,
+                    ),
+                    Some(contour) => evaulate_points_on_contour::<f64>(
+                        &function, &context, &variables, x_interval, xp,
+                        &contour, &job,
+                    ),
+                };
+                let _ = sender.send(
+                    points.map(|points| Function::new(operation, points)),
+                );
+            });
+            return Command::perform(receiver, move |function| {
+                // The sender is only dropped unsent if the evaluation panics
+                let function = function.unwrap_or_else(|_| {
+                    Err(Error::new(ErrorKind::Cancelled, Span::default()))
+                });
+                Message::Grapher(GrapherMessage::FunctionEvaluated(
+                    progress.clone(),
+                    function,
+                ))
+            });
         }
     };
+    Command::none()
 }
 
 #[derive(Debug)]
@@ -557,6 +919,7 @@ struct GraphState {
     cursor_position: Point,
     points: Vec<Complex<f32>>,
     functions: Vec<Function>,
+    markers: Vec<Marker>,
     selected_option: Option<FOptions>,
 }
 impl Default for GraphState {
@@ -570,6 +933,7 @@ impl Default for GraphState {
             cursor_position: Point::ORIGIN,
             points: Vec::new(),
             functions: Vec::new(),
+            markers: Vec::new(),
             selected_option: Default::default(),
         }
     }
@@ -601,6 +965,8 @@ impl<Message> canvas::Program<Message> for GraphState {
             self.function_cache.draw(bounds.size(), |frame| {
                 for function in self.functions.iter() {
                     for (z, r, g) in function.points.iter() {
+                        // Points keep double precision until drawn
+                        let z = Complex::new(z.re as f32, z.im as f32);
                         let x = (z.re + m as f32) * scale;
                         if x > frame.size().width || x < -frame.size().width {
                             continue;
@@ -634,6 +1000,38 @@ impl<Message> canvas::Program<Message> for GraphState {
                         frame.fill(&circle, color);
                     }
                 }
+
+                // Markers, drawn over the functions
+                for marker in self.markers.iter() {
+                    let x = (marker.z.re as f32 + m as f32) * scale;
+                    let y = frame.size().height
+                        - ((marker.z.im as f32 + n as f32) * scale);
+                    if x < 0.0
+                        || x > frame.size().width
+                        || y < 0.0
+                        || y > frame.size().height
+                    {
+                        continue;
+                    }
+                    let color = Color::from_rgb8(220, 40, 40);
+                    frame.stroke(
+                        &Path::circle(Point::new(x, y), radius + 4.0),
+                        Stroke {
+                            width: 2.0,
+                            color,
+                            ..Stroke::default()
+                        },
+                    );
+                    frame.fill_text(CText {
+                        content: marker.label.clone(),
+                        color,
+                        size: 14.0,
+                        position: Point::new(x + radius + 6.0, y),
+                        horizontal_alignment: HorizontalAlignment::Left,
+                        vertical_alignment: VerticalAlignment::Bottom,
+                        ..CText::default()
+                    });
+                }
             });
 
         // Grid
@@ -748,16 +1146,24 @@ impl<Message> canvas::Program<Message> for GraphState {
     }
 }
 
-#[derive(Debug)]
-struct Function {
+/// A point marked on the graph with a label, like a root found on another
+/// page.
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub z: Complex<f64>,
+    pub label: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
     operation: String,
-    points: Vec<(Complex<f32>, i32, i32)>,
+    points: Vec<(Complex<f64>, i32, i32)>,
 }
 
 impl Function {
     pub fn new(
         operation: String,
-        points: Vec<(Complex<f32>, i32, i32)>,
+        points: Vec<(Complex<f64>, i32, i32)>,
     ) -> Function {
         Function { operation, points }
     }
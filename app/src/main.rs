@@ -3,27 +3,32 @@ mod calculus;
 mod fractals;
 mod grapher;
 mod parser;
+mod roots;
 
 use calculator::{
-    process_calculator_message, render_calculator, CalcMessage, CalculatorState,
+    calculator_subscription, process_calculator_message, render_calculator,
+    CalcMessage, CalculatorState,
 };
 use calculus::{
-    process_calculus_message, render_calculus, CalculusMessage, CalculusState,
+    calculus_subscription, process_calculus_message, render_calculus,
+    CalculusMessage, CalculusState,
 };
 use fractals::{
     process_fractals_message, render_fractals, FractalsMessage, FractalsState,
 };
 use grapher::{
-    process_grapher_message, render_grapher, GrapherMessage, GrapherState,
+    grapher_subscription, process_grapher_message, render_grapher,
+    GrapherMessage, GrapherState,
 };
 use iced::{
-    executor, menu, Application, Clipboard, Command, Element, Menu, Settings,
+    executor, menu, Application, Clipboard, Color, Command, Element, Menu,
+    Settings, Subscription, Text,
 };
 use iced_native::keyboard::{Hotkey, KeyCode, Modifiers};
-use parser::symbolic::test_ops;
+use parser::error::Error;
+use roots::{process_roots_message, render_roots, RootsMessage, RootsState};
 
 pub fn main() -> iced::Result {
-    test_ops();
     ComplexApp::run(Settings {
         antialiasing: true,
         ..Settings::default()
@@ -36,6 +41,7 @@ pub struct ComplexApp {
     pub grapher: GrapherState,
     pub calculus: CalculusState,
     pub fractals: FractalsState,
+    pub roots: RootsState,
     window: Window,
 }
 
@@ -45,6 +51,7 @@ pub enum Message {
     Grapher(GrapherMessage),
     Calculus(CalculusMessage),
     Fractals(FractalsMessage),
+    Roots(RootsMessage),
     Menu(Window),
 }
 
@@ -54,6 +61,7 @@ pub enum Window {
     Grapher,
     Calculus,
     Fractals,
+    Roots,
 }
 
 impl Default for Window {
@@ -103,6 +111,11 @@ impl Application for ComplexApp {
                         Hotkey::new(alt, KeyCode::F5),
                         Message::Menu(Window::Fractals),
                     ),
+                    menu::Entry::item(
+                        "Roots",
+                        Hotkey::new(alt, KeyCode::F6),
+                        Message::Menu(Window::Roots),
+                    ),
                 ]),
             ),
             menu::Entry::dropdown("Options", Menu::with_entries(vec![])),
@@ -118,15 +131,21 @@ impl Application for ComplexApp {
             Message::Calculator(m) => {
                 process_calculator_message(self, m);
             }
-            Message::Grapher(m) => {
-                process_grapher_message(self, m);
-            }
-            Message::Calculus(m) => {
-                process_calculus_message(self, m);
-            }
+            // Graphs are evaluated in the background, as are the residues
+            // compared with contour integrals
+            Message::Grapher(m) => return process_grapher_message(self, m),
+            Message::Calculus(m) => return process_calculus_message(self, m),
             Message::Fractals(m) => {
                 process_fractals_message(self, m);
             }
+            Message::Roots(m) => {
+                // Marked roots are shown on the grapher right away
+                let plot = matches!(m, RootsMessage::Plot);
+                process_roots_message(self, m);
+                if plot {
+                    self.window = Window::Grapher;
+                }
+            }
             Message::Menu(v) => {
                 self.window = v;
             }
@@ -134,16 +153,43 @@ impl Application for ComplexApp {
         Command::none()
     }
 
+    fn subscription(&self) -> Subscription<Message> {
+        match self.window {
+            // The arrow keys only recall calculations on their page
+            Window::Calculator => Subscription::batch(vec![
+                grapher_subscription(self),
+                calculus_subscription(self),
+                calculator_subscription(),
+            ]),
+            _ => Subscription::batch(vec![
+                grapher_subscription(self),
+                calculus_subscription(self),
+            ]),
+        }
+    }
+
     fn view(&mut self) -> Element<Message> {
         match self.window {
             Window::Calculator => render_calculator(self),
             Window::Grapher => render_grapher(self),
             Window::Calculus => render_calculus(self),
             Window::Fractals => render_fractals(self),
+            Window::Roots => render_roots(self),
         }
     }
 }
 
+/// Inline message shown under an input that could not be parsed or
+/// evaluated.
+pub fn error_message(error: &Option<Error>) -> Text {
+    match error {
+        Some(e) => Text::new(e.to_string())
+            .size(14)
+            .color(Color::from_rgb(0.8, 0.1, 0.1)),
+        None => Text::new("").size(14),
+    }
+}
+
 // TODO
 // Error messages
 // - Global widget for popup error messages
@@ -158,9 +204,6 @@ impl Application for ComplexApp {
 // TESTS
 // - automatic tests for parsers - MUST
 //
-// PARSER / INPUT / CALCULATIONS
-// - Polar calculations
-//
 // NEW PAGES
 // Grapher
 // - Polar grid
@@ -0,0 +1,526 @@
+use crate::parser::branches::BranchCut;
+use crate::parser::definitions::Definitions;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::{Arity, Function};
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+use num_traits::{Float, FloatConst, Zero};
+use std::collections::{HashMap, HashSet};
+
+/// Floating point types expressions can be evaluated with, on any thread.
+pub trait Real: Float + FloatConst + std::fmt::Debug + Send + Sync {}
+
+impl<T: Float + FloatConst + std::fmt::Debug + Send + Sync> Real for T {}
+
+/// Converts a literal, stored in double precision, to `T`.
+pub fn cast<T: Real>(z: Complex<f64>) -> Complex<T> {
+    Complex::new(
+        T::from(z.re).unwrap_or_else(T::nan),
+        T::from(z.im).unwrap_or_else(T::nan),
+    )
+}
+
+/// `-z`, subtracted from zero so that a zero component stays positive and
+/// `-1` is not moved across the branch cut of `ln` and `powc`. Every
+/// evaluator negates numbers with it.
+pub fn negate<T: Real>(z: Complex<T>) -> Complex<T> {
+    Complex::<T>::zero() - z
+}
+
+/// The error of a user function call left in an expression. Evaluators
+/// other than [`Expr::eval_with`] take expressions [`Expr::inline`] has
+/// replaced the calls of.
+pub fn not_inlined(name: &str, span: Span) -> Error {
+    Error::new(ErrorKind::UnknownFunction(name.to_string()), span)
+}
+
+/// Widens a result back to double precision.
+pub fn widen<T: Real>(z: Complex<T>) -> Complex<f64> {
+    Complex::new(
+        z.re.to_f64().unwrap_or(f64::NAN),
+        z.im.to_f64().unwrap_or(f64::NAN),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+impl Operator {
+    pub fn from_char(c: char) -> Option<Operator> {
+        match c {
+            '+' => Some(Operator::Add),
+            '-' => Some(Operator::Sub),
+            '*' => Some(Operator::Mul),
+            '/' => Some(Operator::Div),
+            '^' => Some(Operator::Pow),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> char {
+        match self {
+            Operator::Add => '+',
+            Operator::Sub => '-',
+            Operator::Mul => '*',
+            Operator::Div => '/',
+            Operator::Pow => '^',
+        }
+    }
+
+    pub fn apply<T: Real>(
+        &self,
+        z1: Complex<T>,
+        z2: Complex<T>,
+        cut: BranchCut<T>,
+    ) -> Complex<T> {
+        match self {
+            Operator::Add => z1 + z2,
+            Operator::Sub => z1 - z2,
+            Operator::Mul => z1 * z2,
+            Operator::Div => z1 / z2,
+            Operator::Pow => cut.pow(z1, z2, T::zero()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constant {
+    Pi,
+    E,
+    /// `2π`, a full turn.
+    Tau,
+    /// The golden ratio, `(1 + √5)/2`.
+    Phi,
+}
+
+impl Constant {
+    pub const ALL: [Constant; 4] =
+        [Constant::Pi, Constant::E, Constant::Tau, Constant::Phi];
+
+    pub fn from_name(name: &str) -> Option<Constant> {
+        match name {
+            "PI" | "pi" => Some(Constant::Pi),
+            "e" | "E" => Some(Constant::E),
+            "tau" => Some(Constant::Tau),
+            "phi" => Some(Constant::Phi),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Constant::Pi => "PI",
+            Constant::E => "e",
+            Constant::Tau => "tau",
+            Constant::Phi => "phi",
+        }
+    }
+
+    pub fn value<T: Real>(&self) -> Complex<T> {
+        let x = match self {
+            Constant::Pi => T::PI(),
+            Constant::E => T::E(),
+            Constant::Tau => T::TAU(),
+            Constant::Phi => {
+                let five = T::from(5).unwrap_or_else(T::nan);
+                (T::one() + five.sqrt()) / (T::one() + T::one())
+            }
+        };
+        Complex::new(x, T::zero())
+    }
+}
+
+/// A parsed expression, evaluated by walking the tree.
+///
+/// Literals are stored in double precision and converted to the float type
+/// the expression is evaluated with. Variables, operators and calls of user
+/// functions keep the span of their token, so evaluation errors can point
+/// back into the input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(Complex<f64>),
+    /// A literal with more digits than a double holds, with its text, like
+    /// `3.14159265358979323846` or `1.00000000000000000001i`. The
+    /// arbitrary precision and exact evaluators read the text, the others
+    /// the rounded value.
+    Decimal(Complex<f64>, String),
+    Constant(Constant),
+    Variable(String, Span),
+    Neg(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>, Span),
+    Call(Function, Vec<Expr>),
+    UserCall(String, Vec<Expr>, Span),
+    /// `[a, b]`, a column vector, or `[[a, b], [c, d]]`, a matrix by rows.
+    /// Only [`matrix::eval`](crate::parser::matrix::eval) computes them.
+    Matrix(Vec<Expr>, Span),
+}
+
+/// How deeply user functions may call each other before evaluation stops.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Values for the variables of an expression.
+pub trait Env<T> {
+    fn get(&self, name: &str) -> Option<Complex<T>>;
+}
+
+impl<T: Real> Env<T> for HashMap<String, Complex<T>> {
+    fn get(&self, name: &str) -> Option<Complex<T>> {
+        HashMap::get(self, name).copied()
+    }
+}
+
+impl<T: Real> Env<T> for [(&str, Complex<T>)] {
+    fn get(&self, name: &str) -> Option<Complex<T>> {
+        self.iter().find(|(n, _)| *n == name).map(|(_, z)| *z)
+    }
+}
+
+/// What an expression is evaluated with besides its variables.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    pub definitions: &'a Definitions,
+    /// Angle of the branch cut of the multivalued functions, see
+    /// [`BranchCut`].
+    pub branch_cut: f64,
+}
+
+impl<'a> Context<'a> {
+    /// The functions in `definitions`, with principal branches.
+    pub fn new(definitions: &'a Definitions) -> Context<'a> {
+        Context {
+            definitions,
+            branch_cut: std::f64::consts::PI,
+        }
+    }
+}
+
+/// Lets environments that are not sized, like slices, be used as
+/// `dyn Env`.
+struct Borrowed<'a, E: ?Sized>(&'a E);
+
+impl<'a, T, E: Env<T> + ?Sized> Env<T> for Borrowed<'a, E> {
+    fn get(&self, name: &str) -> Option<Complex<T>> {
+        self.0.get(name)
+    }
+}
+
+/// The arguments of a user function call, bound to its parameters over the
+/// variables the outermost expression was evaluated with.
+struct Frame<'a, T> {
+    params: &'a [String],
+    args: Vec<Complex<T>>,
+    globals: &'a dyn Env<T>,
+}
+
+impl<'a, T: Real> Env<T> for Frame<'a, T> {
+    fn get(&self, name: &str) -> Option<Complex<T>> {
+        match self.params.iter().position(|p| p == name) {
+            Some(i) => Some(self.args[i]),
+            None => self.globals.get(name),
+        }
+    }
+}
+
+/// The variables of an expression, with `variable` set to a point.
+pub struct At<'a> {
+    pub variable: &'a str,
+    pub z: Complex<f64>,
+    pub variables: &'a HashMap<String, Complex<f64>>,
+}
+
+impl<'a> Env<f64> for At<'a> {
+    fn get(&self, name: &str) -> Option<Complex<f64>> {
+        if name == self.variable {
+            Some(self.z)
+        } else {
+            self.variables.get(name).copied()
+        }
+    }
+}
+
+impl Expr {
+    /// The expression as a function of `variable` in double precision, for
+    /// the numerical methods. Points where it cannot be evaluated, like its
+    /// poles, give NaN.
+    ///
+    /// It is evaluated at `probe` first, errors that do not depend on the
+    /// point, like unknown identifiers, are returned.
+    pub fn function_of<'a>(
+        &'a self,
+        variable: &'a str,
+        variables: &'a HashMap<String, Complex<f64>>,
+        context: &'a Context<'a>,
+        probe: Complex<f64>,
+    ) -> Result<impl Fn(Complex<f64>) -> Complex<f64> + 'a, Error> {
+        let eval = move |z| {
+            let env = At {
+                variable,
+                z,
+                variables,
+            };
+            self.eval_with(&env, context)
+        };
+        match eval(probe) {
+            Err(e) if e.kind != ErrorKind::DivisionByZero => Err(e),
+            _ => Ok(move |z| {
+                eval(z).unwrap_or_else(|_| Complex::new(f64::NAN, f64::NAN))
+            }),
+        }
+    }
+
+    /// Evaluates the expression with the variables in `env`.
+    ///
+    /// Dividing by an exact zero is an error instead of an infinity, callers
+    /// sampling many points may skip those.
+    pub fn eval<T: Real, E: Env<T> + ?Sized>(
+        &self,
+        env: &E,
+    ) -> Result<Complex<T>, Error> {
+        self.eval_with(env, &Context::new(&Definitions::default()))
+    }
+
+    /// Evaluates the expression with the variables in `env`, and the user
+    /// functions and branch cut of `context`.
+    pub fn eval_with<T: Real, E: Env<T> + ?Sized>(
+        &self,
+        env: &E,
+        context: &Context,
+    ) -> Result<Complex<T>, Error> {
+        let env = Borrowed(env);
+        let cut = T::from(context.branch_cut)
+            .map_or_else(BranchCut::principal, BranchCut::new);
+        self.evaluate(&env, &env, context.definitions, cut, 0)
+    }
+
+    /// Evaluates with `env`, the variables in scope, and `globals`, the
+    /// variables user function bodies see besides their parameters.
+    fn evaluate<T: Real>(
+        &self,
+        env: &dyn Env<T>,
+        globals: &dyn Env<T>,
+        definitions: &Definitions,
+        cut: BranchCut<T>,
+        depth: usize,
+    ) -> Result<Complex<T>, Error> {
+        let eval =
+            |expr: &Expr| expr.evaluate(env, globals, definitions, cut, depth);
+        match self {
+            Expr::Number(z) | Expr::Decimal(z, _) => Ok(cast(*z)),
+            Expr::Constant(c) => Ok(c.value()),
+            Expr::Variable(name, span) => env.get(name).ok_or_else(|| {
+                Error::new(ErrorKind::UnknownIdentifier(name.clone()), *span)
+            }),
+            Expr::Neg(expr) => Ok(negate(eval(expr)?)),
+            Expr::Binary(op, left, right, span) => {
+                let z1 = eval(left)?;
+                let z2 = eval(right)?;
+                if *op == Operator::Div && z2.is_zero() {
+                    return Err(Error::new(ErrorKind::DivisionByZero, *span));
+                }
+                Ok(op.apply(z1, z2, cut))
+            }
+            Expr::Call(f, args) => {
+                f.check_scalar()?;
+                let args =
+                    args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+                Ok(f.apply(&args, cut))
+            }
+            Expr::UserCall(name, args, span) => {
+                let error = |kind| Error::new(kind, *span);
+                let definition = definitions.get(name).ok_or_else(|| {
+                    error(ErrorKind::UnknownFunction(name.clone()))
+                })?;
+                // Arguments are checked when parsing, but the function may
+                // have been redefined since
+                if definition.params.len() != args.len() {
+                    return Err(error(ErrorKind::WrongArgumentCount(
+                        name.clone(),
+                        Arity::Exactly(definition.params.len()),
+                        args.len(),
+                    )));
+                }
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(error(ErrorKind::RecursionLimit(name.clone())));
+                }
+                let frame = Frame {
+                    params: &definition.params,
+                    args: args.iter().map(eval).collect::<Result<_, _>>()?,
+                    globals,
+                };
+                definition.body.evaluate(
+                    &frame,
+                    globals,
+                    definitions,
+                    cut,
+                    depth + 1,
+                )
+            }
+            Expr::Matrix(_, span) => {
+                Err(Error::new(ErrorKind::NotScalar, *span))
+            }
+        }
+    }
+
+    /// The expression with calls of user functions replaced by their
+    /// bodies, with the arguments in place of the parameters.
+    pub fn inline(&self, definitions: &Definitions) -> Result<Expr, Error> {
+        self.inline_at(definitions, 0)
+    }
+
+    fn inline_at(
+        &self,
+        definitions: &Definitions,
+        depth: usize,
+    ) -> Result<Expr, Error> {
+        let inline = |expr: &Expr| expr.inline_at(definitions, depth);
+        let inline_all = |args: &[Expr]| {
+            args.iter().map(inline).collect::<Result<Vec<_>, _>>()
+        };
+        Ok(match self {
+            Expr::Number(_)
+            | Expr::Decimal(_, _)
+            | Expr::Constant(_)
+            | Expr::Variable(_, _) => self.clone(),
+            Expr::Neg(expr) => Expr::Neg(Box::new(inline(expr)?)),
+            Expr::Binary(op, left, right, span) => Expr::Binary(
+                *op,
+                Box::new(inline(left)?),
+                Box::new(inline(right)?),
+                *span,
+            ),
+            Expr::Call(f, args) => Expr::Call(*f, inline_all(args)?),
+            Expr::Matrix(elements, span) => {
+                Expr::Matrix(inline_all(elements)?, *span)
+            }
+            Expr::UserCall(name, args, span) => {
+                let error = |kind| Error::new(kind, *span);
+                let definition = definitions.get(name).ok_or_else(|| {
+                    error(ErrorKind::UnknownFunction(name.clone()))
+                })?;
+                if definition.params.len() != args.len() {
+                    return Err(error(ErrorKind::WrongArgumentCount(
+                        name.clone(),
+                        Arity::Exactly(definition.params.len()),
+                        args.len(),
+                    )));
+                }
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(error(ErrorKind::RecursionLimit(name.clone())));
+                }
+                definition
+                    .body
+                    .substitute(&definition.params, &inline_all(args)?)
+                    .inline_at(definitions, depth + 1)?
+            }
+        })
+    }
+
+    /// Replaces the variables named in `params` with `args`, like the
+    /// parameters of a function body with the arguments of a call.
+    pub fn substitute(&self, params: &[String], args: &[Expr]) -> Expr {
+        let substitute = |expr: &Expr| expr.substitute(params, args);
+        match self {
+            Expr::Variable(name, _) => {
+                match params.iter().position(|p| p == name) {
+                    Some(i) => args[i].clone(),
+                    None => self.clone(),
+                }
+            }
+            Expr::Number(_) | Expr::Decimal(_, _) | Expr::Constant(_) => {
+                self.clone()
+            }
+            Expr::Neg(expr) => Expr::Neg(Box::new(substitute(expr))),
+            Expr::Binary(op, left, right, span) => Expr::Binary(
+                *op,
+                Box::new(substitute(left)),
+                Box::new(substitute(right)),
+                *span,
+            ),
+            Expr::Call(f, args) => {
+                Expr::Call(*f, args.iter().map(substitute).collect())
+            }
+            Expr::UserCall(name, args, span) => Expr::UserCall(
+                name.clone(),
+                args.iter().map(substitute).collect(),
+                *span,
+            ),
+            Expr::Matrix(elements, span) => {
+                Expr::Matrix(elements.iter().map(substitute).collect(), *span)
+            }
+        }
+    }
+
+    /// The names of the variables and user functions the expression depends
+    /// on, also through the bodies of the user functions it calls.
+    pub fn dependencies(&self, definitions: &Definitions) -> HashSet<String> {
+        let mut names = HashSet::new();
+        self.collect_dependencies(definitions, &[], &mut names);
+        names
+    }
+
+    fn collect_dependencies(
+        &self,
+        definitions: &Definitions,
+        params: &[String],
+        names: &mut HashSet<String>,
+    ) {
+        match self {
+            Expr::Number(_) | Expr::Decimal(_, _) | Expr::Constant(_) => {}
+            Expr::Variable(name, _) => {
+                if !params.contains(name) {
+                    names.insert(name.clone());
+                }
+            }
+            Expr::Neg(expr) => {
+                expr.collect_dependencies(definitions, params, names)
+            }
+            Expr::Binary(_, left, right, _) => {
+                left.collect_dependencies(definitions, params, names);
+                right.collect_dependencies(definitions, params, names);
+            }
+            Expr::Call(_, args) | Expr::Matrix(args, _) => {
+                for arg in args {
+                    arg.collect_dependencies(definitions, params, names);
+                }
+            }
+            Expr::UserCall(name, args, _) => {
+                for arg in args {
+                    arg.collect_dependencies(definitions, params, names);
+                }
+                // A function already seen is not followed again, so
+                // recursive definitions end
+                if names.insert(name.clone()) {
+                    if let Some(definition) = definitions.get(name) {
+                        definition.body.collect_dependencies(
+                            definitions,
+                            &definition.params,
+                            names,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn contains_variable(&self, name: &str) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Decimal(_, _) | Expr::Constant(_) => false,
+            Expr::Variable(v, _) => v == name,
+            Expr::Neg(expr) => expr.contains_variable(name),
+            Expr::Binary(_, left, right, _) => {
+                left.contains_variable(name) || right.contains_variable(name)
+            }
+            Expr::Call(_, args)
+            | Expr::UserCall(_, args, _)
+            | Expr::Matrix(args, _) => {
+                args.iter().any(|arg| arg.contains_variable(name))
+            }
+        }
+    }
+}
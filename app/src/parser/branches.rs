@@ -0,0 +1,297 @@
+use crate::parser::ast::Real;
+use num_complex::Complex;
+use num_traits::{One, Zero};
+
+/// Most values listed for a rational power, `z^(p/q)` has `q` of them.
+pub const MAX_BRANCHES: usize = 32;
+
+/// Where the multivalued functions (`ln`, `log`, `sqrt`, `cbrt`, `root`,
+/// `pow` and `^`, and the inverse trigonometric and hyperbolic functions
+/// written with `ln` and `sqrt`) are cut, the ray from zero at `angle`.
+///
+/// Arguments are taken in `(angle - 2π, angle]`, so a cut at `π` is the
+/// principal branch of `num_complex`, along the negative real axis. That one
+/// also keeps the side of a signed zero, `-4 - 0i` has argument `-π`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BranchCut<T> {
+    angle: T,
+}
+
+impl<T: Real> BranchCut<T> {
+    pub fn principal() -> BranchCut<T> {
+        BranchCut { angle: T::PI() }
+    }
+
+    pub fn new(angle: T) -> BranchCut<T> {
+        BranchCut { angle }
+    }
+
+    fn is_principal(&self) -> bool {
+        self.angle == T::PI()
+    }
+
+    /// The argument of `z`, in `(angle - 2π, angle]`.
+    pub fn arg(&self, z: Complex<T>) -> T {
+        let arg = z.arg();
+        if self.is_principal() {
+            return arg;
+        }
+        let turn = T::PI() + T::PI();
+        arg - ((arg - self.angle) / turn).ceil() * turn
+    }
+
+    /// The `k`th branch of the logarithm, `ln|z| + i(arg z + 2πk)`.
+    pub fn ln(&self, z: Complex<T>, k: T) -> Complex<T> {
+        if self.is_principal() && k.is_zero() {
+            return z.ln();
+        }
+        let turn = T::PI() + T::PI();
+        Complex::new(z.norm().ln(), self.arg(z) + turn * k)
+    }
+
+    /// The `k`th branch of `z^w`, `exp(w ln(z, k))`.
+    pub fn pow(&self, z: Complex<T>, w: Complex<T>, k: T) -> Complex<T> {
+        if self.is_principal() && k.is_zero() {
+            return z.powc(w);
+        }
+        if w.is_zero() {
+            return Complex::one();
+        }
+        (w * self.ln(z, k)).exp()
+    }
+
+    /// The `k`th square root, the principal one negated for odd `k`.
+    pub fn sqrt(&self, z: Complex<T>, k: T) -> Complex<T> {
+        if self.is_principal() && k.is_zero() {
+            return z.sqrt();
+        }
+        let half = T::one() / (T::one() + T::one());
+        self.pow(z, Complex::new(half, T::zero()), k)
+    }
+
+    /// The `k`th cube root.
+    pub fn cbrt(&self, z: Complex<T>, k: T) -> Complex<T> {
+        if self.is_principal() && k.is_zero() {
+            return z.cbrt();
+        }
+        let third = T::one() / (T::one() + T::one() + T::one());
+        self.pow(z, Complex::new(third, T::zero()), k)
+    }
+
+    /// `-i ln(iz + sqrt(1 - z^2))`, as in `num_complex`.
+    pub fn asin(&self, z: Complex<T>) -> Complex<T> {
+        if self.is_principal() {
+            return z.asin();
+        }
+        let root = self.sqrt(Complex::<T>::one() - z * z, T::zero());
+        -i::<T>() * self.ln(i::<T>() * z + root, T::zero())
+    }
+
+    /// `-i ln(z + i sqrt(1 - z^2))`, as in `num_complex`.
+    pub fn acos(&self, z: Complex<T>) -> Complex<T> {
+        if self.is_principal() {
+            return z.acos();
+        }
+        let root = self.sqrt(Complex::<T>::one() - z * z, T::zero());
+        -i::<T>() * self.ln(z + i::<T>() * root, T::zero())
+    }
+
+    /// `(ln(1 + iz) - ln(1 - iz)) / 2i`, as in `num_complex`.
+    pub fn atan(&self, z: Complex<T>) -> Complex<T> {
+        if self.is_principal() {
+            return z.atan();
+        }
+        let one = Complex::<T>::one();
+        let iz = i::<T>() * z;
+        (self.ln(one + iz, T::zero()) - self.ln(one - iz, T::zero()))
+            / (i::<T>() + i::<T>())
+    }
+
+    /// `ln(z + sqrt(z^2 + 1))`, as in `num_complex`.
+    pub fn asinh(&self, z: Complex<T>) -> Complex<T> {
+        if self.is_principal() {
+            return z.asinh();
+        }
+        let root = self.sqrt(z * z + T::one(), T::zero());
+        self.ln(z + root, T::zero())
+    }
+
+    /// `2 ln(sqrt((z + 1)/2) + sqrt((z - 1)/2))`, as in `num_complex`.
+    pub fn acosh(&self, z: Complex<T>) -> Complex<T> {
+        if self.is_principal() {
+            return z.acosh();
+        }
+        let two = T::one() + T::one();
+        let plus = self.sqrt((z + T::one()) / two, T::zero());
+        let minus = self.sqrt((z - T::one()) / two, T::zero());
+        self.ln(plus + minus, T::zero()) * two
+    }
+
+    /// `(ln(1 + z) - ln(1 - z)) / 2`, as in `num_complex`.
+    pub fn atanh(&self, z: Complex<T>) -> Complex<T> {
+        if self.is_principal() {
+            return z.atanh();
+        }
+        let one = Complex::<T>::one();
+        (self.ln(one + z, T::zero()) - self.ln(one - z, T::zero()))
+            / (T::one() + T::one())
+    }
+
+    /// Every value of `z^w` for a rational exponent `w = p/q`, the `q`
+    /// branches from `k = 0`.
+    ///
+    /// Complex exponents, and real ones that are not a fraction with a
+    /// denominator up to [`MAX_BRANCHES`], have infinitely many values and
+    /// give `None`.
+    pub fn power_branches(
+        &self,
+        z: Complex<T>,
+        w: Complex<T>,
+    ) -> Option<Vec<Complex<T>>> {
+        if !w.im.is_zero() {
+            return None;
+        }
+        let exponent = w.re.to_f64()?;
+        let q = (1..=MAX_BRANCHES).find(|q| {
+            let p = exponent * *q as f64;
+            (p - p.round()).abs() < 1e-6
+        })?;
+        Some(
+            (0..q)
+                .map(|k| self.pow(z, w, T::from(k).unwrap_or_else(T::nan)))
+                .collect(),
+        )
+    }
+}
+
+fn i<T: Real>() -> Complex<T> {
+    Complex::new(T::zero(), T::one())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn assert_close(z: Complex<f64>, expected: Complex<f64>) {
+        assert!((z - expected).norm() < 1e-12, "{} != {}", z, expected);
+    }
+
+    #[test]
+    fn takes_arguments_below_the_cut() {
+        let z = Complex::new(-1.0, -1.0);
+        assert_close(
+            Complex::new(BranchCut::principal().arg(z), 0.0),
+            Complex::new(-0.75 * PI, 0.0),
+        );
+        let cut = BranchCut::new(0.0);
+        assert_close(
+            Complex::new(cut.arg(z), 0.0),
+            Complex::new(-0.75 * PI, 0.0),
+        );
+        assert_close(
+            Complex::new(cut.arg(Complex::new(-1.0, 1.0)), 0.0),
+            Complex::new(-1.25 * PI, 0.0),
+        );
+        assert_close(
+            Complex::new(cut.arg(Complex::new(1.0, 0.0)), 0.0),
+            Complex::new(0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn lists_logarithm_branches() {
+        let z = Complex::new(2.0, 1.0);
+        let principal = BranchCut::principal();
+        assert_eq!(principal.ln(z, 0.0), z.ln());
+        assert_close(
+            principal.ln(z, 2.0),
+            z.ln() + Complex::new(0.0, 4.0 * PI),
+        );
+        assert_close(
+            principal.ln(z, -1.0),
+            z.ln() - Complex::new(0.0, 2.0 * PI),
+        );
+    }
+
+    #[test]
+    fn moves_the_cut_of_roots() {
+        let z = Complex::new(-4.0, -0.0);
+        assert_close(BranchCut::principal().sqrt(z, 0.0), z.sqrt());
+        assert_close(BranchCut::principal().sqrt(z, 1.0), -z.sqrt());
+        // With the cut along the positive real axis, arguments are in
+        // (-2π, 0]
+        let cut = BranchCut::new(0.0);
+        assert_close(cut.sqrt(z, 0.0), Complex::new(0.0, -2.0));
+        assert_close(
+            cut.sqrt(Complex::new(4.0, 0.0), 0.0),
+            Complex::new(2.0, 0.0),
+        );
+        assert_close(
+            cut.cbrt(Complex::new(8.0, 0.0), 1.0),
+            Complex::from_polar(2.0, 2.0 * PI / 3.0),
+        );
+    }
+
+    #[test]
+    fn lists_every_root() {
+        let roots = BranchCut::principal()
+            .power_branches(
+                Complex::new(-8.0, 0.0),
+                Complex::new(1.0 / 3.0, 0.0),
+            )
+            .unwrap();
+        assert_eq!(roots.len(), 3);
+        for (k, root) in roots.iter().enumerate() {
+            let angle = (1.0 + 2.0 * k as f64) * PI / 3.0;
+            assert_close(*root, Complex::from_polar(2.0, angle));
+        }
+
+        let z = Complex::new(1.0, 2.0);
+        let powers = BranchCut::principal()
+            .power_branches(z, Complex::new(2.0 / 3.0, 0.0))
+            .unwrap();
+        assert_eq!(powers.len(), 3);
+        for power in powers {
+            assert_close(power.powi(3), z * z);
+        }
+
+        let cut = BranchCut::<f64>::principal();
+        assert!(cut.power_branches(z, Complex::new(PI, 0.0)).is_none());
+        assert!(cut.power_branches(z, Complex::new(0.5, 1.0)).is_none());
+    }
+
+    #[test]
+    fn moves_the_cut_of_inverse_functions() {
+        let z = Complex::new(-3.0, -0.0);
+        let principal = BranchCut::principal();
+        assert_eq!(principal.acosh(z), z.acosh());
+        assert_eq!(principal.atan(z), z.atan());
+        // Every branch is still an inverse
+        for angle in [0.0, PI / 2.0, -PI / 3.0].iter() {
+            let cut = BranchCut::new(*angle);
+            for z in [
+                Complex::new(0.3, 0.2),
+                Complex::new(-2.0, 0.5),
+                Complex::new(1.5, -3.0),
+            ]
+            .iter()
+            {
+                let z = *z;
+                assert_close(cut.asin(z).sin(), z);
+                assert_close(cut.acos(z).cos(), z);
+                assert_close(cut.atan(z).tan(), z);
+                assert_close(cut.asinh(z).sinh(), z);
+                assert_close(cut.acosh(z).cosh(), z);
+                assert_close(cut.atanh(z).tanh(), z);
+            }
+        }
+        // With the cut along the positive real axis, sqrt(z^2 + 1) jumps
+        // across the real line, where the principal asinh is continuous
+        let cut = BranchCut::new(0.0);
+        let above = Complex::new(-2.0, 1e-9);
+        let below = Complex::new(-2.0, -1e-9);
+        assert!((cut.asinh(above) - cut.asinh(below)).norm() > 1.0);
+        assert!((above.asinh() - below.asinh()).norm() < 1e-6);
+    }
+}
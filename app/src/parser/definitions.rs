@@ -0,0 +1,52 @@
+use crate::parser::ast::Expr;
+use std::collections::HashMap;
+
+/// A function defined by the user, like `f(z) := z^2 + c`.
+///
+/// The body sees its parameters and the variables the calling expression is
+/// evaluated with, so `c` is looked up each time `f` is called.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+    /// The input the definition was parsed from.
+    pub source: String,
+}
+
+/// The functions defined by the user, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Definitions {
+    functions: HashMap<String, Definition>,
+}
+
+impl Definitions {
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.functions.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Adds a definition, replacing an earlier one with the same name.
+    pub fn insert(&mut self, definition: Definition) {
+        self.functions.insert(definition.name.clone(), definition);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Definition> {
+        self.functions.remove(name)
+    }
+
+    pub fn clear(&mut self) {
+        self.functions.clear();
+    }
+
+    /// The definitions sorted by name.
+    pub fn sorted(&self) -> Vec<&Definition> {
+        let mut definitions: Vec<&Definition> =
+            self.functions.values().collect();
+        definitions.sort_by(|a, b| a.name.cmp(&b.name));
+        definitions
+    }
+}
@@ -0,0 +1,565 @@
+use crate::parser::ast::{Constant, Context, Expr, Operator};
+use crate::parser::definitions::Definitions;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+use crate::parser::lexer::Span;
+use crate::parser::simplify::simplify;
+use num_complex::Complex;
+use std::collections::HashMap;
+
+/// The derivative of `expr` with respect to `variable`, by the chain rule.
+///
+/// Calls of user functions are expanded first, every other variable is a
+/// constant. Functions that are not holomorphic (`re`, `abs`, `conj`, ...)
+/// and those whose derivative is not a built-in (`gamma`, `lgamma`, `zeta`)
+/// are errors, as are branch indices and Bessel orders that depend on
+/// `variable`. Those calls carry no span, so the error spans nothing.
+///
+/// The result is simplified, see [`simplify`].
+pub fn derivative(
+    expr: &Expr,
+    variable: &str,
+    definitions: &Definitions,
+) -> Result<Expr, Error> {
+    let expr = expr.inline(definitions)?;
+    differentiate(&expr, variable).map(|derivative| simplify(&derivative))
+}
+
+fn differentiate(expr: &Expr, variable: &str) -> Result<Expr, Error> {
+    if !expr.contains_variable(variable) {
+        return Ok(number(0.0));
+    }
+    let d = |expr: &Expr| differentiate(expr, variable);
+    Ok(match expr {
+        Expr::Variable(_, _) => number(1.0),
+        Expr::Neg(expr) => neg(d(expr)?),
+        Expr::Binary(Operator::Add, left, right, _) => add(d(left)?, d(right)?),
+        Expr::Binary(Operator::Sub, left, right, _) => sub(d(left)?, d(right)?),
+        Expr::Binary(Operator::Mul, left, right, _) => add(
+            mul(d(left)?, right.as_ref().clone()),
+            mul(left.as_ref().clone(), d(right)?),
+        ),
+        Expr::Binary(Operator::Div, left, right, _) => {
+            if !right.contains_variable(variable) {
+                div(d(left)?, right.as_ref().clone())
+            } else {
+                div(
+                    sub(
+                        mul(d(left)?, right.as_ref().clone()),
+                        mul(left.as_ref().clone(), d(right)?),
+                    ),
+                    pow(right.as_ref().clone(), number(2.0)),
+                )
+            }
+        }
+        Expr::Binary(Operator::Pow, base, exponent, _) => {
+            power(expr, base, exponent, variable)?
+        }
+        Expr::Call(f, args) => call(expr, *f, args, variable)?,
+        // Element by element
+        Expr::Matrix(elements, span) => Expr::Matrix(
+            elements.iter().map(d).collect::<Result<_, _>>()?,
+            *span,
+        ),
+        // Numbers and constants have no variable, and user functions are
+        // inlined before differentiating
+        Expr::Number(_) | Expr::Decimal(_, _) | Expr::Constant(_) => {
+            number(0.0)
+        }
+        Expr::UserCall(name, _, span) => {
+            return Err(Error::new(
+                ErrorKind::UnknownFunction(name.clone()),
+                *span,
+            ))
+        }
+    })
+}
+
+/// `(u^w)' = w u^(w-1) u'` for a constant exponent, and `u^w (w ln u)'`
+/// otherwise.
+fn power(
+    expr: &Expr,
+    base: &Expr,
+    exponent: &Expr,
+    variable: &str,
+) -> Result<Expr, Error> {
+    if !exponent.contains_variable(variable) {
+        let reduced = pow(base.clone(), sub(exponent.clone(), number(1.0)));
+        return Ok(mul(
+            differentiate(base, variable)?,
+            mul(exponent.clone(), reduced),
+        ));
+    }
+    let log = mul(
+        exponent.clone(),
+        Expr::Call(Function::Ln, vec![base.clone()]),
+    );
+    Ok(mul(differentiate(&log, variable)?, expr.clone()))
+}
+
+/// The chain rule, `f(u)' = u' f'(u)`.
+fn call(
+    expr: &Expr,
+    f: Function,
+    args: &[Expr],
+    variable: &str,
+) -> Result<Expr, Error> {
+    let not_differentiable = || {
+        Err(Error::new(
+            ErrorKind::NotDifferentiable(f.name().to_string()),
+            Span::default(),
+        ))
+    };
+    // Branch indices and orders are rounded, they may not vary
+    let indices: &[Expr] = match f {
+        Function::Ln | Function::Sqrt | Function::Lambertw => &args[1..],
+        Function::Root => &args[2..],
+        Function::Besselj => &args[..1],
+        _ => &[],
+    };
+    if indices.iter().any(|arg| arg.contains_variable(variable)) {
+        return not_differentiable();
+    }
+
+    let u = || args[0].clone();
+    let call = |f: Function, u: Expr| Expr::Call(f, vec![u]);
+    let square = |u: Expr| pow(u, number(2.0));
+    let outer = match f {
+        Function::Sin => call(Function::Cos, u()),
+        Function::Cos => neg(call(Function::Sin, u())),
+        Function::Tan => div(number(1.0), square(call(Function::Cos, u()))),
+        Function::Csc => neg(mul(expr.clone(), call(Function::Cot, u()))),
+        Function::Sec => mul(expr.clone(), call(Function::Tan, u())),
+        Function::Cot => {
+            neg(div(number(1.0), square(call(Function::Sin, u()))))
+        }
+        Function::Asin => div(
+            number(1.0),
+            call(Function::Sqrt, sub(number(1.0), square(u()))),
+        ),
+        Function::Acos => neg(div(
+            number(1.0),
+            call(Function::Sqrt, sub(number(1.0), square(u()))),
+        )),
+        Function::Atan => div(number(1.0), add(number(1.0), square(u()))),
+        Function::Sinh => call(Function::Cosh, u()),
+        Function::Cosh => call(Function::Sinh, u()),
+        Function::Tanh => div(number(1.0), square(call(Function::Cosh, u()))),
+        Function::Asinh => div(
+            number(1.0),
+            call(Function::Sqrt, add(square(u()), number(1.0))),
+        ),
+        Function::Acosh => div(
+            number(1.0),
+            mul(
+                call(Function::Sqrt, sub(u(), number(1.0))),
+                call(Function::Sqrt, add(u(), number(1.0))),
+            ),
+        ),
+        Function::Atanh => div(number(1.0), sub(number(1.0), square(u()))),
+        Function::Inv => neg(div(number(1.0), square(u()))),
+        Function::Exp => expr.clone(),
+        Function::Cis => {
+            mul(Expr::Number(Complex::new(0.0, 1.0)), expr.clone())
+        }
+        Function::Ln => div(number(1.0), u()),
+        Function::Sqrt => div(number(1.0), mul(number(2.0), expr.clone())),
+        Function::Cbrt => {
+            div(number(1.0), mul(number(3.0), square(expr.clone())))
+        }
+        Function::Erf | Function::Erfc => {
+            let gaussian = mul(
+                div(
+                    number(2.0),
+                    call(Function::Sqrt, Expr::Constant(Constant::Pi)),
+                ),
+                call(Function::Exp, neg(square(u()))),
+            );
+            if f == Function::Erf {
+                gaussian
+            } else {
+                neg(gaussian)
+            }
+        }
+        // J_n' = (J_(n-1) - J_(n+1)) / 2
+        Function::Besselj => {
+            let order = |shift: Expr| {
+                Expr::Call(Function::Besselj, vec![shift, args[1].clone()])
+            };
+            let n = args[0].clone();
+            let derivative = div(
+                sub(
+                    order(sub(n.clone(), number(1.0))),
+                    order(add(n, number(1.0))),
+                ),
+                number(2.0),
+            );
+            return Ok(mul(differentiate(&args[1], variable)?, derivative));
+        }
+        // W' = 1 / (e^W (1 + W)), which is also right at zero
+        Function::Lambertw => div(
+            number(1.0),
+            mul(
+                call(Function::Exp, expr.clone()),
+                add(number(1.0), expr.clone()),
+            ),
+        ),
+        // Rewritten in terms of the functions above
+        Function::Log => {
+            let ln = |u: Expr| call(Function::Ln, u);
+            return differentiate(&div(ln(u()), ln(args[1].clone())), variable);
+        }
+        Function::Pow => {
+            let power = Expr::Binary(
+                Operator::Pow,
+                Box::new(u()),
+                Box::new(args[1].clone()),
+                Span::default(),
+            );
+            return differentiate(&power, variable);
+        }
+        // root(u, n, k) = exp(ln(u, k) / n)
+        Function::Root => {
+            let mut ln_args = vec![u()];
+            ln_args.extend(args.get(2).cloned());
+            let log = div(Expr::Call(Function::Ln, ln_args), args[1].clone());
+            return Ok(mul(differentiate(&log, variable)?, expr.clone()));
+        }
+        _ => return not_differentiable(),
+    };
+    Ok(mul(differentiate(&args[0], variable)?, outer))
+}
+
+fn number(x: f64) -> Expr {
+    Expr::Number(Complex::new(x, 0.0))
+}
+
+fn value(expr: &Expr) -> Option<Complex<f64>> {
+    match expr {
+        Expr::Number(z) => Some(*z),
+        _ => None,
+    }
+}
+
+fn is(expr: &Expr, x: f64) -> bool {
+    value(expr) == Some(Complex::new(x, 0.0))
+}
+
+fn binary(op: Operator, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, Box::new(left), Box::new(right), Span::default())
+}
+
+// The constructors below drop the zeros and ones the rules above leave
+// behind, fold numbers and move negations and constant factors to the front.
+
+fn neg(expr: Expr) -> Expr {
+    match expr {
+        Expr::Number(z) => Expr::Number(-z),
+        Expr::Neg(expr) => *expr,
+        _ => Expr::Neg(Box::new(expr)),
+    }
+}
+
+fn add(left: Expr, right: Expr) -> Expr {
+    match (value(&left), value(&right)) {
+        (Some(a), Some(b)) => Expr::Number(a + b),
+        (Some(a), _) if a == Complex::new(0.0, 0.0) => right,
+        (_, Some(b)) if b == Complex::new(0.0, 0.0) => left,
+        _ => match right {
+            Expr::Neg(right) => binary(Operator::Sub, left, *right),
+            _ => binary(Operator::Add, left, right),
+        },
+    }
+}
+
+fn sub(left: Expr, right: Expr) -> Expr {
+    match (value(&left), value(&right)) {
+        (Some(a), Some(b)) => Expr::Number(a - b),
+        (Some(a), _) if a == Complex::new(0.0, 0.0) => neg(right),
+        (_, Some(b)) if b == Complex::new(0.0, 0.0) => left,
+        _ => match right {
+            Expr::Neg(right) => binary(Operator::Add, left, *right),
+            _ => binary(Operator::Sub, left, right),
+        },
+    }
+}
+
+fn mul(left: Expr, right: Expr) -> Expr {
+    if is(&left, 0.0) || is(&right, 0.0) {
+        return number(0.0);
+    }
+    if is(&left, 1.0) {
+        return right;
+    }
+    if is(&right, 1.0) {
+        return left;
+    }
+    match (left, right) {
+        (Expr::Number(a), Expr::Number(b)) => Expr::Number(a * b),
+        (Expr::Neg(left), right) => neg(mul(*left, right)),
+        (left, Expr::Neg(right)) => neg(mul(left, *right)),
+        (left, right @ Expr::Number(_)) => mul(right, left),
+        (Expr::Number(a), Expr::Binary(Operator::Mul, b, rest, _))
+            if value(&b).is_some() =>
+        {
+            mul(Expr::Number(a * value(&b).unwrap_or_default()), *rest)
+        }
+        (left, Expr::Binary(Operator::Mul, factor, rest, _))
+            if value(&factor).is_some() =>
+        {
+            mul(mul(*factor, left), *rest)
+        }
+        (left, Expr::Binary(Operator::Div, numerator, denominator, _)) => {
+            div(mul(left, *numerator), *denominator)
+        }
+        (left, right) => binary(Operator::Mul, left, right),
+    }
+}
+
+fn div(left: Expr, right: Expr) -> Expr {
+    if is(&left, 0.0) {
+        return number(0.0);
+    }
+    if is(&right, 1.0) {
+        return left;
+    }
+    match left {
+        Expr::Neg(left) => neg(div(*left, right)),
+        _ => binary(Operator::Div, left, right),
+    }
+}
+
+fn pow(base: Expr, exponent: Expr) -> Expr {
+    if is(&exponent, 0.0) {
+        return number(1.0);
+    }
+    if is(&exponent, 1.0) {
+        return base;
+    }
+    binary(Operator::Pow, base, exponent)
+}
+
+/// The partial derivatives of `f = u + iv` at a point, `u_x` being the
+/// derivative of the real part along the real axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CauchyRiemann {
+    pub u_x: f64,
+    pub u_y: f64,
+    pub v_x: f64,
+    pub v_y: f64,
+}
+
+impl CauchyRiemann {
+    /// How far the partials are from `u_x = v_y` and `u_y = -v_x`.
+    pub fn residual(&self) -> f64 {
+        (self.u_x - self.v_y).hypot(self.u_y + self.v_x)
+    }
+
+    /// Whether the equations hold, up to the error of the differences.
+    pub fn holds(&self) -> bool {
+        let scale = self.u_x.hypot(self.u_y).max(self.v_x.hypot(self.v_y));
+        self.residual() <= 1e-6 * (1.0 + scale)
+    }
+
+    /// `f'(z) = u_x + i v_x`, when the equations hold.
+    pub fn derivative(&self) -> Complex<f64> {
+        Complex::new(self.u_x, self.v_x)
+    }
+}
+
+/// The partial derivatives of `expr` at `point`, with `variable` set to
+/// nearby points, by central differences in double precision.
+pub fn cauchy_riemann(
+    expr: &Expr,
+    variable: &str,
+    point: Complex<f64>,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+) -> Result<CauchyRiemann, Error> {
+    let h = 1e-5 * point.norm().max(1.0);
+    let mut env = variables.clone();
+    let mut f = |z: Complex<f64>| {
+        env.insert(variable.to_string(), z);
+        expr.eval_with::<f64, _>(&env, context)
+    };
+    let along_x = (f(point + h)? - f(point - h)?) / (2.0 * h);
+    let step = Complex::new(0.0, h);
+    let along_y = (f(point + step)? - f(point - step)?) / (2.0 * h);
+    Ok(CauchyRiemann {
+        u_x: along_x.re,
+        u_y: along_y.re,
+        v_x: along_x.im,
+        v_y: along_y.im,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::{
+        parse, parse_statement, parse_with, Statement,
+    };
+
+    fn d(input: &str) -> String {
+        derivative(&parse(input).unwrap(), "z", &Definitions::default())
+            .unwrap()
+            .to_string()
+    }
+
+    /// Checks the derivative against central differences at a few points.
+    fn check(input: &str) {
+        let expr = parse(input).unwrap();
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let derivative = derivative(&expr, "z", &definitions).unwrap();
+        for point in [
+            Complex::new(0.3, 0.4),
+            Complex::new(-0.7, 0.2),
+            Complex::new(1.5, -0.6),
+        ]
+        .iter()
+        {
+            let expected =
+                cauchy_riemann(&expr, "z", *point, &HashMap::new(), &context)
+                    .unwrap()
+                    .derivative();
+            let found: Complex<f64> =
+                derivative.eval(&[("z", *point)][..]).unwrap();
+            assert!(
+                (found - expected).norm() < 1e-7 * (1.0 + expected.norm()),
+                "d/dz {} = {} at {}: {} != {}",
+                input,
+                derivative,
+                point,
+                found,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn differentiates_polynomials() {
+        assert_eq!(d("z^3"), "3*z^2");
+        assert_eq!(d("3*z^2 + 2*z - 7"), "6*z + 2");
+        assert_eq!(d("-z"), "-1");
+        assert_eq!(d("c*z"), "c");
+        assert_eq!(d("PI"), "0");
+    }
+
+    #[test]
+    fn applies_the_chain_rule() {
+        assert_eq!(d("sin(z^2)"), "2*z*cos(z^2)");
+        assert_eq!(d("cos z"), "-sin(z)");
+        assert_eq!(d("exp(2z)"), "2*exp(2*z)");
+        assert_eq!(d("ln(z)"), "1/z");
+        assert_eq!(d("(z + 1)/(z - 2)"), "-3/(z - 2)^2");
+        assert_eq!(d("z*sin(z)"), "sin(z) + z*cos(z)");
+    }
+
+    #[test]
+    fn differentiates_every_holomorphic_function() {
+        for input in [
+            "sin z",
+            "cos z",
+            "tan z",
+            "csc z",
+            "sec z",
+            "cot z",
+            "asin z",
+            "acos z",
+            "atan z",
+            "sinh z",
+            "cosh z",
+            "tanh z",
+            "asinh z",
+            "acosh(z + 2)",
+            "atanh z",
+            "inv z",
+            "exp z",
+            "cis z",
+            "ln z",
+            "ln(z, 1)",
+            "sqrt z",
+            "sqrt(z, 1)",
+            "cbrt z",
+            "erf z",
+            "erfc z",
+            "besselj(2, z)",
+            "lambertw z",
+            "lambertw(z, 1)",
+            "log(z, 3)",
+            "log(2, z)",
+            "pow(z, 3)",
+            "root(z, 3)",
+            "root(z, 3, 1)",
+            "z^z",
+            "2^z",
+            "z^(1/3)",
+            "(z + 1)/(z - 2)",
+            "sin(z)*cos(z)",
+        ]
+        .iter()
+        {
+            check(input);
+        }
+    }
+
+    #[test]
+    fn inlines_user_functions() {
+        let mut definitions = Definitions::default();
+        for input in ["f(z) := z^2 + c", "g(w) := f(w) * w"].iter() {
+            match parse_statement(input, &definitions).unwrap() {
+                Statement::Definition(d) => definitions.insert(d),
+                _ => unreachable!(),
+            }
+        }
+        let expr = parse_with("g(2z)", &definitions).unwrap();
+        let derivative = derivative(&expr, "z", &definitions).unwrap();
+        let env =
+            [("z", Complex::new(1.0, 1.0)), ("c", Complex::new(3.0, 0.0))];
+        // d/dz (8z^3 + 2cz) = 24z^2 + 2c
+        let found: Complex<f64> = derivative.eval(&env[..]).unwrap();
+        assert!((found - Complex::new(6.0, 48.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_functions_without_derivatives() {
+        let error = |input| {
+            derivative(&parse(input).unwrap(), "z", &Definitions::default())
+                .unwrap_err()
+                .kind
+        };
+        let kind = |name: &str| ErrorKind::NotDifferentiable(name.to_string());
+        assert_eq!(error("conj z"), kind("conj"));
+        assert_eq!(error("2 + abs(z)"), kind("abs"));
+        assert_eq!(error("gamma(z)"), kind("gamma"));
+        assert_eq!(error("ln(2, z)"), kind("ln"));
+        assert_eq!(error("besselj(z, 1)"), kind("besselj"));
+        assert_eq!(d("abs(c) * z"), "abs(c)");
+    }
+
+    #[test]
+    fn checks_cauchy_riemann_equations() {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let at = |input, point| {
+            cauchy_riemann(
+                &parse(input).unwrap(),
+                "z",
+                point,
+                &HashMap::new(),
+                &context,
+            )
+            .unwrap()
+        };
+        let point = Complex::new(0.5, -1.5);
+        let exp = at("exp z", point);
+        assert!(exp.holds());
+        assert!((exp.derivative() - point.exp()).norm() < 1e-8);
+        assert!(!at("conj z", point).holds());
+        assert!(!at("re(z)^2", point).holds());
+        // |z|^2 only satisfies them at zero
+        assert!(!at("norm z", point).holds());
+        assert!(at("norm z", Complex::new(0.0, 0.0)).holds());
+    }
+}
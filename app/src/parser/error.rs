@@ -0,0 +1,160 @@
+use crate::parser::functions::Arity;
+use crate::parser::lexer::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    EmptyExpression,
+    UnexpectedCharacter(char),
+    MalformedNumber(String),
+    UnbalancedParenthesis,
+    UnknownIdentifier(String),
+    UnknownFunction(String),
+    MissingOperand,
+    MissingOperator,
+    MisplacedComma,
+    /// The function, the arguments it takes and the arguments it was given.
+    WrongArgumentCount(String, Arity, usize),
+    InvalidDefinition,
+    /// A variable name that is not an identifier, like `2x` or `f(z)`.
+    InvalidName(String),
+    ReservedName(String),
+    RecursionLimit(String),
+    /// A function without a symbolic derivative, because it is not
+    /// holomorphic or has none in terms of the built-in functions.
+    NotDifferentiable(String),
+    /// A function only available in single and double precision.
+    NotPrecise(String),
+    /// A function whose value has an exponent too large to hold in
+    /// arbitrary precision, like `exp(1e19)`.
+    Overflow(String),
+    DivisionByZero,
+    /// A matrix where only numbers are computed, in the grapher, the
+    /// calculus page or the arbitrary precision and exact modes.
+    NotScalar,
+    /// A matrix row of another length than the first.
+    RaggedMatrix,
+    /// Operands whose shapes do not fit the operator, like `2x3 * 2x3`.
+    ShapeMismatch(String),
+    /// A function or operator and the shape of the values it takes.
+    WrongShape(String, String),
+    SingularMatrix,
+    /// An iteration that did not reach the tolerance it was asked for.
+    NoConvergence(String),
+    /// An expression that is not a polynomial in the variable, like `1/z`.
+    NotPolynomial(String),
+    /// The zero polynomial, every number is a root of it.
+    ZeroPolynomial,
+    /// A polynomial of a higher degree than the root finder takes.
+    DegreeTooHigh(usize),
+    /// A zero, pole or branch cut of a function on the contour it is
+    /// followed along.
+    OnContour,
+    /// An evaluation stopped before it finished.
+    Cancelled,
+}
+
+/// An error found while parsing or evaluating an input, with the byte range
+/// of the input it refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Span,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, span: Span) -> Error {
+        Error { kind, span }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::EmptyExpression => write!(f, "empty expression"),
+            ErrorKind::UnexpectedCharacter(c) => {
+                write!(f, "unexpected character '{}'", c)
+            }
+            ErrorKind::MalformedNumber(n) => {
+                write!(f, "malformed number '{}'", n)
+            }
+            ErrorKind::UnbalancedParenthesis => {
+                write!(f, "unbalanced parenthesis")
+            }
+            ErrorKind::UnknownIdentifier(name) => {
+                write!(f, "unknown identifier '{}'", name)
+            }
+            ErrorKind::UnknownFunction(name) => {
+                write!(f, "unknown function '{}'", name)
+            }
+            ErrorKind::MissingOperand => write!(f, "missing operand"),
+            ErrorKind::MissingOperator => write!(f, "missing operator"),
+            ErrorKind::MisplacedComma => {
+                write!(f, "comma outside of a function call")
+            }
+            ErrorKind::WrongArgumentCount(name, arity, found) => {
+                write!(f, "'{}' takes {}, found {}", name, arity, found)
+            }
+            ErrorKind::InvalidDefinition => {
+                write!(f, "definitions are written like f(z) := z^2")
+            }
+            ErrorKind::InvalidName(name) => {
+                write!(f, "'{}' is not a valid variable name", name)
+            }
+            ErrorKind::ReservedName(name) => {
+                write!(f, "'{}' is a built-in name", name)
+            }
+            ErrorKind::RecursionLimit(name) => {
+                write!(f, "too many nested calls of '{}'", name)
+            }
+            ErrorKind::NotDifferentiable(name) => {
+                write!(f, "'{}' has no symbolic derivative", name)
+            }
+            ErrorKind::NotPrecise(name) => {
+                write!(f, "'{}' is not available in arbitrary precision", name)
+            }
+            ErrorKind::Overflow(name) => {
+                write!(f, "the value of '{}' is too large", name)
+            }
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::NotScalar => {
+                write!(f, "matrices are only computed by the calculator")
+            }
+            ErrorKind::RaggedMatrix => {
+                write!(f, "the rows of a matrix need the same length")
+            }
+            ErrorKind::ShapeMismatch(shapes) => {
+                write!(f, "the shapes of {} do not fit", shapes)
+            }
+            ErrorKind::WrongShape(name, shape) => {
+                write!(f, "'{}' takes {}", name, shape)
+            }
+            ErrorKind::SingularMatrix => write!(f, "the matrix is singular"),
+            ErrorKind::NoConvergence(name) => {
+                write!(f, "'{}' did not converge", name)
+            }
+            ErrorKind::NotPolynomial(variable) => {
+                write!(f, "not a polynomial in '{}'", variable)
+            }
+            ErrorKind::ZeroPolynomial => {
+                write!(f, "every number is a root of the zero polynomial")
+            }
+            ErrorKind::DegreeTooHigh(degree) => {
+                write!(f, "polynomials are solved up to degree {}", degree)
+            }
+            ErrorKind::OnContour => {
+                write!(f, "the function has a zero or pole on the contour")
+            }
+            ErrorKind::Cancelled => write!(f, "evaluation cancelled"),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (at {}..{})",
+            self.kind, self.span.start, self.span.end
+        )
+    }
+}
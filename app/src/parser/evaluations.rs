@@ -1,184 +1,469 @@
-use crate::parser::symbolic::parse_complex_calculation;
+use crate::parser::ast::{cast, widen, Context, Expr, Operator, Real};
+use crate::parser::branches::BranchCut;
+use crate::parser::definitions::Definitions;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+use crate::parser::lexer::Span;
+use crate::parser::program::Program;
 use num_complex::Complex;
+use rayon::prelude::*;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-pub fn evaulate_points_on_contour(
-    algorithm: Vec<String>,
-    x_interval: (i32, i32),
-    x_precision: i32,
-    contour: Vec<String>,
-) -> Vec<(Complex<f32>, i32, i32)> {
-    let mut points = vec![];
-
-    // Loop x interval
-    for i in x_interval.0 * x_precision..x_interval.1 * x_precision + 1 {
-        let x = i as f32 / x_precision as f32;
-        // Create new contour with x change
-        let mut contour_algorithm = vec![];
-        contour.iter().for_each(|v| {
-            if v == "x" {
-                contour_algorithm.push(x.to_string());
-            } else {
-                contour_algorithm.push(v.clone());
-            }
-        });
-        let y = parse_complex_calculation(contour_algorithm);
+/// Shared with an evaluation running on other threads, to follow how many of
+/// its points are done and to cancel it. The search for zeros and poles
+/// counts its cells with it instead.
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
 
-        if y.is_none() {
-            break;
+impl Progress {
+    /// The part of the points evaluated so far, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
         }
+        self.done.load(Ordering::Relaxed) as f32 / total as f32
+    }
 
-        let y = y.unwrap().re;
-
-        // format complex number in reverse polish notation
-        let z = Complex::new(x, y);
-        let z = format!("{}+i{}", z.re, z.im);
-        let mut z: Vec<String> = z.split("+").map(|s| s.to_string()).collect();
-        z.push("+".to_string());
-
-        // Create new algorithm with z change
-        let mut algorithm_z_change = vec![];
-        algorithm.iter().for_each(|v| {
-            if v == "z" {
-                algorithm_z_change.append(&mut z.clone());
-            } else {
-                algorithm_z_change.push(v.clone());
-            }
-        });
-
-        // Calculate new algorithm
-        let z = parse_complex_calculation(algorithm_z_change);
-
-        // Check if calculation exists, and add to points
-        if z.is_some() {
-            let z = z.unwrap();
-            let re = z.re;
-            let im = z.im;
-            if !re.is_infinite()
-                && !re.is_nan()
-                && !im.is_infinite()
-                && !im.is_nan()
-            {
-                points.push((z, i as i32, i as i32));
-            }
+    /// Stops the evaluation at the next column, which then fails with
+    /// [`ErrorKind::Cancelled`].
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn start(&self, total: usize) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::new(ErrorKind::Cancelled, Span::default()));
         }
+        Ok(())
+    }
+
+    pub(crate) fn advance(&self, points: usize) {
+        self.done.fetch_add(points, Ordering::Relaxed);
+    }
+}
+
+fn is_finite<T: Real>(z: Complex<T>) -> bool {
+    z.re.is_finite() && z.im.is_finite()
+}
+
+/// Converts a grid index into a coordinate.
+fn coordinate<T: Real>(i: i32, precision: i32) -> T {
+    T::from(i).unwrap_or_else(T::nan)
+        / T::from(precision).unwrap_or_else(T::nan)
+}
+
+/// Points where the calculation divided by zero, or that are not finite, are
+/// skipped, any other error stops the evaluation.
+fn keep_point<T: Real>(
+    z: Result<Complex<T>, Error>,
+) -> Result<Option<Complex<T>>, Error> {
+    match z {
+        Ok(z) => Ok(Some(z).filter(|z| is_finite(*z))),
+        Err(Error {
+            kind: ErrorKind::DivisionByZero,
+            ..
+        }) => Ok(None),
+        Err(e) => Err(e),
     }
-    points
 }
 
-pub fn evaulate_points(
-    algorithm: Vec<String>,
+/// Evaluates `function` at `z = x + iy` for the `x` of the interval, with
+/// `y` given by `contour` at `x`.
+///
+/// The points are split between threads, `progress` counts them as they are
+/// done and stops the evaluation when cancelled.
+pub fn evaulate_points_on_contour<T: Real>(
+    function: &Expr,
+    context: &Context,
+    variables: &HashMap<String, Complex<T>>,
+    x_interval: (i32, i32),
+    x_precision: i32,
+    contour: &Expr,
+    progress: &Progress,
+) -> Result<Vec<(Complex<T>, i32, i32)>, Error> {
+    let function = Program::compile(function, &["z"], variables, context)?;
+    let contour = Program::compile(contour, &["x"], variables, context)?;
+    let xs = x_interval.0 * x_precision..x_interval.1 * x_precision + 1;
+    progress.start(xs.len());
+
+    let points = xs
+        .into_par_iter()
+        .map_init(Vec::new, |stack, i| {
+            progress.check()?;
+            progress.advance(1);
+            let x: T = coordinate(i, x_precision);
+            // Evaluate contour with x change
+            let y = contour.run(&[Complex::new(x, T::zero())], stack);
+
+            let y = match y {
+                Ok(y) => y.re,
+                Err(Error {
+                    kind: ErrorKind::DivisionByZero,
+                    ..
+                }) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            // Calculate function with z change
+            let z = function.run(&[Complex::new(x, y)], stack);
+            Ok(keep_point(z)?.map(|z| (z, i, i)))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(points.into_iter().flatten().collect())
+}
+
+/// Evaluates `function` on the grid of the intervals, with `x_precision`
+/// and `y_precision` points per unit.
+///
+/// Each column of the grid is evaluated on its own thread, `progress` counts
+/// the points as they are done and stops the evaluation when cancelled.
+#[allow(clippy::too_many_arguments)]
+pub fn evaulate_points<T: Real>(
+    function: &Expr,
+    context: &Context,
+    variables: &HashMap<String, Complex<T>>,
     x_interval: (i32, i32),
     x_precision: i32,
     y_interval: (i32, i32),
     y_precision: i32,
-) -> Vec<(Complex<f32>, i32, i32)> {
-    let mut points = vec![];
-
-    // If no z, just evaluate algorithm
-    if !algorithm.contains(&"z".to_string()) {
-        let z = parse_complex_calculation(algorithm);
-        if z.is_some() {
-            let z = z.unwrap();
-            let re = z.re;
-            let im = z.im;
-            if !re.is_infinite()
-                && !re.is_nan()
-                && !im.is_infinite()
-                && !im.is_nan()
-            {
-                points.push((z, 1, 1));
-            }
-        }
-        return points;
-    }
-
-    // Loop intervals
-    for i in x_interval.0 * x_precision..x_interval.1 * x_precision + 1 {
-        let x = i as f32 / x_precision as f32;
-
-        for j in y_interval.0 * y_precision..y_interval.1 * y_precision + 1 {
-            let y = j as f32 / y_precision as f32;
-
-            // format complex number in reverse polish notation
-            let z = Complex::new(x, y);
-            let z = format!("{}+i{}", z.re, z.im);
-            let mut z: Vec<String> =
-                z.split("+").map(|s| s.to_string()).collect();
-            z.push("+".to_string());
-
-            // Create new algorithm with z change
-            let mut algorithm_z_change = vec![];
-            algorithm.iter().for_each(|v| {
-                if v == "z" {
-                    algorithm_z_change.append(&mut z.clone());
-                } else {
-                    algorithm_z_change.push(v.clone());
-                }
-            });
-
-            // Calculate new algorithm
-            let z = parse_complex_calculation(algorithm_z_change);
-
-            // Check if calculation exists, and add to points
-            if z.is_some() {
-                let z = z.unwrap();
-                let re = z.re;
-                let im = z.im;
-                if !re.is_infinite()
-                    && !re.is_nan()
-                    && !im.is_infinite()
-                    && !im.is_nan()
-                {
-                    points.push((z, j as i32, i as i32));
+    progress: &Progress,
+) -> Result<Vec<(Complex<T>, i32, i32)>, Error> {
+    // If no z, just evaluate function
+    if !function.inline(context.definitions)?.contains_variable("z") {
+        let z = keep_point(function.eval_with(variables, context))?;
+        return Ok(z.map(|z| (z, 1, 1)).into_iter().collect());
+    }
+
+    let function = Program::compile(function, &["z"], variables, context)?;
+    let xs = x_interval.0 * x_precision..x_interval.1 * x_precision + 1;
+    let ys = y_interval.0 * y_precision..y_interval.1 * y_precision + 1;
+    progress.start(xs.len() * ys.len());
+
+    // Loop intervals, a column per x
+    let columns = xs
+        .into_par_iter()
+        .map_init(Vec::new, |stack, i| {
+            progress.check()?;
+            let x = coordinate(i, x_precision);
+            let mut column = Vec::with_capacity(ys.len());
+
+            for j in ys.clone() {
+                let y = coordinate(j, y_precision);
+
+                // Calculate function with z change
+                let z = function.run(&[Complex::new(x, y)], stack);
+                if let Some(z) = keep_point(z)? {
+                    column.push((z, j, i));
                 }
             }
+            progress.advance(ys.len());
+            Ok(column)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(columns.concat())
+}
+
+/// Evaluates with `T` as the float type, the variables are narrowed to it
+/// and the result is widened back to double precision.
+pub fn calculate_with_vars<T: Real>(
+    expr: &Expr,
+    variables: &HashMap<String, (i32, Complex<f64>)>,
+    definitions: &Definitions,
+) -> Result<Complex<f64>, Error> {
+    let env = narrow::<T>(variables);
+    expr.eval_with(&env, &Context::new(definitions)).map(widen)
+}
+
+fn narrow<T: Real>(
+    variables: &HashMap<String, (i32, Complex<f64>)>,
+) -> HashMap<String, Complex<T>> {
+    variables
+        .iter()
+        .map(|(k, (_, z))| (k.clone(), cast(*z)))
+        .collect()
+}
+
+/// Every value of `expr` when it is a root or a rational power, like
+/// `z^(1/n)`, `z^(2/3)`, `root(z, n)`, `sqrt(z)` or `cbrt(z)`, from the
+/// principal one. Anything else has its single value.
+pub fn calculate_branches<T: Real>(
+    expr: &Expr,
+    variables: &HashMap<String, (i32, Complex<f64>)>,
+    definitions: &Definitions,
+) -> Result<Vec<Complex<f64>>, Error> {
+    let env = narrow::<T>(variables);
+    let context = Context::new(definitions);
+    let eval = |expr: &Expr| expr.eval_with::<T, _>(&env, &context);
+    let real = |x: f64| Ok(cast(Complex::new(x, 0.0)));
+
+    let (base, exponent) = match expr {
+        Expr::Binary(Operator::Pow, base, exponent, _) => {
+            (base.as_ref(), eval(exponent))
+        }
+        Expr::Call(Function::Root, args) if args.len() == 2 => {
+            (&args[0], eval(&args[1]).map(|n| n.inv()))
         }
+        Expr::Call(Function::Sqrt, args) if args.len() == 1 => {
+            (&args[0], real(0.5))
+        }
+        Expr::Call(Function::Cbrt, args) => (&args[0], real(1.0 / 3.0)),
+        _ => return Ok(vec![widen(eval(expr)?)]),
+    };
+    let z = eval(base)?;
+    let w = exponent?;
+    match BranchCut::principal().power_branches(z, w) {
+        Some(branches) => Ok(branches.into_iter().map(widen).collect()),
+        None => Ok(vec![widen(eval(expr)?)]),
     }
-    points
 }
 
-fn is_string_alphabetic(s: &String) -> bool {
-    // for imaginary numbers
-    if s == "i" {
-        return false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::{
+        parse, parse_statement, parse_with, Statement,
+    };
+
+    fn define(definitions: &mut Definitions, input: &str) {
+        match parse_statement(input, definitions).unwrap() {
+            Statement::Definition(d) => definitions.insert(d),
+            _ => panic!("{} is not a definition", input),
+        }
     }
-    for c in s.chars() {
-        if !c.is_alphabetic() {
-            return false;
+
+    #[test]
+    fn evaluates_negated_function_on_grid() {
+        let function = parse("-z^2").unwrap();
+        let points: Vec<(Complex<f64>, i32, i32)> = evaulate_points(
+            &function,
+            &Context::new(&Definitions::default()),
+            &HashMap::new(),
+            (-1, 1),
+            1,
+            (0, 1),
+            1,
+            &Progress::default(),
+        )
+        .unwrap();
+
+        assert_eq!(points.len(), 6);
+        for (z, j, i) in points {
+            let w = Complex::new(i as f64, j as f64);
+            assert!((z + w * w).norm() < 1e-12);
         }
     }
-    return true;
-}
 
-pub fn calculate_with_vars(
-    algorithm: Vec<String>,
-    variables: &HashMap<String, (i32, Complex<f32>)>,
-) -> Option<Complex<f32>> {
-    let mut algorithm_var_change = vec![];
-
-    // loop algorithm
-    algorithm.iter().for_each(|v| {
-        // if is alphabetic, a variable is present
-        if is_string_alphabetic(&v) {
-            // try to get the variable from hash table
-            let z = variables.get(v);
-
-            // if it exists, format and add to algorithm
-            if let Some((_, z)) = z {
-                let z = format!("{}+i{}", z.re, z.im);
-                let mut z: Vec<String> =
-                    z.split("+").map(|s| s.to_string()).collect();
-                z.push("+".to_string());
-                algorithm_var_change.append(&mut z.clone());
-            } else {
-                algorithm_var_change.push(v.clone());
-            }
-        } else {
-            algorithm_var_change.push(v.clone());
+    #[test]
+    fn evaluates_negated_contour() {
+        let function = parse("-z").unwrap();
+        let contour = parse("-x").unwrap();
+        let points = evaulate_points_on_contour(
+            &function,
+            &Context::new(&Definitions::default()),
+            &HashMap::new(),
+            (0, 2),
+            1,
+            &contour,
+            &Progress::default(),
+        )
+        .unwrap();
+
+        let expected: Vec<Complex<f32>> =
+            (0..3).map(|x| Complex::new(-x as f32, x as f32)).collect();
+        let found: Vec<Complex<f32>> = points.iter().map(|p| p.0).collect();
+        assert_eq!(found, expected);
+    }
+
+    /// Run with `cargo test --release -- --ignored`, timings in debug
+    /// builds mean little.
+    #[test]
+    #[ignore]
+    fn evaluates_a_million_points_in_under_a_second() {
+        let function = parse("z^2 + sin(z)/(z - 0.301i) + exp(-z)*c").unwrap();
+        let mut variables = HashMap::new();
+        variables.insert("c".to_string(), Complex::new(0.5, -1.0));
+
+        let start = std::time::Instant::now();
+        let points: Vec<(Complex<f64>, i32, i32)> = evaulate_points(
+            &function,
+            &Context::new(&Definitions::default()),
+            &variables,
+            (-1, 1),
+            500,
+            (-1, 1),
+            500,
+            &Progress::default(),
+        )
+        .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(points.len(), 1001 * 1001);
+        assert!(elapsed.as_secs_f64() < 1.0, "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn reports_progress_and_cancels() {
+        let function = parse("z^2").unwrap();
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let variables: HashMap<String, Complex<f64>> = HashMap::new();
+        let evaluate = |progress: &Progress| {
+            evaulate_points(
+                &function,
+                &context,
+                &variables,
+                (-1, 1),
+                10,
+                (-1, 1),
+                10,
+                progress,
+            )
+        };
+
+        let progress = Progress::default();
+        assert_eq!(progress.fraction(), 0.0);
+        assert_eq!(evaluate(&progress).unwrap().len(), 21 * 21);
+        assert_eq!(progress.fraction(), 1.0);
+
+        progress.cancel();
+        let error = evaluate(&progress).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Cancelled);
+    }
+
+    #[test]
+    fn calculates_in_requested_precision() {
+        let expr = parse("exp(i*PI) + 1").unwrap();
+        let variables = HashMap::new();
+        let definitions = Definitions::default();
+        let single =
+            calculate_with_vars::<f32>(&expr, &variables, &definitions)
+                .unwrap();
+        let double =
+            calculate_with_vars::<f64>(&expr, &variables, &definitions)
+                .unwrap();
+
+        assert!(double.norm() < 1e-15);
+        assert!(single.norm() > double.norm());
+    }
+
+    #[test]
+    fn skips_division_by_zero() {
+        let function = parse("1/z").unwrap();
+        let points: Vec<(Complex<f64>, i32, i32)> = evaulate_points(
+            &function,
+            &Context::new(&Definitions::default()),
+            &HashMap::new(),
+            (0, 1),
+            1,
+            (0, 0),
+            1,
+            &Progress::default(),
+        )
+        .unwrap();
+        assert_eq!(points.len(), 1);
+    }
+
+    #[test]
+    fn graphs_user_functions_with_calculator_variables() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := z^2 + c");
+        let c = Complex::new(0.5, -1.0);
+        let mut variables = HashMap::new();
+        variables.insert("c".to_string(), c);
+
+        let function = parse_with("f(z)", &definitions).unwrap();
+        let points: Vec<(Complex<f64>, i32, i32)> = evaulate_points(
+            &function,
+            &Context::new(&definitions),
+            &variables,
+            (0, 1),
+            1,
+            (0, 1),
+            1,
+            &Progress::default(),
+        )
+        .unwrap();
+
+        assert_eq!(points.len(), 4);
+        for (w, j, i) in points {
+            let z = Complex::new(i as f64, j as f64);
+            assert!((w - (z * z + c)).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn calculates_user_functions() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := z^2 + c");
+        define(&mut definitions, "g(z, w) := f(z) * w");
+        let mut variables = HashMap::new();
+        variables.insert("c".to_string(), (0, Complex::new(1.0, 0.0)));
+
+        let expr = parse_with("g(i, 2) + f(2)", &definitions).unwrap();
+        let z = calculate_with_vars::<f64>(&expr, &variables, &definitions)
+            .unwrap();
+        assert!((z - Complex::new(5.0, 0.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn lists_dependencies_through_user_functions() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := z^2 + c");
+        define(&mut definitions, "g(w) := f(w) * g(w - 1) + b");
+        let expr = parse_with("g(a) + sin(z) + PI", &definitions).unwrap();
+        let mut names: Vec<String> =
+            expr.dependencies(&definitions).into_iter().collect();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c", "f", "g", "z"]);
+    }
+
+    #[test]
+    fn calculates_every_branch() {
+        let variables = HashMap::new();
+        let definitions = Definitions::default();
+        let branches = |input| {
+            let expr = parse(input).unwrap();
+            calculate_branches::<f64>(&expr, &variables, &definitions).unwrap()
+        };
+
+        let roots = branches("(-16)^(1/4)");
+        assert_eq!(roots.len(), 4);
+        for root in roots.iter() {
+            assert!((root.powi(4) + 16.0).norm() < 1e-12);
         }
-    });
-    // parse new algorithm and return it
-    let z = parse_complex_calculation(algorithm_var_change);
-    z
+        assert_eq!(branches("root(i, 5)").len(), 5);
+        assert_eq!(branches("sqrt(2i)").len(), 2);
+        assert_eq!(branches("cbrt(2i)").len(), 3);
+        assert_eq!(branches("i^2").len(), 1);
+        assert_eq!(branches("2^PI").len(), 1);
+        assert_eq!(branches("sqrt(4, 1)").len(), 1);
+        assert_eq!(branches("1 + 2^0.5").len(), 1);
+    }
+
+    #[test]
+    fn limits_recursion_depth() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := f(z + 1)");
+
+        let expr = parse_with("f(0)", &definitions).unwrap();
+        let error =
+            calculate_with_vars::<f64>(&expr, &HashMap::new(), &definitions)
+                .unwrap_err();
+        assert_eq!(error.kind, ErrorKind::RecursionLimit("f".to_string()));
+    }
 }
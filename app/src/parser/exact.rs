@@ -0,0 +1,360 @@
+use crate::parser::ast::{negate, not_inlined, Expr, Operator};
+use crate::parser::branches::BranchCut;
+use crate::parser::definitions::Definitions;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+use num_bigint::BigInt;
+use num_complex::Complex;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// A complex number with rational parts, exact under `+ - * /`.
+pub type Gaussian = Complex<BigRational>;
+
+/// Most bits the numerators and denominators of an exact power may take,
+/// estimated before computing it. Larger powers are left to floating point,
+/// so that repeated powers like `((3/7)^4096)^64` stay quick.
+const MAX_EXACT_BITS: u64 = 1 << 16;
+
+/// Decimal exponents a literal may have beyond its number of digits, about
+/// the range of a double. Further ones are left to floating point.
+const MAX_DECIMAL_EXPONENT: usize = 330;
+
+/// The value of an expression evaluated exactly, or in double precision
+/// once it needed a function without exact values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Exact(Gaussian),
+    Approximate(Complex<f64>),
+}
+
+impl Value {
+    pub fn to_f64(&self) -> Complex<f64> {
+        match self {
+            Value::Exact(z) => Complex::new(to_f64(&z.re), to_f64(&z.im)),
+            Value::Approximate(z) => *z,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Value::Exact(z) => z.is_zero(),
+            Value::Approximate(z) => z.is_zero(),
+        }
+    }
+
+    fn exact(&self) -> Option<&Gaussian> {
+        match self {
+            Value::Exact(z) => Some(z),
+            Value::Approximate(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Exact(z) => write!(f, "{}", format(z)),
+            Value::Approximate(z) => write!(f, "{}", z),
+        }
+    }
+}
+
+/// A double read as the shortest decimal that rounds to it, so `0.1` is
+/// `1/10`. Infinities and NaN have no exact value.
+pub fn from_f64(z: Complex<f64>) -> Option<Gaussian> {
+    let part = |x: f64| {
+        if x.is_finite() {
+            decimal(&format!("{:e}", x))
+        } else {
+            None
+        }
+    };
+    Some(Complex::new(part(z.re)?, part(z.im)?))
+}
+
+/// The value of a literal as it is written, like `0.12345678901234567890`
+/// or `2.5e-30i`.
+pub fn from_literal(text: &str) -> Option<Gaussian> {
+    match text.strip_suffix('i') {
+        Some(text) => Some(Complex::new(BigRational::zero(), decimal(text)?)),
+        None => Some(Complex::new(decimal(text)?, BigRational::zero())),
+    }
+}
+
+/// The rational a decimal like `12.5`, `.5` or `6.02E23` stands for, or
+/// `None` if its exponent is out of the range of [`MAX_DECIMAL_EXPONENT`].
+fn decimal(text: &str) -> Option<BigRational> {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(e) => (&text[..e], text[e + 1..].parse::<i32>().ok()?),
+        None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits: BigInt = format!("{}{}", whole, fraction).parse().ok()?;
+    let exponent = exponent.checked_sub(i32::try_from(fraction.len()).ok()?)?;
+    let length = whole.len() + fraction.len();
+    if exponent.unsigned_abs() as usize > MAX_DECIMAL_EXPONENT + length {
+        return None;
+    }
+    let ten = BigInt::from(10);
+    Some(if exponent >= 0 {
+        BigRational::from_integer(digits * ten.pow(exponent as u32))
+    } else {
+        BigRational::new(digits, ten.pow(-exponent as u32))
+    })
+}
+
+fn to_f64(x: &BigRational) -> f64 {
+    x.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Formats `z` like `-3 + 4i` or `1/2 - (3/4)i`, leaving out zero parts.
+pub fn format(z: &Gaussian) -> String {
+    let imaginary = |y: &BigRational| {
+        if y.is_one() {
+            "i".to_string()
+        } else if y.is_integer() {
+            format!("{}i", y)
+        } else {
+            format!("({})i", y)
+        }
+    };
+    if z.im.is_zero() {
+        z.re.to_string()
+    } else if z.re.is_zero() && z.im.is_negative() {
+        format!("-{}", imaginary(&-&z.im))
+    } else if z.re.is_zero() {
+        imaginary(&z.im)
+    } else if z.im.is_negative() {
+        format!("{} - {}", z.re, imaginary(&-&z.im))
+    } else {
+        format!("{} + {}", z.re, imaginary(&z.im))
+    }
+}
+
+/// Evaluates `expr` exactly with user functions of `definitions` and the
+/// values of `variables`.
+///
+/// Literals are read as the decimals typed, and stay exact under the
+/// operators, integer powers and the component and rounding functions.
+/// Anything else, like `sin` or `PI`, is evaluated in double precision on
+/// the principal branch, and so is every operation using its value.
+pub fn eval(
+    expr: &Expr,
+    variables: &HashMap<String, Value>,
+    definitions: &Definitions,
+) -> Result<Value, Error> {
+    evaluate(&expr.inline(definitions)?, variables)
+}
+
+fn evaluate(
+    expr: &Expr,
+    variables: &HashMap<String, Value>,
+) -> Result<Value, Error> {
+    let eval = |expr: &Expr| evaluate(expr, variables);
+    let cut = BranchCut::principal();
+    match expr {
+        Expr::Number(z) => {
+            Ok(from_f64(*z).map_or(Value::Approximate(*z), Value::Exact))
+        }
+        Expr::Decimal(z, text) => {
+            Ok(from_literal(text).map_or(Value::Approximate(*z), Value::Exact))
+        }
+        Expr::Constant(c) => Ok(Value::Approximate(c.value())),
+        Expr::Variable(name, span) => {
+            variables.get(name).cloned().ok_or_else(|| {
+                Error::new(ErrorKind::UnknownIdentifier(name.clone()), *span)
+            })
+        }
+        Expr::Neg(expr) => Ok(match eval(expr)? {
+            Value::Exact(z) => Value::Exact(-z),
+            Value::Approximate(z) => Value::Approximate(negate(z)),
+        }),
+        Expr::Binary(op, left, right, span) => {
+            let z1 = eval(left)?;
+            let z2 = eval(right)?;
+            if *op == Operator::Div && z2.is_zero() {
+                return Err(Error::new(ErrorKind::DivisionByZero, *span));
+            }
+            let exact = match (&z1, &z2) {
+                (Value::Exact(z1), Value::Exact(z2)) => operator(*op, z1, z2),
+                _ => None,
+            };
+            Ok(exact.map_or_else(
+                || Value::Approximate(op.apply(z1.to_f64(), z2.to_f64(), cut)),
+                Value::Exact,
+            ))
+        }
+        Expr::Call(f, args) => {
+            f.check_scalar()?;
+            let args =
+                args.iter().map(eval).collect::<Result<Vec<_>, Error>>()?;
+            let exact = args
+                .iter()
+                .map(Value::exact)
+                .collect::<Option<Vec<_>>>()
+                .and_then(|args| function(*f, &args));
+            Ok(exact.map_or_else(
+                || {
+                    let args: Vec<_> = args.iter().map(Value::to_f64).collect();
+                    Value::Approximate(f.apply(&args, cut))
+                },
+                Value::Exact,
+            ))
+        }
+        Expr::Matrix(_, span) => Err(Error::new(ErrorKind::NotScalar, *span)),
+        Expr::UserCall(name, _, span) => Err(not_inlined(name, *span)),
+    }
+}
+
+/// `z1 op z2` when it is exact, the divisor is not zero.
+fn operator(op: Operator, z1: &Gaussian, z2: &Gaussian) -> Option<Gaussian> {
+    match op {
+        Operator::Add => Some(z1 + z2),
+        Operator::Sub => Some(z1 - z2),
+        Operator::Mul => Some(z1 * z2),
+        Operator::Div => Some(z1 / z2),
+        Operator::Pow => power(z1, z2),
+    }
+}
+
+/// `z^w` for an integer `w` whose result is not larger than
+/// [`MAX_EXACT_BITS`], zero has no negative powers.
+fn power(z: &Gaussian, w: &Gaussian) -> Option<Gaussian> {
+    if !w.im.is_zero() || !w.re.is_integer() {
+        return None;
+    }
+    let n = w.re.to_integer().to_i32()?;
+    if n < 0 && z.is_zero() {
+        return None;
+    }
+    // The parts of z^n have about n times the bits of those of z
+    let bits = |x: &BigRational| x.numer().bits() + x.denom().bits();
+    let size = (bits(&z.re) + bits(&z.im)).max(1);
+    if size.saturating_mul(n.unsigned_abs() as u64) > MAX_EXACT_BITS {
+        return None;
+    }
+    Some(z.powi(n))
+}
+
+/// The functions with exact values, like [`Function::apply`] computes them.
+fn function(f: Function, args: &[&Gaussian]) -> Option<Gaussian> {
+    let z = args[0];
+    let real = |x: BigRational| Complex::new(x, BigRational::zero());
+    let floor = |z: &Gaussian| Complex::new(z.re.floor(), z.im.floor());
+    match f {
+        Function::Inv if !z.is_zero() => Some(z.inv()),
+        Function::Conj => Some(z.conj()),
+        Function::Re => Some(real(z.re.clone())),
+        Function::Im => Some(real(z.im.clone())),
+        Function::Abs if z.im.is_zero() => Some(real(z.re.abs())),
+        Function::Norm => Some(real(z.norm_sqr())),
+        Function::Floor => Some(floor(z)),
+        Function::Ceil => Some(Complex::new(z.re.ceil(), z.im.ceil())),
+        // Halves are rounded away from zero, as `f64::round` does
+        Function::Round => Some(Complex::new(z.re.round(), z.im.round())),
+        Function::Frac => Some(z - floor(z)),
+        Function::Pow => power(z, args[1]),
+        Function::Min | Function::Max => {
+            Some((*f.extremum(args, |w| &w.re)).clone())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::parse;
+
+    fn exact(input: &str) -> Value {
+        let mut variables = HashMap::new();
+        variables.insert(
+            "a".to_string(),
+            Value::Exact(from_f64(Complex::new(0.5, -2.0)).unwrap()),
+        );
+        variables.insert(
+            "b".to_string(),
+            Value::Approximate(Complex::new(0.1, 0.0)),
+        );
+        eval(&parse(input).unwrap(), &variables, &Definitions::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn keeps_rationals_exact() {
+        for (input, expected) in [
+            ("(2i+1)^2", "-3 + 4i"),
+            ("0.1 + 0.2", "3/10"),
+            ("1/3 + 1/6", "1/2"),
+            ("(1 + i)/(1 - i)", "i"),
+            ("2^-3 - i/4", "1/8 - (1/4)i"),
+            ("(3 - 4i)^-1", "3/25 + (4/25)i"),
+            ("-i^3 * 2i", "-2"),
+            ("a^2", "-15/4 - 2i"),
+            ("conj(a) + norm(a) + re(a)", "21/4 + 2i"),
+            ("round(2.5 - 2.5i) + floor(-1/3) + frac(-0.25)", "11/4 - 3i"),
+            ("2^100", "1267650600228229401496703205376"),
+            ("max(1/3, 0.3, 2/5) - min(1/3, 0.3)", "1/10"),
+            (
+                "0.12345678901234567890",
+                "1234567890123456789/10000000000000000000",
+            ),
+            (
+                "1.000_000_000_000_000_000_1i - i",
+                "(1/10000000000000000000)i",
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(exact(input).to_string(), *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_double_precision() {
+        for input in
+            ["sin(1) + 1/3", "2^0.5", "b + 1", "PI * 2", "(1+i)^i"].iter()
+        {
+            let expr = parse(input).unwrap();
+            let mut variables = HashMap::new();
+            variables.insert("b".to_string(), Complex::new(0.1, 0.0));
+            let expected = expr.eval(&variables).unwrap();
+            match exact(input) {
+                Value::Approximate(z) => {
+                    assert!((z - expected).norm() < 1e-15, "{}", input)
+                }
+                Value::Exact(z) => panic!("{} is exact: {}", input, format(&z)),
+            }
+        }
+        assert!(exact("2^5000").to_f64().re.is_infinite());
+        // Powers stay exact up to a size of their result
+        assert!(matches!(exact("(3/7)^4096"), Value::Exact(_)));
+        assert!(matches!(exact("((3/7)^4096)^64"), Value::Approximate(_)));
+        assert!(matches!(exact("2^100000"), Value::Approximate(_)));
+        // And literals up to an exponent past the range of doubles
+        for input in [
+            "1.00000000000000000000001e-2147483648",
+            "1.00000000000000000000001e999999999",
+        ]
+        .iter()
+        {
+            assert!(from_literal(input).is_none());
+            assert!(matches!(exact(input), Value::Approximate(_)), "{}", input);
+        }
+        assert!(from_literal("1.00000000000000000000001e-300").is_some());
+    }
+
+    #[test]
+    fn reports_division_by_zero() {
+        let error = eval(
+            &parse("1/(i^2 + 1)").unwrap(),
+            &HashMap::new(),
+            &Definitions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind, ErrorKind::DivisionByZero);
+    }
+}
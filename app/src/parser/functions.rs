@@ -0,0 +1,411 @@
+use crate::parser::ast::Real;
+use crate::parser::branches::BranchCut;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::lexer::Span;
+use crate::parser::special;
+use num_complex::Complex;
+use num_traits::Zero;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Csc,
+    Sec,
+    Cot,
+    Asin,
+    Acos,
+    Atan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Asinh,
+    Acosh,
+    Atanh,
+    Inv,
+    Conj,
+    Exp,
+    Cis,
+    Ln,
+    Sqrt,
+    Cbrt,
+    Re,
+    Im,
+    Abs,
+    Arg,
+    Norm,
+    Sgn,
+    Floor,
+    Ceil,
+    Round,
+    Frac,
+    Log,
+    Pow,
+    Root,
+    Atan2,
+    Min,
+    Max,
+    Gamma,
+    Lgamma,
+    Zeta,
+    Erf,
+    Erfc,
+    Besselj,
+    Lambertw,
+    Det,
+    Trace,
+    Transpose,
+    Eig,
+    Mobius,
+}
+
+/// How many arguments a function takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    Between(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exactly(m) => n == m,
+            Arity::Between(min, max) => n >= min && n <= max,
+            Arity::AtLeast(m) => n >= m,
+        }
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (prefix, n) = match *self {
+            Arity::Exactly(n) => (String::new(), n),
+            Arity::Between(min, max) if max == min + 1 => {
+                (format!("{} or ", min), max)
+            }
+            Arity::Between(min, max) => (format!("{} to ", min), max),
+            Arity::AtLeast(n) => ("at least ".to_string(), n),
+        };
+        let plural = if n == 1 { "" } else { "s" };
+        write!(f, "{}{} argument{}", prefix, n, plural)
+    }
+}
+
+impl Function {
+    pub const ALL: [Function; 50] = [
+        Function::Sin,
+        Function::Cos,
+        Function::Tan,
+        Function::Csc,
+        Function::Sec,
+        Function::Cot,
+        Function::Asin,
+        Function::Acos,
+        Function::Atan,
+        Function::Sinh,
+        Function::Cosh,
+        Function::Tanh,
+        Function::Asinh,
+        Function::Acosh,
+        Function::Atanh,
+        Function::Inv,
+        Function::Conj,
+        Function::Exp,
+        Function::Cis,
+        Function::Ln,
+        Function::Sqrt,
+        Function::Cbrt,
+        Function::Re,
+        Function::Im,
+        Function::Abs,
+        Function::Arg,
+        Function::Norm,
+        Function::Sgn,
+        Function::Floor,
+        Function::Ceil,
+        Function::Round,
+        Function::Frac,
+        Function::Log,
+        Function::Pow,
+        Function::Root,
+        Function::Atan2,
+        Function::Min,
+        Function::Max,
+        Function::Gamma,
+        Function::Lgamma,
+        Function::Zeta,
+        Function::Erf,
+        Function::Erfc,
+        Function::Besselj,
+        Function::Lambertw,
+        Function::Det,
+        Function::Trace,
+        Function::Transpose,
+        Function::Eig,
+        Function::Mobius,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Function> {
+        Function::ALL.iter().copied().find(|f| f.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Tan => "tan",
+            Function::Csc => "csc",
+            Function::Sec => "sec",
+            Function::Cot => "cot",
+            Function::Asin => "asin",
+            Function::Acos => "acos",
+            Function::Atan => "atan",
+            Function::Sinh => "sinh",
+            Function::Cosh => "cosh",
+            Function::Tanh => "tanh",
+            Function::Asinh => "asinh",
+            Function::Acosh => "acosh",
+            Function::Atanh => "atanh",
+            Function::Inv => "inv",
+            Function::Conj => "conj",
+            Function::Exp => "exp",
+            Function::Cis => "cis",
+            Function::Ln => "ln",
+            Function::Sqrt => "sqrt",
+            Function::Cbrt => "cbrt",
+            Function::Re => "re",
+            Function::Im => "im",
+            Function::Abs => "abs",
+            Function::Arg => "arg",
+            Function::Norm => "norm",
+            Function::Sgn => "sgn",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Round => "round",
+            Function::Frac => "frac",
+            Function::Log => "log",
+            Function::Pow => "pow",
+            Function::Root => "root",
+            Function::Atan2 => "atan2",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Gamma => "gamma",
+            Function::Lgamma => "lgamma",
+            Function::Zeta => "zeta",
+            Function::Erf => "erf",
+            Function::Erfc => "erfc",
+            Function::Besselj => "besselj",
+            Function::Lambertw => "lambertw",
+            Function::Det => "det",
+            Function::Trace => "trace",
+            Function::Transpose => "transpose",
+            Function::Eig => "eig",
+            Function::Mobius => "mobius",
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        match self {
+            Function::Ln | Function::Sqrt | Function::Lambertw => {
+                Arity::Between(1, 2)
+            }
+            Function::Root => Arity::Between(2, 3),
+            Function::Log
+            | Function::Pow
+            | Function::Atan2
+            | Function::Besselj
+            | Function::Mobius => Arity::Exactly(2),
+            Function::Min | Function::Max => Arity::AtLeast(1),
+            _ => Arity::Exactly(1),
+        }
+    }
+
+    /// Fails for the functions with no value on numbers alone, `mobius`
+    /// which needs a matrix. The evaluators of numbers call it before
+    /// [`Function::apply`].
+    pub fn check_scalar(&self) -> Result<(), Error> {
+        match self {
+            Function::Mobius => Err(Error::new(
+                ErrorKind::WrongShape(
+                    self.name().to_string(),
+                    "a 2x2 matrix and a number".to_string(),
+                ),
+                Span::default(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The argument `min` picks, with the smallest real part given by
+    /// `re`, or `max`, with the largest one, the first of them on ties.
+    /// The evaluators share it for their number types.
+    pub fn extremum<'a, Z, R: PartialOrd>(
+        &self,
+        args: &'a [Z],
+        re: impl Fn(&Z) -> &R,
+    ) -> &'a Z {
+        args.iter().skip(1).fold(&args[0], |pick, w| {
+            let better = match self {
+                Function::Max => re(w) > re(pick),
+                _ => re(w) < re(pick),
+            };
+            if better {
+                w
+            } else {
+                pick
+            }
+        })
+    }
+
+    /// Applies the function to `args`, whose length the parser has already
+    /// checked against [`Function::arity`], with the multivalued ones cut
+    /// at `cut`.
+    ///
+    /// `ln(z, k)`, `sqrt(z, k)` and `root(z, n, k)` take the `k`th branch,
+    /// rounding `k` to an integer, and the principal one without it.
+    ///
+    /// `re`, `im`, `abs`, `arg` and `norm` (the squared modulus) are real
+    /// valued. `sgn` is `z/|z|`, or zero at zero. `floor`, `ceil`, `round`
+    /// and `frac` act on both parts, with `frac(z) = z - floor(z)`.
+    /// `cis(θ)` is `e^(iθ)`, the point at angle `θ` on the unit circle.
+    ///
+    /// `log(z, b)` is the logarithm of `z` in base `b` and `root(z, n)` the
+    /// principal `n`th root. `atan2(y, x)` is the angle of the point
+    /// `(x, y)` and `min`/`max` pick the argument with the smallest or
+    /// largest real part, all three only look at real parts.
+    ///
+    /// The special functions are in [`special`], `besselj(n, z)` is of
+    /// integer order `n` and `lambertw(z, k)` takes the `k`th branch.
+    ///
+    /// The matrix functions, computed by [`matrix`](crate::parser::matrix),
+    /// take a number as a 1x1 matrix. `mobius(M, z)` needs a 2x2 matrix, it
+    /// has no value here, see [`Function::check_scalar`].
+    pub fn apply<T: Real>(
+        &self,
+        args: &[Complex<T>],
+        cut: BranchCut<T>,
+    ) -> Complex<T> {
+        let z = args[0];
+        let branch = |i: usize| args.get(i).map_or(T::zero(), |k| k.re.round());
+        match self {
+            Function::Sin => z.sin(),
+            Function::Cos => z.cos(),
+            Function::Tan => z.tan(),
+            Function::Csc => z.sin().inv(),
+            Function::Sec => z.cos().inv(),
+            Function::Cot => z.tan().inv(),
+            Function::Asin => cut.asin(z),
+            Function::Acos => cut.acos(z),
+            Function::Atan => cut.atan(z),
+            Function::Sinh => z.sinh(),
+            Function::Cosh => z.cosh(),
+            Function::Tanh => z.tanh(),
+            Function::Asinh => cut.asinh(z),
+            Function::Acosh => cut.acosh(z),
+            Function::Atanh => cut.atanh(z),
+            Function::Inv => z.inv(),
+            Function::Conj => z.conj(),
+            Function::Exp => z.exp(),
+            Function::Cis => Complex::new(-z.im, z.re).exp(),
+            Function::Ln => cut.ln(z, branch(1)),
+            Function::Sqrt => cut.sqrt(z, branch(1)),
+            Function::Cbrt => cut.cbrt(z, T::zero()),
+            Function::Re => Complex::new(z.re, T::zero()),
+            Function::Im => Complex::new(z.im, T::zero()),
+            Function::Abs => Complex::new(z.norm(), T::zero()),
+            Function::Arg => Complex::new(z.arg(), T::zero()),
+            Function::Norm => Complex::new(z.norm_sqr(), T::zero()),
+            Function::Sgn if z.is_zero() => z,
+            Function::Sgn => z.unscale(z.norm()),
+            Function::Floor => component_wise(z, T::floor),
+            Function::Ceil => component_wise(z, T::ceil),
+            Function::Round => component_wise(z, T::round),
+            Function::Frac => z - component_wise(z, T::floor),
+            Function::Log => cut.ln(z, T::zero()) / cut.ln(args[1], T::zero()),
+            Function::Pow => cut.pow(z, args[1], T::zero()),
+            Function::Root => cut.pow(z, args[1].inv(), branch(2)),
+            Function::Atan2 => Complex::new(z.re.atan2(args[1].re), T::zero()),
+            Function::Min | Function::Max => *self.extremum(args, |w| &w.re),
+            Function::Gamma => special::gamma(z),
+            Function::Lgamma => special::lgamma(z),
+            Function::Zeta => special::zeta(z),
+            Function::Erf => special::erf(z),
+            Function::Erfc => special::erfc(z),
+            Function::Besselj => special::besselj(z, args[1]),
+            Function::Lambertw => {
+                special::lambertw(z, Complex::new(branch(1), T::zero()))
+            }
+            Function::Det
+            | Function::Trace
+            | Function::Transpose
+            | Function::Eig => z,
+            Function::Mobius => Complex::new(T::nan(), T::nan()),
+        }
+    }
+}
+
+fn component_wise<T: Real>(z: Complex<T>, f: impl Fn(T) -> T) -> Complex<T> {
+    Complex::new(f(z.re), f(z.im))
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [Complex<f64>; 5] = [
+        Complex::new(2.5, -1.25),
+        Complex::new(-3.75, 0.5),
+        Complex::new(0.0, -2.0),
+        Complex::new(-1.5, -0.5),
+        Complex::new(0.0, 0.0),
+    ];
+
+    fn apply(f: Function, z: Complex<f64>) -> Complex<f64> {
+        f.apply(&[z], BranchCut::principal())
+    }
+
+    fn real(x: f64) -> Complex<f64> {
+        Complex::new(x, 0.0)
+    }
+
+    #[test]
+    fn extracts_components() {
+        for z in SAMPLES.iter().copied() {
+            assert_eq!(apply(Function::Re, z), real(z.re));
+            assert_eq!(apply(Function::Im, z), real(z.im));
+            assert_eq!(apply(Function::Abs, z), real(z.norm()));
+            assert_eq!(apply(Function::Arg, z), real(z.arg()));
+            assert_eq!(apply(Function::Norm, z), real(z.norm_sqr()));
+        }
+    }
+
+    #[test]
+    fn computes_sign() {
+        for z in SAMPLES.iter().copied().filter(|z| !z.is_zero()) {
+            let sgn = apply(Function::Sgn, z);
+            assert!((sgn - Complex::from_polar(1.0, z.arg())).norm() < 1e-15);
+        }
+        assert_eq!(apply(Function::Sgn, Complex::zero()), Complex::zero());
+    }
+
+    #[test]
+    fn rounds_component_wise() {
+        let z = Complex::new(2.5, -1.25);
+        assert_eq!(apply(Function::Floor, z), Complex::new(2.0, -2.0));
+        assert_eq!(apply(Function::Ceil, z), Complex::new(3.0, -1.0));
+        assert_eq!(apply(Function::Round, z), Complex::new(3.0, -1.0));
+        assert_eq!(apply(Function::Frac, z), Complex::new(0.5, 0.75));
+        for z in SAMPLES.iter().copied() {
+            let floor = apply(Function::Floor, z);
+            assert_eq!(floor + apply(Function::Frac, z), z);
+        }
+    }
+}
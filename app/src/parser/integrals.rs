@@ -0,0 +1,277 @@
+use crate::parser::ast::{Context, Expr};
+use crate::parser::derivatives::derivative;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+use num_traits::Zero;
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+
+/// The error an integral is computed to, absolute or relative to its value,
+/// whichever is larger.
+const TOLERANCE: f64 = 1e-10;
+
+/// Subintervals an integral may be split into before its estimate is
+/// returned as it is.
+const MAX_INTERVALS: usize = 2000;
+
+/// Nodes of the 15 point Kronrod rule on `[-1, 1]`, from the outside in.
+/// Those at odd indices and the center are the nodes of the 7 point Gauss
+/// rule.
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_5,
+    0.0,
+];
+
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22,
+    0.063_092_092_629_978_55,
+    0.104_790_010_322_250_2,
+    0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_4,
+    0.204_432_940_075_298_9,
+    0.209_482_141_084_727_8,
+];
+
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_7,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+
+/// A path to integrate along.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Contour {
+    /// The circle with a center and a radius, counterclockwise.
+    Circle(Complex<f64>, f64),
+    /// The segments between consecutive points, a single segment with two
+    /// of them.
+    Polyline(Vec<Complex<f64>>),
+    /// `γ(t)`, an expression in `t`, from one value of `t` to another.
+    Parametric(Expr, f64, f64),
+}
+
+/// The value of an integral and an estimate of its error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Integral {
+    pub value: Complex<f64>,
+    pub error: f64,
+}
+
+/// The 15 point Kronrod estimate of the integral of `g` over `[a, b]`,
+/// with its difference to the 7 point Gauss estimate as the error.
+fn kronrod(
+    g: &dyn Fn(f64) -> Complex<f64>,
+    a: f64,
+    b: f64,
+) -> (Complex<f64>, f64) {
+    let center = (a + b) / 2.0;
+    let half = (b - a) / 2.0;
+    let middle = g(center);
+    let mut kronrod = middle * KRONROD_WEIGHTS[7];
+    let mut gauss = middle * GAUSS_WEIGHTS[3];
+    for j in 0..7 {
+        let x = half * KRONROD_NODES[j];
+        let sum = g(center - x) + g(center + x);
+        kronrod += sum * KRONROD_WEIGHTS[j];
+        if j % 2 == 1 {
+            gauss += sum * GAUSS_WEIGHTS[j / 2];
+        }
+    }
+    (kronrod * half, ((kronrod - gauss) * half).norm())
+}
+
+/// The integral of `g` over `[a, b]` by adaptive Gauss-Kronrod quadrature.
+///
+/// The subinterval with the largest error is halved until the errors add
+/// up to less than [`TOLERANCE`], or [`MAX_INTERVALS`] are used and the
+/// estimate is returned as it is. A value that is not finite, or an error
+/// that stays in a subinterval too short to halve, means a pole of the
+/// integrand and is an error.
+pub fn integrate(
+    g: &dyn Fn(f64) -> Complex<f64>,
+    a: f64,
+    b: f64,
+) -> Result<Integral, Error> {
+    let on_contour = || Error::new(ErrorKind::OnContour, Span::default());
+    // The rule has no nodes at the ends
+    if !g(a).is_finite() || !g(b).is_finite() {
+        return Err(on_contour());
+    }
+    let shortest = 1e-12 * (b - a).abs();
+    let mut intervals = vec![];
+    let (value, error) = kronrod(g, a, b);
+    intervals.push((a, b, value, error));
+    loop {
+        let value: Complex<f64> = intervals.iter().map(|i| i.2).sum();
+        let error: f64 = intervals.iter().map(|i| i.3).sum();
+        if !value.is_finite() || !error.is_finite() {
+            return Err(on_contour());
+        }
+        if error <= TOLERANCE * value.norm().max(1.0)
+            || intervals.len() >= MAX_INTERVALS
+        {
+            return Ok(Integral { value, error });
+        }
+        let worst = (0..intervals.len())
+            .max_by(|&i, &j| {
+                intervals[i]
+                    .3
+                    .partial_cmp(&intervals[j].3)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(0);
+        let (a, b, _, _) = intervals.swap_remove(worst);
+        if (b - a).abs() < shortest {
+            return Err(on_contour());
+        }
+        let middle = (a + b) / 2.0;
+        let (left, left_error) = kronrod(g, a, middle);
+        let (right, right_error) = kronrod(g, middle, b);
+        intervals.push((a, middle, left, left_error));
+        intervals.push((middle, b, right, right_error));
+    }
+}
+
+/// `∮ f(z) dz` along `contour`, the integral of `f(γ(t)) γ'(t)` over the
+/// values of `t`. The variables and user functions of a parametric contour
+/// come from `variables` and `context`, its derivative is the symbolic one
+/// when there is one and a central difference otherwise.
+pub fn contour_integral(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    contour: &Contour,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+) -> Result<Integral, Error> {
+    match contour {
+        Contour::Circle(center, radius) => integrate(
+            &|t| {
+                let turn = Complex::from_polar(*radius, t);
+                f(*center + turn) * Complex::i() * turn
+            },
+            0.0,
+            TAU,
+        ),
+        Contour::Polyline(points) => {
+            let mut total = Integral {
+                value: Complex::zero(),
+                error: 0.0,
+            };
+            for segment in points.windows(2) {
+                let (start, end) = (segment[0], segment[1]);
+                let integral = integrate(
+                    &|t| f(start + (end - start) * t) * (end - start),
+                    0.0,
+                    1.0,
+                )?;
+                total.value += integral.value;
+                total.error += integral.error;
+            }
+            Ok(total)
+        }
+        Contour::Parametric(path, start, end) => {
+            let t = Complex::new(*start, 0.0);
+            let gamma = path.function_of("t", variables, context, t)?;
+            let derivative = derivative(path, "t", context.definitions).ok();
+            let gamma_prime = match &derivative {
+                Some(d) => Some(d.function_of("t", variables, context, t)?),
+                None => None,
+            };
+            let h = 1e-6 * (end - start).abs();
+            integrate(
+                &|t| {
+                    let z = Complex::new(t, 0.0);
+                    let dz = match &gamma_prime {
+                        Some(gamma_prime) => gamma_prime(z),
+                        None => (gamma(z + h) - gamma(z - h)) / (2.0 * h),
+                    };
+                    f(gamma(z)) * dz
+                },
+                *start,
+                *end,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::definitions::Definitions;
+    use crate::parser::symbolic::parse;
+
+    fn integral(input: &str, contour: &Contour) -> Result<Integral, Error> {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let variables = HashMap::new();
+        let expr = parse(input).unwrap();
+        let f = expr.function_of("z", &variables, &context, Complex::zero())?;
+        contour_integral(&f, contour, &variables, &context)
+    }
+
+    fn assert_integral(input: &str, contour: &Contour, expected: Complex<f64>) {
+        let integral = integral(input, contour).unwrap();
+        assert!(
+            (integral.value - expected).norm() < 1e-9 && integral.error < 1e-8,
+            "{}: {:?}",
+            input,
+            integral
+        );
+    }
+
+    #[test]
+    fn integrates_along_contours() {
+        let unit = Contour::Circle(Complex::zero(), 1.0);
+        let two_pi_i = Complex::new(0.0, TAU);
+        assert_integral("1/z", &unit, two_pi_i);
+        assert_integral("z^2 + 3", &unit, Complex::zero());
+        assert_integral("exp(z)/z^3", &unit, two_pi_i / 2.0);
+        assert_integral(
+            "1/(z - 0.5)",
+            &Contour::Circle(Complex::new(0.5, 0.0), 0.01),
+            two_pi_i,
+        );
+        // Along a path the integral of a derivative is the difference
+        let square = Contour::Polyline(vec![
+            Complex::new(1.0, -1.0),
+            Complex::new(1.0, 1.0),
+            Complex::new(-1.0, 1.0),
+            Complex::new(-1.0, -1.0),
+            Complex::new(1.0, -1.0),
+        ]);
+        assert_integral("1/z", &square, two_pi_i);
+        let segment =
+            Contour::Polyline(vec![Complex::zero(), Complex::new(1.0, 1.0)]);
+        assert_integral("2z", &segment, Complex::new(0.0, 2.0));
+        assert_integral("cos(z)", &segment, Complex::new(1.0, 1.0).sin());
+    }
+
+    #[test]
+    fn integrates_along_parametric_contours() {
+        let ellipse =
+            Contour::Parametric(parse("2cos(t) + i sin(t)").unwrap(), 0.0, TAU);
+        assert_integral("1/z", &ellipse, Complex::new(0.0, TAU));
+        // Without a symbolic derivative of the path
+        let path =
+            Contour::Parametric(parse("t + i re(t)^2").unwrap(), -1.0, 1.0);
+        assert_integral("z", &path, Complex::new(0.0, 2.0));
+        assert_integral("z^2", &path, Complex::new(-4.0 / 3.0, 0.0));
+    }
+
+    #[test]
+    fn reports_poles_on_contours() {
+        let unit = Contour::Circle(Complex::zero(), 1.0);
+        let kind = |input| integral(input, &unit).unwrap_err().kind;
+        assert_eq!(kind("1/(z - 1)"), ErrorKind::OnContour);
+        assert_eq!(kind("1/(z - cis(1))"), ErrorKind::OnContour);
+        assert_eq!(kind("w/z"), ErrorKind::UnknownIdentifier("w".to_string()));
+    }
+}
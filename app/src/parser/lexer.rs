@@ -0,0 +1,401 @@
+use crate::parser::ast::Operator;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+
+/// Byte range of a token in the input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// An angle unit written after a value, like `45°`, `45deg` or `1rad`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Degree,
+    Radian,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Imaginary(f64),
+    Identifier(String),
+    Function(Function),
+    UserFunction(String),
+    Operator(Operator),
+    LeftParen,
+    RightParen,
+    /// `[` and `]`, around the elements of a vector or the rows of a
+    /// matrix.
+    LeftBracket,
+    RightBracket,
+    Comma,
+    /// `:=`, separating a function definition from its body.
+    Define,
+    /// `=`, separating the name of a variable from its value.
+    Assign,
+    /// `∠`, between the modulus and the argument of a polar number.
+    Angle,
+    Unit(Unit),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    position: usize,
+    /// Where the last number literal or closing parenthesis ended, a unit
+    /// name may follow it.
+    value_end: Option<usize>,
+    is_user_function: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(
+        input: &'a str,
+        is_user_function: &'a dyn Fn(&str) -> bool,
+    ) -> Lexer<'a> {
+        Lexer {
+            input,
+            position: 0,
+            value_end: None,
+            is_user_function,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.input[self.position..].chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+            self.bump();
+        }
+        &self.input[start..self.position]
+    }
+
+    /// Whether an exponent like `e3`, `E-3` or `e+12` starts here. Without
+    /// digits after it, an `e` is the constant, so `2e` is `2*e`.
+    fn at_exponent(&self) -> bool {
+        let mut chars = self.input[self.position..].chars();
+        if !matches!(chars.next(), Some('e') | Some('E')) {
+            return false;
+        }
+        match chars.next() {
+            Some('+') | Some('-') => {
+                matches!(chars.next(), Some(c) if c.is_ascii_digit())
+            }
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    fn number(&mut self) -> Result<TokenKind, ErrorKind> {
+        let start = self.position;
+        self.take_while(|c| c.is_ascii_digit() || c == '_' || c == '.');
+        if self.at_exponent() {
+            self.bump();
+            if let Some('+') | Some('-') = self.peek() {
+                self.bump();
+            }
+            self.take_while(|c| c.is_ascii_digit() || c == '_');
+        }
+        let text = &self.input[start..self.position];
+        let n = parse_number(text)
+            .ok_or_else(|| ErrorKind::MalformedNumber(text.to_string()))?;
+
+        // A trailing `i` makes the literal imaginary, unless it starts a
+        // longer identifier
+        let is_imaginary = self.peek() == Some('i')
+            && !matches!(self.peek_second(), Some(c) if is_identifier_char(c));
+        if is_imaginary {
+            self.bump();
+            return Ok(TokenKind::Imaginary(n));
+        }
+        self.value_end = Some(self.position);
+        Ok(TokenKind::Number(n))
+    }
+
+    fn word(&mut self) -> TokenKind {
+        let start = self.position;
+        let word = self.take_while(is_identifier_char);
+        if word == "i" {
+            return TokenKind::Imaginary(1.0);
+        }
+        // Units are only names right after a value, `45deg` or `(PI/4)rad`
+        // but not `deg`
+        if self.value_end == Some(start) {
+            match word {
+                "deg" => return TokenKind::Unit(Unit::Degree),
+                "rad" => return TokenKind::Unit(Unit::Radian),
+                _ => {}
+            }
+        }
+        match Function::from_name(word) {
+            Some(f) => TokenKind::Function(f),
+            None if (self.is_user_function)(word) => {
+                TokenKind::UserFunction(word.to_string())
+            }
+            None => TokenKind::Identifier(word.to_string()),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        self.take_while(char::is_whitespace);
+        let start = self.position;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let kind = if c.is_ascii_digit() || c == '.' {
+            self.number()
+        } else if c.is_alphabetic() {
+            Ok(self.word())
+        } else {
+            self.bump();
+            match c {
+                '(' => Ok(TokenKind::LeftParen),
+                ')' => {
+                    self.value_end = Some(self.position);
+                    Ok(TokenKind::RightParen)
+                }
+                '[' => Ok(TokenKind::LeftBracket),
+                ']' => Ok(TokenKind::RightBracket),
+                ',' => Ok(TokenKind::Comma),
+                '=' => Ok(TokenKind::Assign),
+                '∠' => Ok(TokenKind::Angle),
+                '°' => Ok(TokenKind::Unit(Unit::Degree)),
+                ':' if self.peek() == Some('=') => {
+                    self.bump();
+                    Ok(TokenKind::Define)
+                }
+                _ => Operator::from_char(c)
+                    .map(TokenKind::Operator)
+                    .ok_or(ErrorKind::UnexpectedCharacter(c)),
+            }
+        };
+        let span = Span::new(start, self.position);
+        match kind {
+            Ok(kind) => Ok(Some(Token { kind, span })),
+            Err(kind) => Err(Error::new(kind, span)),
+        }
+    }
+}
+
+/// Parses a number literal such as `12`, `.5`, `6.02E23` or `1_000`.
+/// Underscores may only group digits.
+fn parse_number(text: &str) -> Option<f64> {
+    let bytes = text.as_bytes();
+    let is_grouping = |i: usize| {
+        i > 0
+            && i + 1 < bytes.len()
+            && bytes[i - 1].is_ascii_digit()
+            && bytes[i + 1].is_ascii_digit()
+    };
+    if (0..bytes.len()).any(|i| bytes[i] == b'_' && !is_grouping(i)) {
+        return None;
+    }
+    let digits: String = text.chars().filter(|c| *c != '_').collect();
+    digits.parse::<f64>().ok()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits an input into tokens.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    tokenize_with(input, &|_| false)
+}
+
+/// Splits an input into tokens, with the names `is_user_function` accepts
+/// as user function tokens instead of identifiers.
+pub fn tokenize_with(
+    input: &str,
+    is_user_function: &dyn Fn(&str) -> bool,
+) -> Result<Vec<Token>, Error> {
+    let mut lexer = Lexer::new(input, is_user_function);
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    fn malformed(input: &str) -> ErrorKind {
+        tokenize(input).unwrap_err().kind
+    }
+
+    #[test]
+    fn lexes_number_literals() {
+        assert_eq!(kinds("12"), vec![TokenKind::Number(12.0)]);
+        assert_eq!(kinds(".5"), vec![TokenKind::Number(0.5)]);
+        assert_eq!(kinds("1_000"), vec![TokenKind::Number(1000.0)]);
+        assert_eq!(kinds("1e-3"), vec![TokenKind::Number(1e-3)]);
+        assert_eq!(kinds("6.02E23"), vec![TokenKind::Number(6.02e23)]);
+        assert_eq!(kinds("2.5E+4i"), vec![TokenKind::Imaginary(2.5e4)]);
+        assert_eq!(kinds("20i"), vec![TokenKind::Imaginary(20.0)]);
+    }
+
+    #[test]
+    fn lexes_expressions() {
+        let name = |n: &str| TokenKind::Identifier(n.to_string());
+        let tokens = tokenize("sin(z)+ 2.5i*w^-1").unwrap();
+        assert_eq!(
+            kinds("sin(z)+ 2.5i*w^-1"),
+            vec![
+                TokenKind::Function(Function::Sin),
+                TokenKind::LeftParen,
+                name("z"),
+                TokenKind::RightParen,
+                TokenKind::Operator(Operator::Add),
+                TokenKind::Imaginary(2.5),
+                TokenKind::Operator(Operator::Mul),
+                name("w"),
+                TokenKind::Operator(Operator::Pow),
+                TokenKind::Operator(Operator::Sub),
+                TokenKind::Number(1.0),
+            ]
+        );
+        let spans: Vec<_> = tokens.iter().map(|t| t.span).collect();
+        assert_eq!(spans[0], Span::new(0, 3));
+        assert_eq!(spans[4], Span::new(6, 7));
+        assert_eq!(spans[5], Span::new(8, 12));
+        assert_eq!(spans[10], Span::new(16, 17));
+        assert_eq!(
+            kinds("[1, i] = a"),
+            vec![
+                TokenKind::LeftBracket,
+                TokenKind::Number(1.0),
+                TokenKind::Comma,
+                TokenKind::Imaginary(1.0),
+                TokenKind::RightBracket,
+                TokenKind::Assign,
+                name("a"),
+            ]
+        );
+        // Only `i` alone or right after a number is imaginary
+        assert_eq!(kinds("2in"), vec![TokenKind::Number(2.0), name("in")]);
+
+        let user = tokenize_with("g(x) + gg", &|n| n == "g").unwrap();
+        assert_eq!(user[0].kind, TokenKind::UserFunction("g".to_string()));
+        assert_eq!(user[5].kind, name("gg"));
+    }
+
+    #[test]
+    fn lexes_standalone_e_as_identifier() {
+        let e = || TokenKind::Identifier("e".to_string());
+        assert_eq!(kinds("2e"), vec![TokenKind::Number(2.0), e()]);
+        assert_eq!(
+            kinds("2e-z"),
+            vec![
+                TokenKind::Number(2.0),
+                e(),
+                TokenKind::Operator(Operator::Sub),
+                TokenKind::Identifier("z".to_string()),
+            ]
+        );
+        assert_eq!(kinds("Ez"), vec![TokenKind::Identifier("Ez".to_string())]);
+    }
+
+    #[test]
+    fn lexes_polar_numbers() {
+        let degrees = TokenKind::Unit(Unit::Degree);
+        assert_eq!(
+            kinds("2∠45°"),
+            vec![
+                TokenKind::Number(2.0),
+                TokenKind::Angle,
+                TokenKind::Number(45.0),
+                degrees.clone(),
+            ]
+        );
+        assert_eq!(kinds("45deg"), vec![TokenKind::Number(45.0), degrees]);
+        assert_eq!(
+            kinds("1rad"),
+            vec![TokenKind::Number(1.0), TokenKind::Unit(Unit::Radian)]
+        );
+        // Anywhere else they are names
+        let name = |n: &str| TokenKind::Identifier(n.to_string());
+        assert_eq!(kinds("1 rad"), vec![TokenKind::Number(1.0), name("rad")]);
+        assert_eq!(kinds("deg"), vec![name("deg")]);
+        assert_eq!(
+            kinds("2degree"),
+            vec![TokenKind::Number(2.0), name("degree")]
+        );
+        assert_eq!(tokenize("2∠45°").unwrap()[3].span, Span::new(6, 8));
+    }
+
+    #[test]
+    fn lexes_definitions() {
+        let name = |n: &str| TokenKind::Identifier(n.to_string());
+        assert_eq!(
+            kinds("f(z, w) := z"),
+            vec![
+                name("f"),
+                TokenKind::LeftParen,
+                name("z"),
+                TokenKind::Comma,
+                name("w"),
+                TokenKind::RightParen,
+                TokenKind::Define,
+                name("z"),
+            ]
+        );
+        assert_eq!(malformed("f(z) : z"), ErrorKind::UnexpectedCharacter(':'));
+    }
+
+    #[test]
+    fn rejects_malformed_numbers() {
+        let number = |n: &str| ErrorKind::MalformedNumber(n.to_string());
+        assert_eq!(malformed("1.2.3"), number("1.2.3"));
+        assert_eq!(malformed("."), number("."));
+        assert_eq!(malformed("1__000"), number("1__000"));
+        assert_eq!(malformed("1_"), number("1_"));
+        assert_eq!(malformed("2 * 1_.5"), number("1_.5"));
+        assert_eq!(tokenize("2 * 1.2.3").unwrap_err().span, Span::new(4, 9));
+    }
+}
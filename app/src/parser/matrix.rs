@@ -0,0 +1,824 @@
+use crate::parser::ast::{negate, not_inlined, Expr, Operator};
+use crate::parser::branches::BranchCut;
+use crate::parser::definitions::Definitions;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+use num_traits::{One, Zero};
+use std::collections::HashMap;
+
+/// Shifted QR iterations allowed for each eigenvalue.
+const MAX_QR_ITERATIONS: usize = 100;
+
+/// A matrix of complex numbers in double precision, stored by rows. Vectors
+/// are columns, `n x 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    entries: Vec<Complex<f64>>,
+}
+
+impl Matrix {
+    /// The matrix with `entries` by rows, which has to hold `rows * cols`
+    /// of them.
+    pub fn new(rows: usize, cols: usize, entries: Vec<Complex<f64>>) -> Matrix {
+        assert_eq!(entries.len(), rows * cols);
+        Matrix {
+            rows,
+            cols,
+            entries,
+        }
+    }
+
+    pub fn identity(n: usize) -> Matrix {
+        let mut identity = Matrix::new(n, n, vec![Complex::zero(); n * n]);
+        for i in 0..n {
+            identity[(i, i)] = Complex::one();
+        }
+        identity
+    }
+
+    /// A column vector.
+    pub fn column(entries: Vec<Complex<f64>>) -> Matrix {
+        Matrix::new(entries.len(), 1, entries)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn is_square(&self) -> bool {
+        self.rows == self.cols
+    }
+
+    /// Like `2x3`, for errors.
+    fn shape(&self) -> String {
+        format!("{}x{}", self.rows, self.cols)
+    }
+
+    fn map(&self, f: impl Fn(Complex<f64>) -> Complex<f64>) -> Matrix {
+        Matrix::new(
+            self.rows,
+            self.cols,
+            self.entries.iter().map(|z| f(*z)).collect(),
+        )
+    }
+
+    fn zip(
+        &self,
+        other: &Matrix,
+        f: impl Fn(Complex<f64>, Complex<f64>) -> Complex<f64>,
+    ) -> Matrix {
+        let entries = self.entries.iter().zip(other.entries.iter());
+        Matrix::new(
+            self.rows,
+            self.cols,
+            entries.map(|(z, w)| f(*z, *w)).collect(),
+        )
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut transpose =
+            Matrix::new(self.cols, self.rows, self.entries.clone());
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                transpose[(j, i)] = self[(i, j)];
+            }
+        }
+        transpose
+    }
+
+    pub fn trace(&self) -> Complex<f64> {
+        (0..self.rows.min(self.cols)).map(|i| self[(i, i)]).sum()
+    }
+
+    /// The product `self * other`, when the columns of `self` are as many as
+    /// the rows of `other`.
+    pub fn mul(&self, other: &Matrix) -> Option<Matrix> {
+        if self.cols != other.rows {
+            return None;
+        }
+        let mut product = Matrix::new(
+            self.rows,
+            other.cols,
+            vec![Complex::zero(); self.rows * other.cols],
+        );
+        for i in 0..self.rows {
+            for j in 0..other.cols {
+                product[(i, j)] =
+                    (0..self.cols).map(|k| self[(i, k)] * other[(k, j)]).sum();
+            }
+        }
+        Some(product)
+    }
+
+    /// The LU decomposition with partial pivoting, L and U in one matrix,
+    /// with the row permutation and its sign. `None` when a pivot is zero.
+    fn lu(&self) -> Option<(Matrix, Vec<usize>, f64)> {
+        let n = self.rows;
+        let mut lu = self.clone();
+        let mut permutation: Vec<usize> = (0..n).collect();
+        let mut sign = 1.0;
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&a, &b| {
+                    lu[(a, k)].norm().total_cmp(&lu[(b, k)].norm())
+                })
+                .unwrap_or(k);
+            if lu[(pivot, k)].is_zero() {
+                return None;
+            }
+            if pivot != k {
+                for j in 0..n {
+                    lu.entries.swap(k * n + j, pivot * n + j);
+                }
+                permutation.swap(k, pivot);
+                sign = -sign;
+            }
+            for i in k + 1..n {
+                let factor = lu[(i, k)] / lu[(k, k)];
+                lu[(i, k)] = factor;
+                for j in k + 1..n {
+                    let subtrahend = factor * lu[(k, j)];
+                    lu[(i, j)] -= subtrahend;
+                }
+            }
+        }
+        Some((lu, permutation, sign))
+    }
+
+    /// The determinant of a square matrix, by LU decomposition.
+    pub fn det(&self) -> Complex<f64> {
+        match self.lu() {
+            Some((lu, _, sign)) => {
+                (0..self.rows).map(|i| lu[(i, i)]).product::<Complex<f64>>()
+                    * sign
+            }
+            None => Complex::zero(),
+        }
+    }
+
+    /// The inverse of a square matrix, `None` when it is singular.
+    pub fn inverse(&self) -> Option<Matrix> {
+        let n = self.rows;
+        let (lu, permutation, _) = self.lu()?;
+        let mut inverse = Matrix::identity(n);
+        for j in 0..n {
+            // Solves L y = P e_j, then U x = y, for the column j
+            let mut x: Vec<Complex<f64>> = (0..n)
+                .map(|i| {
+                    if permutation[i] == j {
+                        Complex::one()
+                    } else {
+                        Complex::zero()
+                    }
+                })
+                .collect();
+            for i in 0..n {
+                for k in 0..i {
+                    let subtrahend = lu[(i, k)] * x[k];
+                    x[i] -= subtrahend;
+                }
+            }
+            for i in (0..n).rev() {
+                for k in i + 1..n {
+                    let subtrahend = lu[(i, k)] * x[k];
+                    x[i] -= subtrahend;
+                }
+                x[i] /= lu[(i, i)];
+            }
+            for (i, x) in x.into_iter().enumerate() {
+                inverse[(i, j)] = x;
+            }
+        }
+        if inverse.entries.iter().all(|z| z.is_finite()) {
+            Some(inverse)
+        } else {
+            None
+        }
+    }
+
+    /// `self^n` for a square matrix, by repeated squaring, through the
+    /// inverse for negative `n`.
+    fn powi(&self, n: i64) -> Option<Matrix> {
+        let mut base = if n < 0 { self.inverse()? } else { self.clone() };
+        let mut power = Matrix::identity(self.rows);
+        let mut n = n.unsigned_abs();
+        while n > 0 {
+            if n & 1 == 1 {
+                power = power.mul(&base)?;
+            }
+            base = base.mul(&base)?;
+            n >>= 1;
+        }
+        Some(power)
+    }
+
+    /// The eigenvalues of a square matrix, with their multiplicities, sorted
+    /// by real and then imaginary part.
+    ///
+    /// The matrix is reduced to Hessenberg form by Householder reflections,
+    /// then shifted QR steps with Wilkinson shifts split off an eigenvalue
+    /// at the bottom whenever a subdiagonal entry becomes negligible.
+    pub fn eigenvalues(&self) -> Option<Vec<Complex<f64>>> {
+        let mut a = self.hessenberg();
+        let mut eigenvalues = Vec::with_capacity(self.rows);
+        let mut hi = self.rows;
+        let mut iterations = 0;
+        while hi > 0 {
+            let last = hi - 1;
+            // The top of the block still coupled to the last row
+            let mut lo = last;
+            while lo > 0 {
+                let small = f64::EPSILON
+                    * (a[(lo, lo)].norm() + a[(lo - 1, lo - 1)].norm());
+                if a[(lo, lo - 1)].norm() <= small {
+                    a[(lo, lo - 1)] = Complex::zero();
+                    break;
+                }
+                lo -= 1;
+            }
+            if lo == last {
+                eigenvalues.push(a[(last, last)]);
+                hi = last;
+                iterations = 0;
+                continue;
+            }
+            iterations += 1;
+            if iterations > MAX_QR_ITERATIONS {
+                return None;
+            }
+            // Exceptional shifts break the cycles of some matrices
+            let shift = if iterations % 11 == 0 {
+                a[(last, last)] + a[(last, last - 1)].norm()
+            } else {
+                a.wilkinson_shift(last)
+            };
+            a.qr_step(lo, last, shift);
+        }
+        eigenvalues
+            .sort_by(|z, w| z.re.total_cmp(&w.re).then(z.im.total_cmp(&w.im)));
+        Some(eigenvalues)
+    }
+
+    /// A matrix similar to this one, zero below the first subdiagonal.
+    fn hessenberg(&self) -> Matrix {
+        let n = self.rows;
+        let mut a = self.clone();
+        for k in 0..n.saturating_sub(2) {
+            let mut v: Vec<Complex<f64>> =
+                (k + 1..n).map(|i| a[(i, k)]).collect();
+            let norm = v.iter().map(|z| z.norm_sqr()).sum::<f64>().sqrt();
+            if norm == 0.0 {
+                continue;
+            }
+            // The sign that avoids cancelling, as a phase
+            let phase = if v[0].is_zero() {
+                Complex::one()
+            } else {
+                v[0] / v[0].norm()
+            };
+            v[0] += phase * norm;
+            let v_norm = v.iter().map(|z| z.norm_sqr()).sum::<f64>().sqrt();
+            for z in v.iter_mut() {
+                *z /= v_norm;
+            }
+            // A = (I - 2vv*) A (I - 2vv*)
+            for j in 0..n {
+                let dot: Complex<f64> =
+                    (0..v.len()).map(|i| v[i].conj() * a[(k + 1 + i, j)]).sum();
+                for i in 0..v.len() {
+                    a[(k + 1 + i, j)] -= v[i] * dot * 2.0;
+                }
+            }
+            for i in 0..n {
+                let dot: Complex<f64> =
+                    (0..v.len()).map(|j| a[(i, k + 1 + j)] * v[j]).sum();
+                for j in 0..v.len() {
+                    a[(i, k + 1 + j)] -= dot * v[j].conj() * 2.0;
+                }
+            }
+        }
+        a
+    }
+
+    /// The eigenvalue of the trailing 2x2 block ending at `last` closest to
+    /// its bottom right entry.
+    fn wilkinson_shift(&self, last: usize) -> Complex<f64> {
+        let a = self[(last - 1, last - 1)];
+        let b = self[(last - 1, last)];
+        let c = self[(last, last - 1)];
+        let d = self[(last, last)];
+        let half = (a - d) * 0.5;
+        let root = (half * half + b * c).sqrt();
+        let denominator = if (half + root).norm() >= (half - root).norm() {
+            half + root
+        } else {
+            half - root
+        };
+        if denominator.is_zero() {
+            d
+        } else {
+            d - b * c / denominator
+        }
+    }
+
+    /// One QR step on the Hessenberg block from `lo` to `hi`, `A - sI = QR`
+    /// then `RQ + sI`, with Givens rotations. The entries outside the block
+    /// do not change its eigenvalues, they are left as they are.
+    fn qr_step(&mut self, lo: usize, hi: usize, shift: Complex<f64>) {
+        for i in lo..=hi {
+            self[(i, i)] -= shift;
+        }
+        let mut rotations = Vec::with_capacity(hi - lo);
+        for k in lo..hi {
+            let x = self[(k, k)];
+            let y = self[(k + 1, k)];
+            let r = (x.norm_sqr() + y.norm_sqr()).sqrt();
+            let (c, s) = if r == 0.0 {
+                (Complex::one(), Complex::zero())
+            } else {
+                (x / r, y / r)
+            };
+            // [conj(c) conj(s); -s c] takes (x, y) to (r, 0)
+            for j in k..=hi {
+                let top = self[(k, j)];
+                let bottom = self[(k + 1, j)];
+                self[(k, j)] = c.conj() * top + s.conj() * bottom;
+                self[(k + 1, j)] = c * bottom - s * top;
+            }
+            rotations.push((c, s));
+        }
+        for (k, (c, s)) in (lo..hi).zip(rotations) {
+            for i in lo..=(k + 1).min(hi) {
+                let left = self[(i, k)];
+                let right = self[(i, k + 1)];
+                self[(i, k)] = left * c + right * s;
+                self[(i, k + 1)] = right * c.conj() - left * s.conj();
+            }
+        }
+        for i in lo..=hi {
+            self[(i, i)] += shift;
+        }
+    }
+
+    /// The matrix written like `[[1, 2], [3, 4]]`, a vector like `[1, 2]`,
+    /// with each entry written by `entry`.
+    pub fn format(&self, entry: impl Fn(&Complex<f64>) -> String) -> String {
+        let row = |i: usize| {
+            let entries: Vec<String> =
+                (0..self.cols).map(|j| entry(&self[(i, j)])).collect();
+            format!("[{}]", entries.join(", "))
+        };
+        if self.cols == 1 {
+            let entries: Vec<String> = self.entries.iter().map(entry).collect();
+            format!("[{}]", entries.join(", "))
+        } else {
+            let rows: Vec<String> = (0..self.rows).map(row).collect();
+            format!("[{}]", rows.join(", "))
+        }
+    }
+}
+
+impl std::ops::Index<(usize, usize)> for Matrix {
+    type Output = Complex<f64>;
+
+    fn index(&self, (i, j): (usize, usize)) -> &Complex<f64> {
+        &self.entries[i * self.cols + j]
+    }
+}
+
+impl std::ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Complex<f64> {
+        &mut self.entries[i * self.cols + j]
+    }
+}
+
+impl std::fmt::Display for Matrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(|z| z.to_string()))
+    }
+}
+
+/// The value of an expression with matrices, a number or a matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(Complex<f64>),
+    Matrix(Matrix),
+}
+
+impl Value {
+    fn shape(&self) -> String {
+        match self {
+            Value::Scalar(_) => "1x1".to_string(),
+            Value::Matrix(m) => m.shape(),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Scalar(z) => write!(f, "{}", z),
+            Value::Matrix(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+/// Whether `expr` has to be computed by [`eval`], because it writes a
+/// matrix or uses one of the matrices in `variables`, also through the user
+/// functions in `definitions`.
+pub fn uses_matrices(
+    expr: &Expr,
+    variables: &HashMap<String, Value>,
+    definitions: &Definitions,
+) -> bool {
+    fn has_matrix(expr: &Expr) -> bool {
+        match expr {
+            Expr::Matrix(_, _) => true,
+            Expr::Neg(expr) => has_matrix(expr),
+            Expr::Binary(_, left, right, _) => {
+                has_matrix(left) || has_matrix(right)
+            }
+            Expr::Call(_, args) | Expr::UserCall(_, args, _) => {
+                args.iter().any(has_matrix)
+            }
+            Expr::Number(_)
+            | Expr::Decimal(_, _)
+            | Expr::Constant(_)
+            | Expr::Variable(_, _) => false,
+        }
+    }
+    has_matrix(expr)
+        || expr.dependencies(definitions).iter().any(|name| {
+            matches!(variables.get(name), Some(Value::Matrix(_)))
+                || matches!(
+                    definitions.get(name),
+                    Some(definition) if has_matrix(&definition.body)
+                )
+        })
+}
+
+/// Evaluates `expr` in double precision on the principal branch, with
+/// numbers and matrices in `variables` and the user functions of
+/// `definitions`.
+///
+/// Matrices add and subtract by entries, multiply by numbers and each
+/// other, and divide as products with the inverse. Square ones take
+/// integer powers. `det`, `trace`, `transpose`, `inv`, `conj` and `eig`
+/// (a vector of the eigenvalues) take a matrix, and `mobius(M, z)` applies
+/// `[[a, b], [c, d]]` to `z` as `(az + b)/(cz + d)`. Other functions only
+/// take numbers.
+pub fn eval(
+    expr: &Expr,
+    variables: &HashMap<String, Value>,
+    definitions: &Definitions,
+) -> Result<Value, Error> {
+    evaluate(&expr.inline(definitions)?, variables)
+}
+
+fn evaluate(
+    expr: &Expr,
+    variables: &HashMap<String, Value>,
+) -> Result<Value, Error> {
+    let eval = |expr: &Expr| evaluate(expr, variables);
+    let cut = BranchCut::principal();
+    match expr {
+        Expr::Number(z) | Expr::Decimal(z, _) => Ok(Value::Scalar(*z)),
+        Expr::Constant(c) => Ok(Value::Scalar(c.value())),
+        Expr::Variable(name, span) => {
+            variables.get(name).cloned().ok_or_else(|| {
+                Error::new(ErrorKind::UnknownIdentifier(name.clone()), *span)
+            })
+        }
+        Expr::Neg(expr) => Ok(match eval(expr)? {
+            Value::Scalar(z) => Value::Scalar(negate(z)),
+            Value::Matrix(m) => Value::Matrix(m.map(negate)),
+        }),
+        Expr::Binary(op, left, right, span) => {
+            binary(*op, eval(left)?, eval(right)?, *span)
+        }
+        Expr::Call(f, args) => {
+            let args =
+                args.iter().map(eval).collect::<Result<Vec<_>, Error>>()?;
+            call(*f, args, cut)
+        }
+        Expr::Matrix(elements, span) => {
+            let elements = elements
+                .iter()
+                .map(eval)
+                .collect::<Result<Vec<_>, Error>>()?;
+            matrix(elements, *span).map(Value::Matrix)
+        }
+        Expr::UserCall(name, _, span) => Err(not_inlined(name, *span)),
+    }
+}
+
+/// The matrix of a bracket, a column of numbers or rows of the same
+/// length.
+fn matrix(elements: Vec<Value>, span: Span) -> Result<Matrix, Error> {
+    let ragged = || Error::new(ErrorKind::RaggedMatrix, span);
+    if elements.iter().all(|e| matches!(e, Value::Scalar(_))) {
+        let entries = elements
+            .into_iter()
+            .map(|e| match e {
+                Value::Scalar(z) => z,
+                Value::Matrix(_) => unreachable!(),
+            })
+            .collect();
+        return Ok(Matrix::column(entries));
+    }
+    // Each row is a vector, a column written in a bracket
+    let rows = elements
+        .into_iter()
+        .map(|e| match e {
+            Value::Matrix(row) if row.cols == 1 => Ok(row.entries),
+            _ => Err(ragged()),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let cols = rows[0].len();
+    if rows.iter().any(|row| row.len() != cols) {
+        return Err(ragged());
+    }
+    Ok(Matrix::new(rows.len(), cols, rows.concat()))
+}
+
+fn binary(
+    op: Operator,
+    left: Value,
+    right: Value,
+    span: Span,
+) -> Result<Value, Error> {
+    let mismatch = |left: &Value, right: &Value| {
+        let shapes =
+            format!("{} {} {}", left.shape(), op.symbol(), right.shape());
+        Error::new(ErrorKind::ShapeMismatch(shapes), span)
+    };
+    let singular = || Error::new(ErrorKind::SingularMatrix, span);
+    let cut = BranchCut::principal();
+    Ok(match (op, &left, &right) {
+        (_, Value::Scalar(z1), Value::Scalar(z2)) => {
+            if op == Operator::Div && z2.is_zero() {
+                return Err(Error::new(ErrorKind::DivisionByZero, span));
+            }
+            Value::Scalar(op.apply(*z1, *z2, cut))
+        }
+        (Operator::Add, Value::Matrix(m1), Value::Matrix(m2))
+        | (Operator::Sub, Value::Matrix(m1), Value::Matrix(m2))
+            if m1.rows == m2.rows && m1.cols == m2.cols =>
+        {
+            Value::Matrix(m1.zip(m2, |z1, z2| op.apply(z1, z2, cut)))
+        }
+        (Operator::Mul, Value::Scalar(z), Value::Matrix(m))
+        | (Operator::Mul, Value::Matrix(m), Value::Scalar(z)) => {
+            Value::Matrix(m.map(|w| w * z))
+        }
+        (Operator::Mul, Value::Matrix(m1), Value::Matrix(m2)) => {
+            Value::Matrix(m1.mul(m2).ok_or_else(|| mismatch(&left, &right))?)
+        }
+        (Operator::Div, Value::Matrix(m), Value::Scalar(z)) => {
+            if z.is_zero() {
+                return Err(Error::new(ErrorKind::DivisionByZero, span));
+            }
+            Value::Matrix(m.map(|w| w / z))
+        }
+        (Operator::Div, _, Value::Matrix(m)) if m.is_square() => {
+            let inverse = m.inverse().ok_or_else(singular)?;
+            match &left {
+                Value::Scalar(z) => Value::Matrix(inverse.map(|w| w * z)),
+                Value::Matrix(m1) => Value::Matrix(
+                    m1.mul(&inverse).ok_or_else(|| mismatch(&left, &right))?,
+                ),
+            }
+        }
+        (Operator::Pow, Value::Matrix(m), Value::Scalar(n))
+            if m.is_square() =>
+        {
+            if n.im != 0.0 || n.re.fract() != 0.0 {
+                return Err(Error::new(
+                    ErrorKind::WrongShape(
+                        "^".to_string(),
+                        "an integer exponent for a matrix".to_string(),
+                    ),
+                    span,
+                ));
+            }
+            Value::Matrix(m.powi(n.re as i64).ok_or_else(singular)?)
+        }
+        _ => return Err(mismatch(&left, &right)),
+    })
+}
+
+fn call(
+    f: Function,
+    args: Vec<Value>,
+    cut: BranchCut<f64>,
+) -> Result<Value, Error> {
+    let wrong_shape = |shape: &str| {
+        Err(Error::new(
+            ErrorKind::WrongShape(f.name().to_string(), shape.to_string()),
+            Span::default(),
+        ))
+    };
+    let singular = || Error::new(ErrorKind::SingularMatrix, Span::default());
+    let m = match (f, &args[..]) {
+        (Function::Mobius, [Value::Matrix(m), Value::Scalar(z)])
+            if m.rows == 2 && m.cols == 2 =>
+        {
+            let numerator = m[(0, 0)] * z + m[(0, 1)];
+            let denominator = m[(1, 0)] * z + m[(1, 1)];
+            if denominator.is_zero() {
+                return Err(Error::new(
+                    ErrorKind::DivisionByZero,
+                    Span::default(),
+                ));
+            }
+            return Ok(Value::Scalar(numerator / denominator));
+        }
+        (Function::Mobius, _) => {
+            return wrong_shape("a 2x2 matrix and a number")
+        }
+        (_, [Value::Matrix(m)]) => m,
+        _ => {
+            return match args
+                .iter()
+                .map(|arg| match arg {
+                    Value::Scalar(z) => Some(*z),
+                    Value::Matrix(_) => None,
+                })
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(args) => Ok(Value::Scalar(f.apply(&args, cut))),
+                None => wrong_shape("numbers"),
+            };
+        }
+    };
+    let square = matches!(
+        f,
+        Function::Det | Function::Trace | Function::Inv | Function::Eig
+    );
+    if square && !m.is_square() {
+        return wrong_shape("a square matrix");
+    }
+    Ok(match f {
+        Function::Det => Value::Scalar(m.det()),
+        Function::Trace => Value::Scalar(m.trace()),
+        Function::Transpose => Value::Matrix(m.transpose()),
+        Function::Inv => Value::Matrix(m.inverse().ok_or_else(singular)?),
+        Function::Conj => Value::Matrix(m.map(|z| z.conj())),
+        Function::Eig => {
+            let eigenvalues = m.eigenvalues().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NoConvergence(f.name().to_string()),
+                    Span::default(),
+                )
+            })?;
+            Value::Matrix(Matrix::column(eigenvalues))
+        }
+        _ => return wrong_shape("numbers"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::parse;
+
+    fn value(input: &str) -> Value {
+        let mut variables = HashMap::new();
+        variables.insert(
+            "M".to_string(),
+            Value::Matrix(Matrix::new(
+                2,
+                2,
+                vec![
+                    Complex::new(1.0, 0.0),
+                    Complex::new(2.0, 0.0),
+                    Complex::new(3.0, 0.0),
+                    Complex::new(4.0, 0.0),
+                ],
+            )),
+        );
+        variables.insert("z".to_string(), Value::Scalar(Complex::i()));
+        eval(&parse(input).unwrap(), &variables, &Definitions::default())
+            .unwrap()
+    }
+
+    fn assert_close(found: &Value, expected: &Value, input: &str) {
+        let (found, expected) = match (found, expected) {
+            (Value::Matrix(m1), Value::Matrix(m2))
+                if m1.rows == m2.rows && m1.cols == m2.cols =>
+            {
+                (m1.entries.clone(), m2.entries.clone())
+            }
+            (Value::Scalar(z1), Value::Scalar(z2)) => (vec![*z1], vec![*z2]),
+            _ => panic!("{}: {} is not {}", input, found, expected),
+        };
+        for (z, w) in found.iter().zip(expected.iter()) {
+            assert!((z - w).norm() < 1e-9, "{}: {} is not {}", input, z, w);
+        }
+    }
+
+    #[test]
+    fn computes_with_matrices() {
+        for (input, expected) in [
+            ("[[1, 2], [3, 4]] * [1, i]", "[1 + 2i, 3 + 4i]"),
+            ("M * M - M^2", "[[0, 0], [0, 0]]"),
+            ("2M + [[1, 0], [0, 1]]", "[[3, 4], [6, 9]]"),
+            ("det(M)", "-2"),
+            ("trace(M) + det([[i, 0], [0, i]])", "4"),
+            ("transpose([[1, 2, 3]])", "[1, 2, 3]"),
+            ("inv(M)", "[[-2, 1], [1.5, -0.5]]"),
+            ("M^-1 - M/M * inv(M)", "[[0, 0], [0, 0]]"),
+            ("conj(z M)", "[[-i, -2i], [-3i, -4i]]"),
+            ("eig([[2, 0], [0, 1]])", "[1, 2]"),
+            ("eig([[0, -1], [1, 0]])", "[-i, i]"),
+            ("mobius([[1, z], [0, 1]], 2)", "2 + i"),
+            ("mobius([[0, 1], [1, 0]], z) + sin(0)", "-i"),
+        ]
+        .iter()
+        {
+            let expected = eval(
+                &parse(expected).unwrap(),
+                &HashMap::new(),
+                &Definitions::default(),
+            )
+            .unwrap();
+            assert_close(&value(input), &expected, input);
+        }
+    }
+
+    #[test]
+    fn finds_eigenvalues() {
+        // A scaled rotation and a Jordan block
+        let found = value(
+            "eig([[2, 1, 0, 0], [-1, 2, 0, 0], [0, 0, 1, 1e-3], [0, 0, 0, 1]])",
+        );
+        let expected = ["1", "1", "2 - i", "2 + i"];
+        match found {
+            Value::Matrix(m) => {
+                for (z, w) in m.entries.iter().zip(expected.iter()) {
+                    let w = parse(w).unwrap().eval(&HashMap::new()).unwrap();
+                    assert!((z - w).norm() < 1e-6, "{} is not {}", z, w);
+                }
+            }
+            Value::Scalar(z) => panic!("{} is not a matrix", z),
+        }
+        // The eigenvalues of a random-looking complex matrix multiply to its
+        // determinant and add up to its trace
+        let m = Matrix::new(
+            3,
+            3,
+            [1.0, 2.0, -1.0, 0.5, 3.0, 2.0, -2.0, 1.0, 0.25]
+                .iter()
+                .zip([0.5, -1.0, 2.0, 1.0, 0.0, -0.5, 1.5, 1.0, -2.0].iter())
+                .map(|(x, y)| Complex::new(*x, *y))
+                .collect(),
+        );
+        let eigenvalues = m.eigenvalues().unwrap();
+        let product: Complex<f64> = eigenvalues.iter().product();
+        let sum: Complex<f64> = eigenvalues.iter().sum();
+        assert!((product - m.det()).norm() < 1e-9);
+        assert!((sum - m.trace()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn reports_shape_errors() {
+        let error = |input: &str| {
+            eval(
+                &parse(input).unwrap(),
+                &HashMap::new(),
+                &Definitions::default(),
+            )
+            .unwrap_err()
+            .kind
+        };
+        assert_eq!(
+            error("[1, 2] * [1, 2]"),
+            ErrorKind::ShapeMismatch("2x1 * 2x1".to_string())
+        );
+        assert_eq!(error("[[1, 2], [3]]"), ErrorKind::RaggedMatrix);
+        assert_eq!(error("inv([[1, 2], [2, 4]])"), ErrorKind::SingularMatrix);
+        assert_eq!(
+            error("det([1, 2])"),
+            ErrorKind::WrongShape(
+                "det".to_string(),
+                "a square matrix".to_string()
+            )
+        );
+        assert_eq!(
+            error("sin([1, 2])"),
+            ErrorKind::WrongShape("sin".to_string(), "numbers".to_string())
+        );
+        assert_eq!(
+            parse("[1, 2] + 1").unwrap().eval::<f64, _>(&HashMap::new()),
+            Err(Error::new(ErrorKind::NotScalar, Span::new(0, 6)))
+        );
+    }
+}
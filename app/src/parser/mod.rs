@@ -1,2 +1,20 @@
+pub mod ast;
+pub mod branches;
+pub mod definitions;
+pub mod derivatives;
+pub mod error;
 pub mod evaluations;
+pub mod exact;
+pub mod functions;
+pub mod integrals;
+pub mod lexer;
+pub mod matrix;
+pub mod polynomial;
+pub mod precise;
+pub mod printing;
+pub mod program;
+pub mod residues;
+pub mod simplify;
+pub mod special;
 pub mod symbolic;
+pub mod zeros;
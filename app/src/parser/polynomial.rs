@@ -0,0 +1,625 @@
+use crate::parser::ast::{Context, Env, Expr, Operator};
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+use num_traits::Zero;
+use std::f64::consts::TAU;
+
+/// The highest degree of the polynomials expanded and solved, the work of
+/// finding the roots grows with its square.
+pub const MAX_DEGREE: usize = 512;
+
+/// Passes over all the approximations before the roots are given up on.
+const MAX_ITERATIONS: usize = 500;
+
+/// Newton steps taken on each root after the approximations converge.
+const POLISHING_STEPS: usize = 8;
+
+/// A polynomial with complex coefficients, lowest degree first and without
+/// zeros after the leading one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polynomial {
+    coefficients: Vec<Complex<f64>>,
+}
+
+/// A root of a polynomial and how many times it is repeated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Root {
+    pub value: Complex<f64>,
+    pub multiplicity: usize,
+}
+
+impl Polynomial {
+    /// The polynomial with `coefficients`, lowest degree first.
+    pub fn new(mut coefficients: Vec<Complex<f64>>) -> Polynomial {
+        while matches!(coefficients.last(), Some(c) if c.is_zero()) {
+            coefficients.pop();
+        }
+        Polynomial { coefficients }
+    }
+
+    /// The polynomial with `coefficients`, highest degree first as they are
+    /// written, like `1, 0, -1` for `z^2 - 1`.
+    pub fn from_coefficients(
+        mut coefficients: Vec<Complex<f64>>,
+    ) -> Polynomial {
+        coefficients.reverse();
+        Polynomial::new(coefficients)
+    }
+
+    /// Expands `expr` into a polynomial in `variable`. Every other variable
+    /// is a constant taken from `env`, and user functions are inlined.
+    ///
+    /// Only sums, products, divisions by constants and powers with natural
+    /// exponents of the variable are polynomials, anything else around it,
+    /// like `1/z` or `sin(z)`, is an error.
+    pub fn from_expr<E: Env<f64> + ?Sized>(
+        expr: &Expr,
+        variable: &str,
+        env: &E,
+        context: &Context,
+    ) -> Result<Polynomial, Error> {
+        let expr = expr.inline(context.definitions)?;
+        Expander {
+            variable,
+            env,
+            context,
+        }
+        .expand(&expr)
+    }
+
+    /// The coefficients, lowest degree first.
+    pub fn coefficients(&self) -> &[Complex<f64>] {
+        &self.coefficients
+    }
+
+    /// The degree, taken as 0 for the zero polynomial.
+    pub fn degree(&self) -> usize {
+        self.coefficients.len().saturating_sub(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.coefficients.is_empty()
+    }
+
+    pub fn eval(&self, z: Complex<f64>) -> Complex<f64> {
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(Complex::zero(), |p, c| p * z + c)
+    }
+
+    /// The value and the derivative at `z`, by Horner's method.
+    fn eval_with_derivative(
+        &self,
+        z: Complex<f64>,
+    ) -> (Complex<f64>, Complex<f64>) {
+        let mut p = Complex::zero();
+        let mut dp = Complex::zero();
+        for c in self.coefficients.iter().rev() {
+            dp = dp * z + p;
+            p = p * z + c;
+        }
+        (p, dp)
+    }
+
+    /// A bound of the rounding error of [`eval`](Polynomial::eval) at `z`.
+    /// Below it the value is as good as zero.
+    fn rounding_error(&self, z: Complex<f64>) -> f64 {
+        let modulus = z.norm();
+        let sum = self
+            .coefficients
+            .iter()
+            .rev()
+            .fold(0.0, |sum, c| sum * modulus + c.norm());
+        4.0 * self.coefficients.len() as f64 * f64::EPSILON * sum
+    }
+
+    pub fn derivative(&self) -> Polynomial {
+        Polynomial::new(
+            self.coefficients
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(k, c)| c * k as f64)
+                .collect(),
+        )
+    }
+
+    fn scale(&self, factor: Complex<f64>) -> Polynomial {
+        Polynomial::new(self.coefficients.iter().map(|c| c * factor).collect())
+    }
+
+    fn add(&self, other: &Polynomial) -> Polynomial {
+        let n = self.coefficients.len().max(other.coefficients.len());
+        let coefficient = |p: &Polynomial, k: usize| {
+            p.coefficients.get(k).copied().unwrap_or_else(Complex::zero)
+        };
+        Polynomial::new(
+            (0..n)
+                .map(|k| coefficient(self, k) + coefficient(other, k))
+                .collect(),
+        )
+    }
+
+    fn mul(&self, other: &Polynomial) -> Polynomial {
+        if self.is_zero() || other.is_zero() {
+            return Polynomial::new(vec![]);
+        }
+        let mut coefficients =
+            vec![Complex::zero(); self.degree() + other.degree() + 1];
+        for (i, a) in self.coefficients.iter().enumerate() {
+            for (j, b) in other.coefficients.iter().enumerate() {
+                coefficients[i + j] += a * b;
+            }
+        }
+        Polynomial::new(coefficients)
+    }
+
+    /// The `n`th power by repeated squaring, so that a constant takes
+    /// `log₂ n` products.
+    fn pow(&self, mut n: u64) -> Polynomial {
+        let mut power = Polynomial::new(vec![Complex::new(1.0, 0.0)]);
+        let mut square = self.clone();
+        while n > 0 {
+            if n % 2 == 1 {
+                power = power.mul(&square);
+            }
+            n /= 2;
+            if n > 0 {
+                square = square.mul(&square);
+            }
+        }
+        power
+    }
+
+    /// All the roots, each once with its multiplicity, sorted by their real
+    /// and then their imaginary parts.
+    ///
+    /// The roots are approximated together by Aberth's method, each one
+    /// pushed away from the others, until the polynomial is zero up to
+    /// rounding at all of them. The approximations of a repeated root
+    /// scatter around it, those whose inclusion disks overlap are taken as
+    /// one root, at their mean. Newton's method then polishes each root, on
+    /// the derivative that has it as a simple root.
+    pub fn roots(&self) -> Result<Vec<Root>, Error> {
+        if self.is_zero() {
+            return Err(Error::new(ErrorKind::ZeroPolynomial, Span::default()));
+        }
+        // Roots at zero are exact, and leave a polynomial without them
+        let zeros =
+            self.coefficients.iter().take_while(|c| c.is_zero()).count();
+        let reduced = Polynomial::new(self.coefficients[zeros..].to_vec());
+        let mut roots = vec![];
+        if zeros > 0 {
+            roots.push(Root {
+                value: Complex::zero(),
+                multiplicity: zeros,
+            });
+        }
+        if reduced.degree() > 0 {
+            let approximations = reduced.aberth().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NoConvergence("roots".to_string()),
+                    Span::default(),
+                )
+            })?;
+            for cluster in reduced.clusters(&approximations) {
+                let center = cluster
+                    .iter()
+                    .map(|&i| approximations[i])
+                    .sum::<Complex<f64>>()
+                    / cluster.len() as f64;
+                roots.push(Root {
+                    value: reduced.polish(center, cluster.len()),
+                    multiplicity: cluster.len(),
+                });
+            }
+        }
+        roots.sort_by(|a, b| {
+            a.value
+                .re
+                .partial_cmp(&b.value.re)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(
+                    a.value
+                        .im
+                        .partial_cmp(&b.value.im)
+                        .unwrap_or(std::cmp::Ordering::Equal),
+                )
+        });
+        Ok(roots)
+    }
+
+    /// Approximations of all the roots of a polynomial without a root at
+    /// zero, or `None` if they do not converge.
+    fn aberth(&self) -> Option<Vec<Complex<f64>>> {
+        let n = self.degree();
+        let leading = self.coefficients[n];
+        if self.coefficients.iter().any(|c| !c.is_finite()) {
+            return None;
+        }
+        // Started on a circle as large as the roots may be, turned off the
+        // axes so the roots of real polynomials are not started in step
+        let radius = (1..=n)
+            .map(|k| {
+                (self.coefficients[n - k] / leading)
+                    .norm()
+                    .powf(1.0 / k as f64)
+            })
+            .fold(0.0, f64::max);
+        let mut roots: Vec<Complex<f64>> = (0..n)
+            .map(|k| {
+                Complex::from_polar(radius, TAU * k as f64 / n as f64 + 0.4)
+            })
+            .collect();
+        let mut converged = vec![false; n];
+
+        for _ in 0..MAX_ITERATIONS {
+            for i in 0..n {
+                if converged[i] {
+                    continue;
+                }
+                let z = roots[i];
+                let (p, dp) = self.eval_with_derivative(z);
+                if p.norm() <= self.rounding_error(z) {
+                    converged[i] = true;
+                    continue;
+                }
+                let repulsion: Complex<f64> = roots
+                    .iter()
+                    .filter(|&&w| w != z)
+                    .map(|w| (z - w).inv())
+                    .sum();
+                let denominator = dp / p - repulsion;
+                if !denominator.is_zero() {
+                    roots[i] = z - denominator.inv();
+                }
+            }
+            if converged.iter().all(|&c| c) {
+                return Some(roots);
+            }
+        }
+        None
+    }
+
+    /// Groups the approximations of the roots whose inclusion disks
+    /// overlap, each group holds as many roots as approximations.
+    fn clusters(&self, roots: &[Complex<f64>]) -> Vec<Vec<usize>> {
+        let n = roots.len();
+        let leading = self.coefficients[n];
+        let radii: Vec<f64> = roots
+            .iter()
+            .map(|&z| {
+                let product: f64 = roots
+                    .iter()
+                    .filter(|&&w| w != z)
+                    .map(|w| (z - w).norm())
+                    .product();
+                n as f64 * (self.eval(z) / leading).norm() / product
+            })
+            .collect();
+        let mut labels: Vec<usize> = (0..n).collect();
+        for i in 0..n {
+            for j in i + 1..n {
+                let overlap =
+                    (roots[i] - roots[j]).norm() <= radii[i] + radii[j];
+                if overlap && labels[i] != labels[j] {
+                    let (from, to) = (labels[j], labels[i]);
+                    for label in labels.iter_mut() {
+                        if *label == from {
+                            *label = to;
+                        }
+                    }
+                }
+            }
+        }
+        let mut clusters: Vec<Vec<usize>> = vec![];
+        for i in 0..n {
+            match clusters.iter_mut().find(|c| labels[c[0]] == labels[i]) {
+                Some(cluster) => cluster.push(i),
+                None => clusters.push(vec![i]),
+            }
+        }
+        clusters
+    }
+
+    /// Newton's method from `z` on the derivative where a root repeated
+    /// `multiplicity` times is simple, for as long as it gets closer.
+    fn polish(&self, z: Complex<f64>, multiplicity: usize) -> Complex<f64> {
+        let mut q = self.clone();
+        for _ in 1..multiplicity {
+            q = q.derivative();
+        }
+        let mut z = z;
+        let mut value = q.eval(z).norm();
+        for _ in 0..POLISHING_STEPS {
+            let (p, dp) = q.eval_with_derivative(z);
+            if p.is_zero() || dp.is_zero() {
+                break;
+            }
+            let next = z - p / dp;
+            let next_value = q.eval(next).norm();
+            if next_value.is_nan() || next_value >= value {
+                break;
+            }
+            z = next;
+            value = next_value;
+        }
+        z
+    }
+
+    /// The polynomial written in `variable`, highest degree first, like
+    /// `z^3 - 2i*z + 1`.
+    pub fn to_expr(&self, variable: &str) -> Expr {
+        let span = Span::default();
+        let mut terms = self
+            .coefficients
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, c)| !c.is_zero());
+        let term = |k: usize, c: Complex<f64>| {
+            let power = match k {
+                0 => return Expr::Number(c),
+                1 => Expr::Variable(variable.to_string(), span),
+                _ => Expr::Binary(
+                    Operator::Pow,
+                    Box::new(Expr::Variable(variable.to_string(), span)),
+                    Box::new(Expr::Number(Complex::new(k as f64, 0.0))),
+                    span,
+                ),
+            };
+            if c == Complex::new(1.0, 0.0) {
+                power
+            } else if c == Complex::new(-1.0, 0.0) {
+                Expr::Neg(Box::new(power))
+            } else {
+                Expr::Binary(
+                    Operator::Mul,
+                    Box::new(Expr::Number(c)),
+                    Box::new(power),
+                    span,
+                )
+            }
+        };
+        let (k, c) = match terms.next() {
+            Some((k, c)) => (k, *c),
+            None => return Expr::Number(Complex::zero()),
+        };
+        terms.fold(term(k, c), |sum, (k, c)| {
+            // Negative real and imaginary coefficients are subtracted
+            let negative =
+                c.im == 0.0 && c.re < 0.0 || c.re == 0.0 && c.im < 0.0;
+            let (op, c) = if negative {
+                (Operator::Sub, -c)
+            } else {
+                (Operator::Add, *c)
+            };
+            Expr::Binary(op, Box::new(sum), Box::new(term(k, c)), span)
+        })
+    }
+}
+
+/// Expands expressions into polynomials in one variable.
+struct Expander<'a, E: ?Sized> {
+    variable: &'a str,
+    env: &'a E,
+    context: &'a Context<'a>,
+}
+
+impl<'a, E: Env<f64> + ?Sized> Expander<'a, E> {
+    fn expand(&self, expr: &Expr) -> Result<Polynomial, Error> {
+        let not_polynomial = |span| {
+            Error::new(
+                ErrorKind::NotPolynomial(self.variable.to_string()),
+                span,
+            )
+        };
+        if !expr.contains_variable(self.variable) {
+            let c = expr.eval_with(self.env, self.context)?;
+            return Ok(Polynomial::new(vec![c]));
+        }
+        let polynomial = match expr {
+            Expr::Variable(..) => {
+                Polynomial::new(vec![Complex::zero(), Complex::new(1.0, 0.0)])
+            }
+            Expr::Neg(expr) => {
+                self.expand(expr)?.scale(Complex::new(-1.0, 0.0))
+            }
+            Expr::Binary(op, left, right, span) => match op {
+                Operator::Add => self.expand(left)?.add(&self.expand(right)?),
+                Operator::Sub => self
+                    .expand(left)?
+                    .add(&self.expand(right)?.scale(Complex::new(-1.0, 0.0))),
+                Operator::Mul => self.expand(left)?.mul(&self.expand(right)?),
+                Operator::Div => {
+                    if right.contains_variable(self.variable) {
+                        return Err(not_polynomial(*span));
+                    }
+                    let divisor = right.eval_with(self.env, self.context)?;
+                    if divisor.is_zero() {
+                        return Err(Error::new(
+                            ErrorKind::DivisionByZero,
+                            *span,
+                        ));
+                    }
+                    self.expand(left)?.scale(divisor.inv())
+                }
+                Operator::Pow => {
+                    if right.contains_variable(self.variable) {
+                        return Err(not_polynomial(*span));
+                    }
+                    let n = right.eval_with(self.env, self.context)?;
+                    if n.im != 0.0 || n.re.fract() != 0.0 || n.re < 0.0 {
+                        return Err(not_polynomial(*span));
+                    }
+                    let base = self.expand(left)?;
+                    if base.degree() as f64 * n.re > MAX_DEGREE as f64 {
+                        return Err(Error::new(
+                            ErrorKind::DegreeTooHigh(MAX_DEGREE),
+                            *span,
+                        ));
+                    }
+                    // Saturated above u64::MAX, where only the constants
+                    // 0 and 1 have finite powers
+                    base.pow(n.re as u64)
+                }
+            },
+            // The variable inside a function, which is not a polynomial
+            _ => {
+                return Err(not_polynomial(variable_span(expr, self.variable)))
+            }
+        };
+        if polynomial.degree() > MAX_DEGREE {
+            return Err(Error::new(
+                ErrorKind::DegreeTooHigh(MAX_DEGREE),
+                variable_span(expr, self.variable),
+            ));
+        }
+        Ok(polynomial)
+    }
+}
+
+/// The span of the first occurrence of `variable` in `expr`.
+fn variable_span(expr: &Expr, variable: &str) -> Span {
+    match expr {
+        Expr::Variable(name, span) if name == variable => *span,
+        Expr::Neg(expr) => variable_span(expr, variable),
+        Expr::Binary(_, left, right, _) => {
+            if left.contains_variable(variable) {
+                variable_span(left, variable)
+            } else {
+                variable_span(right, variable)
+            }
+        }
+        Expr::Call(_, args)
+        | Expr::UserCall(_, args, _)
+        | Expr::Matrix(args, _) => args
+            .iter()
+            .find(|arg| arg.contains_variable(variable))
+            .map_or(Span::default(), |arg| variable_span(arg, variable)),
+        _ => Span::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::definitions::Definitions;
+    use crate::parser::symbolic::{parse, parse_list};
+    use std::collections::HashMap;
+
+    fn polynomial(input: &str) -> Result<Polynomial, Error> {
+        let definitions = Definitions::default();
+        let mut variables = HashMap::new();
+        variables.insert("a".to_string(), Complex::new(2.0, 0.0));
+        Polynomial::from_expr(
+            &parse(input).unwrap(),
+            "z",
+            &variables,
+            &Context::new(&definitions),
+        )
+    }
+
+    fn assert_roots(input: &str, expected: &[(Complex<f64>, usize)]) {
+        let roots = polynomial(input).unwrap().roots().unwrap();
+        assert_eq!(roots.len(), expected.len(), "{}: {:?}", input, roots);
+        for (root, (value, multiplicity)) in roots.iter().zip(expected) {
+            assert!(
+                (root.value - value).norm() < 1e-9
+                    && root.multiplicity == *multiplicity,
+                "{}: {:?}",
+                input,
+                roots
+            );
+        }
+    }
+
+    #[test]
+    fn expands_polynomials() {
+        let expanded = |input: &str| polynomial(input).unwrap().to_expr("z");
+        assert_eq!(expanded("(z - 1)(z + 1)").to_string(), "z^2 - 1");
+        assert_eq!(expanded("a z^3/2 - i z + 1").to_string(), "z^3 - i*z + 1");
+        assert_eq!(expanded("(z + i)^2").to_string(), "z^2 + 2i*z - 1");
+        assert_eq!(
+            expanded("(z + 1)^5").to_string(),
+            "z^5 + 5*z^4 + 10*z^3 + 10*z^2 + 5*z + 1"
+        );
+        // Constant powers take a product per binary digit of the exponent
+        assert_eq!(expanded("z + (z - z - 1)^1e12").to_string(), "z + 1");
+        assert_eq!(expanded("z (z - z + 1)^1e300").to_string(), "z");
+        let coefficients = parse_list("1, -2, a + i", &Definitions::default())
+            .unwrap()
+            .iter()
+            .map(|c| c.eval(&[("a", Complex::new(2.0, 0.0))][..]).unwrap())
+            .collect();
+        assert_eq!(
+            Polynomial::from_coefficients(coefficients)
+                .to_expr("z")
+                .to_string(),
+            "z^2 - 2*z + (2 + i)"
+        );
+    }
+
+    #[test]
+    fn finds_roots() {
+        let h = 3f64.sqrt() / 2.0;
+        assert_roots(
+            "z^3 - 1",
+            &[
+                (Complex::new(-0.5, -h), 1),
+                (Complex::new(-0.5, h), 1),
+                (Complex::new(1.0, 0.0), 1),
+            ],
+        );
+        assert_roots(
+            "z^2 (z - 2)",
+            &[(Complex::zero(), 2), (Complex::new(2.0, 0.0), 1)],
+        );
+        assert_roots("3", &[]);
+        let wilkinson = (1..=10)
+            .map(|k| format!("(z - {})", k))
+            .collect::<Vec<_>>()
+            .join("");
+        let expected: Vec<_> =
+            (1..=10).map(|k| (Complex::new(k as f64, 0.0), 1)).collect();
+        assert_roots(&wilkinson, &expected);
+    }
+
+    #[test]
+    fn finds_repeated_roots() {
+        assert_roots(
+            "(z - 1)^3 (z + 2i)",
+            &[(Complex::new(0.0, -2.0), 1), (Complex::new(1.0, 0.0), 3)],
+        );
+        assert_roots(
+            "(z^2 + 1)^2 (z - 0.5)",
+            &[
+                (Complex::new(0.0, -1.0), 2),
+                (Complex::new(0.0, 1.0), 2),
+                (Complex::new(0.5, 0.0), 1),
+            ],
+        );
+        assert_roots("(z - 1 - i)^5", &[(Complex::new(1.0, 1.0), 5)]);
+    }
+
+    #[test]
+    fn rejects_other_expressions() {
+        let kind = |input: &str| polynomial(input).unwrap_err();
+        let not_polynomial = ErrorKind::NotPolynomial("z".to_string());
+        assert_eq!(kind("sin(z) + 1").kind, not_polynomial);
+        assert_eq!(kind("sin(z) + 1").span, Span::new(4, 5));
+        assert_eq!(kind("1/z").kind, not_polynomial);
+        assert_eq!(kind("z^0.5").kind, not_polynomial);
+        assert_eq!(kind("z^1000").kind, ErrorKind::DegreeTooHigh(MAX_DEGREE));
+        assert_eq!(
+            kind("(z^2 + 1)^1e12").kind,
+            ErrorKind::DegreeTooHigh(MAX_DEGREE)
+        );
+        assert_eq!(
+            polynomial("0 z").unwrap().roots().unwrap_err().kind,
+            ErrorKind::ZeroPolynomial
+        );
+    }
+}
@@ -0,0 +1,999 @@
+use crate::parser::ast::{not_inlined, Constant, Expr, Operator};
+use crate::parser::branches::MAX_BRANCHES;
+use crate::parser::definitions::Definitions;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+use crate::parser::lexer::Span;
+use dashu_float::ops::{Abs, SquareRoot};
+use dashu_float::round::mode::HalfAway;
+use dashu_float::{DBig, FBig};
+use num_complex::Complex;
+use std::collections::HashMap;
+use std::f64::consts::{LOG10_2, LOG2_10};
+use std::str::FromStr;
+
+/// Binary floats rounding to the nearest value, their precision is in bits.
+pub type Float = FBig<HalfAway>;
+
+/// Significant digits used when none are asked for.
+pub const DEFAULT_DIGITS: usize = 50;
+
+/// Most significant digits an evaluation may ask for.
+pub const MAX_DIGITS: usize = 1000;
+
+/// Bits computed beyond the requested digits, which absorb the rounding
+/// errors of the operations so the digits shown are right.
+const GUARD_BITS: usize = 32;
+
+/// The largest arguments of `exp` are below `2^MAX_EXP_MAGNITUDE`, past
+/// them the exponent of `e^x` does not fit in a float.
+const MAX_EXP_MAGNITUDE: isize = 60;
+
+/// A complex number with arbitrary precision parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BigComplex {
+    pub re: Float,
+    pub im: Float,
+}
+
+impl BigComplex {
+    pub fn new(re: Float, im: Float) -> BigComplex {
+        BigComplex { re, im }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.re.repr().is_zero() && self.im.repr().is_zero()
+    }
+
+    fn is_real(&self) -> bool {
+        self.im.repr().is_zero()
+    }
+
+    /// The value rounded to double precision, for the pages that use it.
+    pub fn to_f64(&self) -> Complex<f64> {
+        Complex::new(self.re.to_f64().value(), self.im.to_f64().value())
+    }
+
+    fn conj(&self) -> BigComplex {
+        BigComplex::new(self.re.clone(), -&self.im)
+    }
+
+    fn neg(&self) -> BigComplex {
+        BigComplex::new(-&self.re, -&self.im)
+    }
+
+    fn add(&self, w: &BigComplex) -> BigComplex {
+        BigComplex::new(&self.re + &w.re, &self.im + &w.im)
+    }
+
+    fn sub(&self, w: &BigComplex) -> BigComplex {
+        BigComplex::new(&self.re - &w.re, &self.im - &w.im)
+    }
+
+    fn mul(&self, w: &BigComplex) -> BigComplex {
+        BigComplex::new(
+            &self.re * &w.re - &self.im * &w.im,
+            &self.re * &w.im + &self.im * &w.re,
+        )
+    }
+
+    /// Multiplies by `i`.
+    fn rotate(&self) -> BigComplex {
+        BigComplex::new(-&self.im, self.re.clone())
+    }
+
+    fn scale(&self, x: &Float) -> BigComplex {
+        BigComplex::new(&self.re * x, &self.im * x)
+    }
+
+    fn norm_sqr(&self) -> Float {
+        &self.re * &self.re + &self.im * &self.im
+    }
+}
+
+/// Evaluates expressions with a chosen number of significant digits.
+///
+/// It supports the operators and the elementary functions, `sin` to
+/// `atanh`, `exp`, `ln`, roots and powers, the component and rounding
+/// functions and `log`, `atan2`, `min` and `max`, all on their principal
+/// branch unless one is asked for. The special functions are only available
+/// in single and double precision.
+///
+/// Literals are stored in double precision by the parser, they are read
+/// back as the shortest decimal that gives the same double, which is the
+/// one typed for inputs of up to 15 significant digits.
+pub struct Precise {
+    digits: usize,
+    bits: usize,
+    pi: Float,
+}
+
+impl Precise {
+    /// An evaluator for `digits` significant digits, kept between 1 and
+    /// [`MAX_DIGITS`].
+    pub fn new(digits: usize) -> Precise {
+        let digits = digits.clamp(1, MAX_DIGITS);
+        let bits = digits_to_bits(digits) + GUARD_BITS;
+        Precise {
+            digits,
+            bits,
+            pi: pi(bits + GUARD_BITS),
+        }
+    }
+
+    pub fn digits(&self) -> usize {
+        self.digits
+    }
+
+    fn int(&self, n: i64) -> Float {
+        Float::from(n).with_precision(self.bits).value()
+    }
+
+    fn zero(&self) -> BigComplex {
+        BigComplex::new(self.int(0), self.int(0))
+    }
+
+    fn one(&self) -> BigComplex {
+        BigComplex::new(self.int(1), self.int(0))
+    }
+
+    fn real(&self, x: Float) -> BigComplex {
+        BigComplex::new(x, self.int(0))
+    }
+
+    fn pi(&self) -> Float {
+        self.pi.clone().with_precision(self.bits).value()
+    }
+
+    /// Reads a decimal number, like `0.1` or `6.02e23`, to the precision.
+    pub fn decimal(&self, text: &str) -> Option<Float> {
+        let x = DBig::from_str(text.trim()).ok()?;
+        Some(x.with_base_and_precision::<2>(self.bits).value())
+    }
+
+    /// A double, as the shortest decimal that rounds to it.
+    pub fn from_f64(&self, z: Complex<f64>) -> Result<BigComplex, Error> {
+        let part = |x: f64| {
+            self.decimal(&format!("{:e}", x)).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::MalformedNumber(x.to_string()),
+                    Span::default(),
+                )
+            })
+        };
+        Ok(BigComplex::new(part(z.re)?, part(z.im)?))
+    }
+
+    /// A literal as it is written, like `3.14159265358979323846` or
+    /// `2.5e-30i`.
+    pub fn from_literal(&self, text: &str) -> Result<BigComplex, Error> {
+        let malformed = || {
+            Error::new(
+                ErrorKind::MalformedNumber(text.to_string()),
+                Span::default(),
+            )
+        };
+        Ok(match text.strip_suffix('i') {
+            Some(digits) => BigComplex::new(
+                self.int(0),
+                self.decimal(digits).ok_or_else(malformed)?,
+            ),
+            None => BigComplex::new(
+                self.decimal(text).ok_or_else(malformed)?,
+                self.int(0),
+            ),
+        })
+    }
+
+    /// `r e^(iθ)`.
+    pub fn from_polar(&self, r: &Float, theta: &Float) -> BigComplex {
+        let (sin, cos) = self.sin_cos(theta);
+        BigComplex::new(r * cos, r * sin)
+    }
+
+    /// Evaluates `expr` with user functions of `definitions` and the values
+    /// of `variables`.
+    pub fn eval(
+        &self,
+        expr: &Expr,
+        variables: &HashMap<String, BigComplex>,
+        definitions: &Definitions,
+    ) -> Result<BigComplex, Error> {
+        self.eval_inlined(&expr.inline(definitions)?, variables)
+    }
+
+    fn eval_inlined(
+        &self,
+        expr: &Expr,
+        variables: &HashMap<String, BigComplex>,
+    ) -> Result<BigComplex, Error> {
+        let eval = |expr: &Expr| self.eval_inlined(expr, variables);
+        match expr {
+            Expr::Number(z) => self.from_f64(*z),
+            Expr::Decimal(_, text) => self.from_literal(text),
+            Expr::Constant(Constant::Pi) => Ok(self.real(self.pi())),
+            Expr::Constant(Constant::E) => Ok(self.real(self.int(1).exp())),
+            Expr::Constant(Constant::Tau) => {
+                Ok(self.real(self.pi() * self.int(2)))
+            }
+            Expr::Constant(Constant::Phi) => {
+                let root = self.int(5).sqrt();
+                Ok(self.real((self.int(1) + root) / self.int(2)))
+            }
+            Expr::Variable(name, span) => {
+                variables.get(name).cloned().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::UnknownIdentifier(name.clone()),
+                        *span,
+                    )
+                })
+            }
+            Expr::Neg(expr) => Ok(eval(expr)?.neg()),
+            Expr::Binary(op, left, right, span) => {
+                let z1 = eval(left)?;
+                let z2 = eval(right)?;
+                match op {
+                    Operator::Add => Ok(z1.add(&z2)),
+                    Operator::Sub => Ok(z1.sub(&z2)),
+                    Operator::Mul => Ok(z1.mul(&z2)),
+                    Operator::Div => self.div(&z1, &z2, *span),
+                    Operator::Pow => self.pow(&z1, &z2, *span),
+                }
+            }
+            Expr::Call(f, args) => {
+                f.check_scalar()?;
+                let args =
+                    args.iter().map(eval).collect::<Result<Vec<_>, Error>>()?;
+                self.apply(*f, &args)
+            }
+            Expr::Matrix(_, span) => {
+                Err(Error::new(ErrorKind::NotScalar, *span))
+            }
+            Expr::UserCall(name, _, span) => Err(not_inlined(name, *span)),
+        }
+    }
+
+    /// Every value of `expr` when it is a root or a rational power, like
+    /// [`calculate_branches`](crate::parser::evaluations::calculate_branches)
+    /// does in single and double precision.
+    pub fn eval_branches(
+        &self,
+        expr: &Expr,
+        variables: &HashMap<String, BigComplex>,
+        definitions: &Definitions,
+    ) -> Result<Vec<BigComplex>, Error> {
+        let expr = expr.inline(definitions)?;
+        let eval = |expr: &Expr| self.eval_inlined(expr, variables);
+        let (base, exponent) = match &expr {
+            Expr::Binary(Operator::Pow, base, exponent, _) => {
+                (base.as_ref(), eval(exponent)?)
+            }
+            Expr::Call(Function::Root, args) if args.len() == 2 => {
+                let n = eval(&args[1])?;
+                (&args[0], self.div(&self.one(), &n, Span::default())?)
+            }
+            Expr::Call(Function::Sqrt, args) if args.len() == 1 => {
+                (&args[0], self.real(self.int(1) / self.int(2)))
+            }
+            Expr::Call(Function::Cbrt, args) => {
+                (&args[0], self.real(self.int(1) / self.int(3)))
+            }
+            _ => return Ok(vec![eval(&expr)?]),
+        };
+        let q = if exponent.is_real() {
+            let exponent = exponent.re.to_f64().value();
+            (1..=MAX_BRANCHES).find(|q| {
+                let p = exponent * *q as f64;
+                (p - p.round()).abs() < 1e-6
+            })
+        } else {
+            None
+        };
+        let q = match q {
+            Some(q) => q,
+            None => return Ok(vec![eval(&expr)?]),
+        };
+        let z = eval(base)?;
+        if z.is_zero() {
+            return Ok(vec![eval(&expr)?]);
+        }
+        (0..q)
+            .map(|k| self.exp(&exponent.mul(&self.ln(&z, k as i64))))
+            .collect()
+    }
+
+    /// Formats `z` like `num_complex` does, `a+bi`, with the digits of the
+    /// evaluator. A part that is below the last digit of the other one is
+    /// shown as zero, as it is only rounding error.
+    pub fn format(&self, z: &BigComplex) -> String {
+        let digits_bits = digits_to_bits(self.digits) as isize;
+        let re_magnitude = magnitude(&z.re);
+        let im_magnitude = magnitude(&z.im);
+        let re = if re_magnitude + digits_bits < im_magnitude {
+            "0".to_string()
+        } else {
+            self.format_real(&z.re)
+        };
+        let im = if im_magnitude + digits_bits < re_magnitude {
+            "0".to_string()
+        } else {
+            self.format_real(&z.im)
+        };
+        match im.strip_prefix('-') {
+            Some(im) => format!("{}-{}i", re, im),
+            None => format!("{}+{}i", re, im),
+        }
+    }
+
+    /// The modulus and principal argument of `z`, formatted with the digits
+    /// of the evaluator.
+    pub fn format_polar(&self, z: &BigComplex) -> (String, String) {
+        let modulus = z.norm_sqr().sqrt();
+        let argument = self.atan2(&z.im, &z.re);
+        (self.format_real(&modulus), self.format_real(&argument))
+    }
+
+    fn div(
+        &self,
+        z1: &BigComplex,
+        z2: &BigComplex,
+        span: Span,
+    ) -> Result<BigComplex, Error> {
+        if z2.is_zero() {
+            return Err(Error::new(ErrorKind::DivisionByZero, span));
+        }
+        let norm = z2.norm_sqr();
+        let z = z1.mul(&z2.conj());
+        Ok(BigComplex::new(z.re / &norm, z.im / &norm))
+    }
+
+    fn inv(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        self.div(&self.one(), z, Span::default())
+    }
+
+    /// `z^w`, by repeated squaring for integer exponents so they stay exact,
+    /// and as `exp(w ln(z))` otherwise.
+    fn pow(
+        &self,
+        z: &BigComplex,
+        w: &BigComplex,
+        span: Span,
+    ) -> Result<BigComplex, Error> {
+        if w.is_real() && w.re.repr().is_int() {
+            let n = w.re.to_f64().value();
+            if n.abs() <= (1u64 << 32) as f64 {
+                let mut n = n.abs() as u64;
+                let mut base = z.clone();
+                let mut result = self.one();
+                while n > 0 {
+                    if n & 1 != 0 {
+                        result = result.mul(&base);
+                    }
+                    base = base.mul(&base);
+                    n /= 2;
+                }
+                if w.re < self.int(0) {
+                    return self.div(&self.one(), &result, span);
+                }
+                return Ok(result);
+            }
+        }
+        if z.is_zero() {
+            if w.re > self.int(0) {
+                return Ok(self.zero());
+            }
+            return Err(Error::new(ErrorKind::DivisionByZero, span));
+        }
+        self.exp(&w.mul(&self.ln(z, 0)))
+    }
+
+    /// The `k`th branch of the logarithm, which is undefined at zero.
+    fn ln(&self, z: &BigComplex, k: i64) -> BigComplex {
+        let turn = self.pi() * self.int(2 * k);
+        let re = z.norm_sqr().ln() / self.int(2);
+        BigComplex::new(re, self.atan2(&z.im, &z.re) + turn)
+    }
+
+    fn checked_ln(&self, z: &BigComplex, k: i64) -> Result<BigComplex, Error> {
+        if z.is_zero() {
+            return Err(Error::new(ErrorKind::DivisionByZero, Span::default()));
+        }
+        Ok(self.ln(z, k))
+    }
+
+    /// `e^z`, which is zero for real parts so negative that its exponent
+    /// does not fit, and too large for positive ones.
+    fn exp(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        if magnitude(&z.re) > MAX_EXP_MAGNITUDE {
+            if z.re < self.int(0) {
+                return Ok(self.zero());
+            }
+            return Err(overflow("exp"));
+        }
+        let (sin, cos) = self.sin_cos(&z.im);
+        let r = z.re.exp();
+        Ok(BigComplex::new(&r * cos, r * sin))
+    }
+
+    /// The principal square root, with the cut along the negative real axis
+    /// where it takes the upper side.
+    fn sqrt(&self, z: &BigComplex) -> BigComplex {
+        if z.is_zero() {
+            return self.zero();
+        }
+        let r = z.norm_sqr().sqrt();
+        let two = self.int(2);
+        let t = ((r + z.re.clone().abs()) / &two).sqrt();
+        let u = z.im.clone().abs() / (&t * &two);
+        if z.re >= self.int(0) {
+            let im = &z.im / (&t * two);
+            BigComplex::new(t, im)
+        } else if z.im < self.int(0) {
+            BigComplex::new(u, -t)
+        } else {
+            BigComplex::new(u, t)
+        }
+    }
+
+    fn sin(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let (sin, cos) = self.sin_cos(&z.re);
+        let (sinh, cosh) = self.sinh_cosh(&z.im, "sin")?;
+        Ok(BigComplex::new(sin * cosh, cos * sinh))
+    }
+
+    fn cos(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let (sin, cos) = self.sin_cos(&z.re);
+        let (sinh, cosh) = self.sinh_cosh(&z.im, "cos")?;
+        Ok(BigComplex::new(cos * cosh, -(sin * sinh)))
+    }
+
+    fn sinh(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let (sin, cos) = self.sin_cos(&z.im);
+        let (sinh, cosh) = self.sinh_cosh(&z.re, "sinh")?;
+        Ok(BigComplex::new(sinh * cos, cosh * sin))
+    }
+
+    fn cosh(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let (sin, cos) = self.sin_cos(&z.im);
+        let (sinh, cosh) = self.sinh_cosh(&z.re, "cosh")?;
+        Ok(BigComplex::new(cosh * cos, sinh * sin))
+    }
+
+    /// `-i ln(iz + sqrt(1 - z^2))`, as in `num_complex`.
+    fn asin(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let root = self.sqrt(&self.one().sub(&z.mul(z)));
+        let ln = self.checked_ln(&z.rotate().add(&root), 0)?;
+        Ok(ln.rotate().neg())
+    }
+
+    /// `-i ln(z + i sqrt(1 - z^2))`, as in `num_complex`.
+    fn acos(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let root = self.sqrt(&self.one().sub(&z.mul(z)));
+        let ln = self.checked_ln(&z.add(&root.rotate()), 0)?;
+        Ok(ln.rotate().neg())
+    }
+
+    /// `(ln(1 + iz) - ln(1 - iz)) / 2i`, as in `num_complex`.
+    fn atan(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let iz = z.rotate();
+        let ln = self
+            .checked_ln(&self.one().add(&iz), 0)?
+            .sub(&self.checked_ln(&self.one().sub(&iz), 0)?);
+        Ok(ln.rotate().neg().scale(&(self.int(1) / self.int(2))))
+    }
+
+    /// `ln(z + sqrt(z^2 + 1))`.
+    fn asinh(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let root = self.sqrt(&z.mul(z).add(&self.one()));
+        self.checked_ln(&z.add(&root), 0)
+    }
+
+    /// `2 ln(sqrt((z + 1)/2) + sqrt((z - 1)/2))`, as in `num_complex`.
+    fn acosh(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let half = self.int(1) / self.int(2);
+        let plus = self.sqrt(&z.add(&self.one()).scale(&half));
+        let minus = self.sqrt(&z.sub(&self.one()).scale(&half));
+        Ok(self.checked_ln(&plus.add(&minus), 0)?.scale(&self.int(2)))
+    }
+
+    /// `(ln(1 + z) - ln(1 - z)) / 2`.
+    fn atanh(&self, z: &BigComplex) -> Result<BigComplex, Error> {
+        let ln = self
+            .checked_ln(&self.one().add(z), 0)?
+            .sub(&self.checked_ln(&self.one().sub(z), 0)?);
+        Ok(ln.scale(&(self.int(1) / self.int(2))))
+    }
+
+    fn apply(
+        &self,
+        f: Function,
+        args: &[BigComplex],
+    ) -> Result<BigComplex, Error> {
+        let z = &args[0];
+        let branch = |i: usize| {
+            args.get(i)
+                .map_or(0, |k| round(&k.re).to_f64().value() as i64)
+        };
+        let div = |z1: &BigComplex, z2: &BigComplex| {
+            self.div(z1, z2, Span::default())
+        };
+        let real = |x: Float| Ok(self.real(x));
+        let component_wise =
+            |f: fn(&Float) -> Float| Ok(BigComplex::new(f(&z.re), f(&z.im)));
+        match f {
+            Function::Sin => self.sin(z),
+            Function::Cos => self.cos(z),
+            Function::Tan => div(&self.sin(z)?, &self.cos(z)?),
+            Function::Csc => self.inv(&self.sin(z)?),
+            Function::Sec => self.inv(&self.cos(z)?),
+            Function::Cot => div(&self.cos(z)?, &self.sin(z)?),
+            Function::Asin => self.asin(z),
+            Function::Acos => self.acos(z),
+            Function::Atan => self.atan(z),
+            Function::Sinh => self.sinh(z),
+            Function::Cosh => self.cosh(z),
+            Function::Tanh => div(&self.sinh(z)?, &self.cosh(z)?),
+            Function::Asinh => self.asinh(z),
+            Function::Acosh => self.acosh(z),
+            Function::Atanh => self.atanh(z),
+            Function::Inv => self.inv(z),
+            Function::Conj => Ok(z.conj()),
+            Function::Exp => self.exp(z),
+            Function::Cis => self.exp(&z.rotate()),
+            Function::Ln => self.checked_ln(z, branch(1)),
+            // The odd branches are the principal one negated
+            Function::Sqrt if branch(1) % 2 != 0 => Ok(self.sqrt(z).neg()),
+            Function::Sqrt => Ok(self.sqrt(z)),
+            Function::Cbrt => self.root(z, &self.real(self.int(3)), 0),
+            Function::Re => real(z.re.clone()),
+            Function::Im => real(z.im.clone()),
+            Function::Abs => real(z.norm_sqr().sqrt()),
+            Function::Arg => real(self.atan2(&z.im, &z.re)),
+            Function::Norm => real(z.norm_sqr()),
+            Function::Sgn if z.is_zero() => Ok(z.clone()),
+            Function::Sgn => Ok(z.scale(&(self.int(1) / z.norm_sqr().sqrt()))),
+            Function::Floor => component_wise(Float::floor),
+            Function::Ceil => component_wise(Float::ceil),
+            Function::Round => component_wise(round),
+            Function::Frac => component_wise(frac),
+            Function::Log => {
+                div(&self.checked_ln(z, 0)?, &self.checked_ln(&args[1], 0)?)
+            }
+            Function::Pow => self.pow(z, &args[1], Span::default()),
+            Function::Root => self.root(z, &args[1], branch(2)),
+            Function::Atan2 => real(self.atan2(&z.re, &args[1].re)),
+            Function::Min | Function::Max => {
+                Ok(f.extremum(args, |w| &w.re).clone())
+            }
+            Function::Gamma
+            | Function::Lgamma
+            | Function::Zeta
+            | Function::Erf
+            | Function::Erfc
+            | Function::Besselj
+            | Function::Lambertw => Err(Error::new(
+                ErrorKind::NotPrecise(f.name().to_string()),
+                Span::default(),
+            )),
+            // A number is a 1x1 matrix
+            Function::Det
+            | Function::Trace
+            | Function::Transpose
+            | Function::Eig => Ok(z.clone()),
+            // Fails with the shape error, as `eval` does before applying
+            Function::Mobius => f.check_scalar().map(|()| z.clone()),
+        }
+    }
+
+    /// The `k`th branch of the `n`th root, `exp(ln(z, k) / n)`.
+    fn root(
+        &self,
+        z: &BigComplex,
+        n: &BigComplex,
+        k: i64,
+    ) -> Result<BigComplex, Error> {
+        let w = self.inv(n)?;
+        if z.is_zero() {
+            return self.pow(z, &w, Span::default());
+        }
+        self.exp(&w.mul(&self.ln(z, k)))
+    }
+
+    /// The sine and cosine of `x`, by their series once `x` is brought into
+    /// `[-π, π]`.
+    fn sin_cos(&self, x: &Float) -> (Float, Float) {
+        if x.repr().is_zero() {
+            return (self.int(0), self.int(1));
+        }
+        // Large arguments lose their integer part of turns, which takes as
+        // many more bits of π
+        let extra = magnitude(x).max(0) as usize;
+        let bits = self.bits + extra;
+        let pi = if extra > GUARD_BITS {
+            pi(bits + GUARD_BITS)
+        } else {
+            self.pi.clone()
+        };
+        let x = x.clone().with_precision(bits).value();
+        let turn = pi * Float::from(2);
+        let turns = round(&(&x / &turn));
+        let x = (x - turn * turns).with_precision(self.bits).value();
+
+        let epsilon = self.epsilon();
+        let mut sin = self.int(0);
+        let mut cos = self.int(0);
+        let mut term = self.int(1);
+        let mut n = 0;
+        while term.clone().abs() > epsilon {
+            match n % 4 {
+                0 => cos += &term,
+                1 => sin += &term,
+                2 => cos -= &term,
+                _ => sin -= &term,
+            }
+            n += 1;
+            term = term * &x / self.int(n);
+        }
+        (sin, cos)
+    }
+
+    /// `(e^x - e^-x) / 2` and `(e^x + e^-x) / 2`, from `e^x - 1` so small
+    /// arguments keep their digits.
+    fn sinh_cosh(
+        &self,
+        x: &Float,
+        name: &str,
+    ) -> Result<(Float, Float), Error> {
+        // Both are about e^|x| / 2, which overflows with `exp`
+        if magnitude(x) > MAX_EXP_MAGNITUDE {
+            return Err(overflow(name));
+        }
+        let two = self.int(2);
+        let up = x.exp_m1();
+        let down = (-x).exp_m1();
+        let sinh = (&up - &down) / &two;
+        let cosh = (up + down + &two) / two;
+        Ok((sinh, cosh))
+    }
+
+    /// The angle of `(x, y)`, in `(-π, π]`.
+    fn atan2(&self, y: &Float, x: &Float) -> Float {
+        let zero = self.int(0);
+        if y.repr().is_zero() {
+            return if *x < zero { self.pi() } else { zero };
+        }
+        let half_turn = self.pi() / self.int(2);
+        if y.clone().abs() > x.clone().abs() {
+            let angle = half_turn - self.atan_real(&(x / y));
+            return if *y < zero { angle - self.pi() } else { angle };
+        }
+        let angle = self.atan_real(&(y / x));
+        if *x > zero {
+            angle
+        } else if *y < zero {
+            angle - self.pi()
+        } else {
+            angle + self.pi()
+        }
+    }
+
+    /// The arctangent of `t` in `[-1, 1]`, halving the angle until its
+    /// series converges fast, `atan(t) = 2 atan(t / (1 + sqrt(1 + t^2)))`.
+    fn atan_real(&self, t: &Float) -> Float {
+        let one = self.int(1);
+        let small = Float::from_parts(1.into(), -12);
+        let mut t = t.clone();
+        let mut halvings = 0;
+        while t.clone().abs() > small {
+            t = &t / (&one + (&one + &t * &t).sqrt());
+            halvings += 1;
+        }
+
+        let epsilon = self.epsilon();
+        let square = &t * &t;
+        let mut power = t;
+        let mut sum = self.int(0);
+        let mut n = 1;
+        while power.clone().abs() > epsilon {
+            sum += &power / self.int(n);
+            power = -(power * &square);
+            n += 2;
+        }
+        sum * self.int(1 << halvings)
+    }
+
+    /// `x` with at most the digits of the evaluator, in positional notation
+    /// unless that would need many zeros.
+    fn format_real(&self, x: &Float) -> String {
+        if x.repr().is_zero() {
+            return "0".to_string();
+        }
+        // The power of ten of the first digit, the estimate from the binary
+        // exponent can be one short
+        let digits = self.digits as isize;
+        let mut first = ((magnitude(x) - 1) as f64 * LOG10_2).floor() as isize;
+        let text = loop {
+            let scaled = x * self.power_of_ten(digits - 1 - first);
+            let text = round(&scaled).to_int().value().to_string();
+            if text.trim_start_matches('-').len() as isize > digits {
+                first += 1;
+            } else {
+                break text;
+            }
+        };
+        let exponent = first - (digits - 1);
+        let (sign, text) = match text.strip_prefix('-') {
+            Some(text) => ("-", text),
+            None => ("", text.as_str()),
+        };
+        let significant = text.trim_end_matches('0');
+        let exponent = exponent + (text.len() - significant.len()) as isize;
+        // Digits before the decimal point
+        let point = significant.len() as isize + exponent;
+
+        if point > digits || point < -5 {
+            let (first, rest) = significant.split_at(1);
+            let rest = if rest.is_empty() {
+                String::new()
+            } else {
+                format!(".{}", rest)
+            };
+            return format!("{}{}{}e{}", sign, first, rest, point - 1);
+        }
+        if exponent >= 0 {
+            let zeros = "0".repeat(exponent as usize);
+            return format!("{}{}{}", sign, significant, zeros);
+        }
+        if point > 0 {
+            let (integer, fraction) = significant.split_at(point as usize);
+            return format!("{}{}.{}", sign, integer, fraction);
+        }
+        let zeros = "0".repeat(-point as usize);
+        format!("{}0.{}{}", sign, zeros, significant)
+    }
+
+    fn power_of_ten(&self, n: isize) -> Float {
+        let ten = self.int(10);
+        if n >= 0 {
+            ten.powi(n.into())
+        } else {
+            self.int(1) / ten.powi((-n).into())
+        }
+    }
+
+    fn epsilon(&self) -> Float {
+        Float::from_parts(1.into(), -(self.bits as isize) - 8)
+    }
+}
+
+/// Rounds half away from zero, `FBig::round` misreads some numbers below
+/// one.
+fn round(x: &Float) -> Float {
+    let half = Float::from_parts(1.into(), -1);
+    if *x < Float::ZERO {
+        -(half - x).floor()
+    } else {
+        (x + half).floor()
+    }
+}
+
+/// `x - floor(x)`, unlike `fract` it is positive for negative `x`.
+fn frac(x: &Float) -> Float {
+    x - x.floor()
+}
+
+/// Bits that hold `digits` decimal digits.
+fn digits_to_bits(digits: usize) -> usize {
+    (digits as f64 * LOG2_10).ceil() as usize
+}
+
+fn overflow(name: &str) -> Error {
+    Error::new(ErrorKind::Overflow(name.to_string()), Span::default())
+}
+
+/// The power of two just above `|x|`.
+fn magnitude(x: &Float) -> isize {
+    if x.repr().is_zero() {
+        return isize::MIN / 2;
+    }
+    x.repr().exponent() + x.repr().digits() as isize
+}
+
+/// π by the Gauss-Legendre iteration, which doubles the correct digits on
+/// every step.
+fn pi(bits: usize) -> Float {
+    let int = |n: i64| Float::from(n).with_precision(bits).value();
+    let mut a = int(1);
+    let mut b = int(1) / int(2).sqrt();
+    let mut t = int(1) / int(4);
+    let mut p = int(1);
+    let mut correct = 1;
+    while correct < bits {
+        let next = (&a + &b) / int(2);
+        b = (&a * &b).sqrt();
+        let difference = &a - &next;
+        t -= &p * &difference * &difference;
+        p *= int(2);
+        a = next;
+        correct *= 2;
+    }
+    let sum = a + b;
+    &sum * &sum / (t * int(4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::parse;
+
+    const PI_60: &str =
+        "3.14159265358979323846264338327950288419716939937510582097494";
+
+    fn eval(precise: &Precise, input: &str) -> BigComplex {
+        precise
+            .eval(
+                &parse(input).unwrap(),
+                &HashMap::new(),
+                &Definitions::default(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn formats_requested_digits() {
+        let precise = Precise::new(60);
+        assert_eq!(
+            precise.format(&eval(&precise, "PI")),
+            format!("{}+0i", PI_60)
+        );
+        let precise = Precise::new(30);
+        assert_eq!(
+            precise.format(&eval(&precise, "sqrt(2) - i/3")),
+            "1.41421356237309504880168872421-0.333333333333333333333333333333i"
+        );
+        assert_eq!(precise.format(&eval(&precise, "0.1 + 0.2")), "0.3+0i");
+        assert_eq!(
+            precise.format(&eval(&precise, "2^100")),
+            "1.26765060022822940149670320538e30+0i"
+        );
+        assert_eq!(
+            precise.format(&eval(&precise, "-1/8000000")),
+            "-1.25e-7+0i"
+        );
+        assert_eq!(precise.format(&eval(&precise, "(1 + i)^2")), "0+2i");
+        // Literals keep the digits a double would round off
+        let precise = Precise::new(40);
+        let difference = precise.format(&eval(
+            &precise,
+            "1.2345678901234567890123456789 - 1.2345678901234567890123456788",
+        ));
+        assert!(
+            difference.starts_with("1.0000000000")
+                && difference.ends_with("e-28+0i"),
+            "{}",
+            difference
+        );
+        let precise = Precise::new(50);
+        let tiny = precise.decimal("1e-49").unwrap();
+        let z = eval(&precise, &format!("{} - PI", PI_60));
+        assert!(z.norm_sqr().sqrt() < tiny, "{:?}", z.to_f64());
+        assert_eq!(
+            precise.format(&eval(&precise, "0.1234567890123456789i + 1e-30i")),
+            "0+0.123456789012345678900000000001i"
+        );
+
+        let precise = Precise::new(20);
+        assert_eq!(
+            precise.format_polar(&eval(&precise, "-2 - 2i")),
+            (
+                "2.8284271247461900976".to_string(),
+                "-2.3561944901923449288".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn verifies_identities() {
+        let precise = Precise::new(100);
+        let tiny = precise.decimal("1e-100").unwrap();
+        for input in [
+            "exp(i*PI) + 1",
+            "sin(1)^2 + cos(1)^2 - 1",
+            "4*atan(1) - PI",
+            "e - exp(1)",
+            "sqrt(2)^2 - 2",
+            "ln(exp(2 + 3i)) - (2 + 3i)",
+            "asin(sin(0.5 + 0.25i)) - (0.5 + 0.25i)",
+            "tanh(atanh(0.3 - 0.2i)) - (0.3 - 0.2i)",
+            "root(-8, 3, 1) + 2",
+            "cosh(2i) - cos(2)",
+            "2∠45° - sqrt(2)(1 + i)",
+        ]
+        .iter()
+        {
+            let z = eval(&precise, input);
+            assert!(z.norm_sqr().sqrt() < tiny, "{} = {:?}", input, z.to_f64());
+        }
+    }
+
+    #[test]
+    fn agrees_with_double_precision() {
+        let precise = Precise::new(30);
+        let definitions = Definitions::default();
+        let variables: HashMap<String, Complex<f64>> = HashMap::new();
+        let context = crate::parser::ast::Context::new(&definitions);
+        for name in [
+            "sin", "cos", "tan", "csc", "sec", "cot", "asin", "acos", "atan",
+            "sinh", "cosh", "tanh", "asinh", "acosh", "atanh", "inv", "exp",
+            "cis", "ln", "sqrt", "cbrt", "abs", "arg", "sgn", "frac",
+        ]
+        .iter()
+        {
+            for z in ["2.5 - 1.25i", "-3.75 + 0.5i", "-1.5 - 0.5i", "-2"].iter()
+            {
+                let input = format!("{}({})", name, z);
+                let expr = parse(&input).unwrap();
+                let expected: Complex<f64> =
+                    expr.eval_with(&variables, &context).unwrap();
+                let found = eval(&precise, &input).to_f64();
+                assert!(
+                    (found - expected).norm()
+                        <= 1e-13 * (1.0 + expected.norm()),
+                    "{}: {} != {}",
+                    input,
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        let precise = Precise::new(20);
+        let definitions = Definitions::default();
+        let error = |input| {
+            precise
+                .eval(&parse(input).unwrap(), &HashMap::new(), &definitions)
+                .unwrap_err()
+                .kind
+        };
+        assert_eq!(error("1/(1 - 1)"), ErrorKind::DivisionByZero);
+        assert_eq!(error("ln(0)"), ErrorKind::DivisionByZero);
+        assert_eq!(
+            error("gamma(2)"),
+            ErrorKind::NotPrecise("gamma".to_string())
+        );
+        assert_eq!(
+            error("x + 1"),
+            ErrorKind::UnknownIdentifier("x".to_string())
+        );
+        // Exponents too large for a float, while tiny values are zero
+        assert_eq!(error("exp(1e19)"), ErrorKind::Overflow("exp".to_string()));
+        assert_eq!(
+            error("sin(1e19 i)"),
+            ErrorKind::Overflow("sin".to_string())
+        );
+        assert!(eval(&precise, "exp(-1e19)").is_zero());
+        assert!(eval(&precise, "2^(-1e19)").is_zero());
+    }
+
+    #[test]
+    fn lists_branches_precisely() {
+        let precise = Precise::new(40);
+        let branches = precise
+            .eval_branches(
+                &parse("(-16)^(1/4)").unwrap(),
+                &HashMap::new(),
+                &Definitions::default(),
+            )
+            .unwrap();
+        assert_eq!(branches.len(), 4);
+        let tiny = precise.decimal("1e-38").unwrap();
+        for root in branches.iter() {
+            let fourth = root.mul(root).mul(root).mul(root);
+            let error = fourth.add(&precise.real(precise.int(16)));
+            assert!(error.norm_sqr().sqrt() < tiny);
+        }
+    }
+}
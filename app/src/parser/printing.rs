@@ -0,0 +1,315 @@
+use crate::parser::ast::{Constant, Expr, Operator};
+use crate::parser::functions::Function;
+use num_complex::Complex;
+
+/// How tightly an expression binds when printed, following the parser:
+/// sums, products, negations, powers, then everything that needs no
+/// parentheses.
+fn binding(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary(Operator::Add, ..) | Expr::Binary(Operator::Sub, ..) => 1,
+        Expr::Binary(Operator::Mul, ..) | Expr::Binary(Operator::Div, ..) => 2,
+        Expr::Neg(_) => 3,
+        Expr::Number(z) if z.re != 0.0 && z.im != 0.0 => 1,
+        Expr::Number(z) if z.re < 0.0 || z.im < 0.0 => 3,
+        Expr::Binary(Operator::Pow, ..) => 4,
+        _ => 5,
+    }
+}
+
+/// The least binding the right operand of `op` needs to go without
+/// parentheses. A negation after an operator is always kept in them.
+fn right_binding(op: Operator, right: &Expr) -> u8 {
+    match (op, binding(right)) {
+        (_, 3) => 4,
+        (Operator::Add, _) | (Operator::Sub, _) => 2,
+        _ => 4,
+    }
+}
+
+fn left_binding(op: Operator) -> u8 {
+    match op {
+        Operator::Add | Operator::Sub => 1,
+        Operator::Mul | Operator::Div => 2,
+        Operator::Pow => 5,
+    }
+}
+
+fn number(z: Complex<f64>) -> String {
+    let imaginary = |im: f64| match im {
+        1.0 => "i".to_string(),
+        -1.0 => "-i".to_string(),
+        _ => format!("{}i", im),
+    };
+    if z.im == 0.0 {
+        format!("{}", z.re)
+    } else if z.re == 0.0 {
+        imaginary(z.im)
+    } else if z.im < 0.0 {
+        format!("{} - {}", z.re, imaginary(-z.im))
+    } else {
+        format!("{} + {}", z.re, imaginary(z.im))
+    }
+}
+
+/// Writes `expr`, in parentheses when it binds looser than `min`.
+fn write_operand(
+    f: &mut std::fmt::Formatter<'_>,
+    expr: &Expr,
+    min: u8,
+) -> std::fmt::Result {
+    if binding(expr) < min {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+fn write_call(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    args: &[Expr],
+) -> std::fmt::Result {
+    write_list(f, &format!("{}(", name), args, ")")
+}
+
+fn write_list(
+    f: &mut std::fmt::Formatter<'_>,
+    open: &str,
+    items: &[Expr],
+    close: &str,
+) -> std::fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, "{}", close)
+}
+
+/// Infix text that parses back to the same expression, with the fewest
+/// parentheses that keep it, like `-z^2 + 3*(z - 1)`.
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Number(z) => write!(f, "{}", number(*z)),
+            Expr::Decimal(_, text) => write!(f, "{}", text),
+            Expr::Constant(c) => write!(f, "{}", c.name()),
+            Expr::Variable(name, _) => write!(f, "{}", name),
+            Expr::Neg(expr) => {
+                write!(f, "-")?;
+                write_operand(f, expr, 4)
+            }
+            Expr::Binary(op, left, right, _) => {
+                write_operand(f, left, left_binding(*op))?;
+                match op {
+                    Operator::Add | Operator::Sub => {
+                        write!(f, " {} ", op.symbol())?
+                    }
+                    _ => write!(f, "{}", op.symbol())?,
+                }
+                write_operand(f, right, right_binding(*op, right))
+            }
+            Expr::Call(function, args) => write_call(f, function.name(), args),
+            Expr::UserCall(name, args, _) => write_call(f, name, args),
+            Expr::Matrix(elements, _) => write_list(f, "[", elements, "]"),
+        }
+    }
+}
+
+/// LaTeX source for `expr`, with fractions for divisions and the usual
+/// notation for roots, moduli, conjugates and the special functions.
+pub fn latex(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(z) => number(*z),
+        Expr::Decimal(_, text) => text.clone(),
+        Expr::Constant(Constant::Pi) => "\\pi".to_string(),
+        Expr::Constant(Constant::E) => "e".to_string(),
+        Expr::Constant(Constant::Tau) => "\\tau".to_string(),
+        Expr::Constant(Constant::Phi) => "\\varphi".to_string(),
+        Expr::Variable(name, _) if name.chars().count() > 1 => {
+            format!("\\mathrm{{{}}}", name)
+        }
+        Expr::Variable(name, _) => name.clone(),
+        Expr::Neg(expr) => format!("-{}", latex_operand(expr, 4)),
+        Expr::Binary(Operator::Div, left, right, _) => {
+            format!("\\frac{{{}}}{{{}}}", latex(left), latex(right))
+        }
+        Expr::Binary(Operator::Pow, base, exponent, _) => power(base, exponent),
+        Expr::Binary(op, left, right, _) => {
+            let symbol = match op {
+                Operator::Mul => " \\cdot ".to_string(),
+                _ => format!(" {} ", op.symbol()),
+            };
+            format!(
+                "{}{}{}",
+                latex_operand(left, left_binding(*op)),
+                symbol,
+                latex_operand(right, right_binding(*op, right))
+            )
+        }
+        Expr::Call(function, args) => call(*function, args),
+        Expr::UserCall(name, args, _) => {
+            let name = if name.chars().count() > 1 {
+                format!("\\operatorname{{{}}}", name)
+            } else {
+                name.clone()
+            };
+            format!("{}{}", name, arguments(args))
+        }
+        Expr::Matrix(elements, _) => matrix(elements),
+    }
+}
+
+/// A `pmatrix` of the rows of a matrix, or of a column for a vector.
+fn matrix(elements: &[Expr]) -> String {
+    let rows: Vec<String> = elements
+        .iter()
+        .map(|element| match element {
+            Expr::Matrix(row, _) => {
+                row.iter().map(latex).collect::<Vec<_>>().join(" & ")
+            }
+            _ => latex(element),
+        })
+        .collect();
+    format!("\\begin{{pmatrix}}{}\\end{{pmatrix}}", rows.join(" \\\\ "))
+}
+
+fn parenthesized(text: String) -> String {
+    format!("\\left({}\\right)", text)
+}
+
+/// Fractions need no parentheses except as the base of a power.
+fn latex_operand(expr: &Expr, min: u8) -> String {
+    let fraction = matches!(expr, Expr::Binary(Operator::Div, ..));
+    if binding(expr) < min && !fraction {
+        parenthesized(latex(expr))
+    } else {
+        latex(expr)
+    }
+}
+
+fn power(base: &Expr, exponent: &Expr) -> String {
+    // Bases written with a superscript or as a fraction need parentheses
+    let base = match base {
+        Expr::Binary(Operator::Div, ..) | Expr::Call(Function::Exp, _) => {
+            parenthesized(latex(base))
+        }
+        _ => latex_operand(base, 5),
+    };
+    format!("{}^{{{}}}", base, latex(exponent))
+}
+
+fn arguments(args: &[Expr]) -> String {
+    parenthesized(args.iter().map(latex).collect::<Vec<_>>().join(", "))
+}
+
+fn call(function: Function, args: &[Expr]) -> String {
+    let arg = |i: usize| latex(&args[i]);
+    match (function, args.len()) {
+        (Function::Sqrt, 1) => format!("\\sqrt{{{}}}", arg(0)),
+        (Function::Cbrt, _) => format!("\\sqrt[3]{{{}}}", arg(0)),
+        (Function::Root, 2) => format!("\\sqrt[{}]{{{}}}", arg(1), arg(0)),
+        (Function::Exp, _) => format!("e^{{{}}}", arg(0)),
+        (Function::Inv, _) => format!("\\frac{{1}}{{{}}}", arg(0)),
+        (Function::Abs, _) => format!("\\left|{}\\right|", arg(0)),
+        (Function::Norm, _) => format!("\\left|{}\\right|^{{2}}", arg(0)),
+        (Function::Conj, _) => format!("\\overline{{{}}}", arg(0)),
+        (Function::Floor, _) => {
+            format!("\\left\\lfloor {}\\right\\rfloor", arg(0))
+        }
+        (Function::Ceil, _) => {
+            format!("\\left\\lceil {}\\right\\rceil", arg(0))
+        }
+        (Function::Pow, _) => power(&args[0], &args[1]),
+        (Function::Log, _) => {
+            format!("\\log_{{{}}}{}", arg(1), parenthesized(arg(0)))
+        }
+        (Function::Besselj, _) => {
+            format!("J_{{{}}}{}", arg(0), parenthesized(arg(1)))
+        }
+        (Function::Lambertw, 2) => {
+            format!("W_{{{}}}{}", arg(1), parenthesized(arg(0)))
+        }
+        (Function::Lambertw, _) => format!("W{}", parenthesized(arg(0))),
+        _ => format!("{}{}", command(function), arguments(args)),
+    }
+}
+
+/// The LaTeX command naming `function`.
+fn command(function: Function) -> String {
+    let command = match function {
+        Function::Sin => "sin",
+        Function::Cos => "cos",
+        Function::Tan => "tan",
+        Function::Csc => "csc",
+        Function::Sec => "sec",
+        Function::Cot => "cot",
+        Function::Asin => "arcsin",
+        Function::Acos => "arccos",
+        Function::Atan => "arctan",
+        Function::Sinh => "sinh",
+        Function::Cosh => "cosh",
+        Function::Tanh => "tanh",
+        Function::Ln => "ln",
+        Function::Arg => "arg",
+        Function::Min => "min",
+        Function::Max => "max",
+        Function::Gamma => "Gamma",
+        Function::Lgamma => "ln\\Gamma",
+        Function::Zeta => "zeta",
+        Function::Re => "operatorname{Re}",
+        Function::Im => "operatorname{Im}",
+        _ => return format!("\\operatorname{{{}}}", function.name()),
+    };
+    format!("\\{}", command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::parse;
+
+    #[test]
+    fn prints_parsable_text() {
+        let print = |input: &str| parse(input).unwrap().to_string();
+        assert_eq!(print("-z^2 + 3(z - 1)"), "-z^2 + 3*(z - 1)");
+        assert_eq!(print("(-z)^2"), "(-z)^2");
+        assert_eq!(print("2^3^2"), "2^3^2");
+        assert_eq!(print("(2^3)^2"), "(2^3)^2");
+        assert_eq!(print("a - (b - c)"), "a - (b - c)");
+        assert_eq!(print("a/(b*c)"), "a/(b*c)");
+        assert_eq!(print("z * -2"), "z*(-2)");
+        assert_eq!(print("root(z, 3, 1) + 2.5i"), "root(z, 3, 1) + 2.5i");
+
+        let env = [("z", Complex::new(2.0, 1.0))];
+        for input in ["-(z*2)", "z^-i", "sin z^2 / 2", "z - -2"].iter() {
+            let text = print(input);
+            assert_eq!(print(&text), text);
+            let expected: Complex<f64> =
+                parse(input).unwrap().eval(&env[..]).unwrap();
+            let found: Complex<f64> =
+                parse(&text).unwrap().eval(&env[..]).unwrap();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn prints_latex() {
+        let print = |input: &str| latex(&parse(input).unwrap());
+        assert_eq!(
+            print("z^2 + 3(z - 1)"),
+            "z^{2} + 3 \\cdot \\left(z - 1\\right)"
+        );
+        assert_eq!(print("(z + 1)/(2z)"), "\\frac{z + 1}{2 \\cdot z}");
+        assert_eq!(print("sqrt(z) - cbrt(z)"), "\\sqrt{z} - \\sqrt[3]{z}");
+        assert_eq!(print("exp(i*PI)"), "e^{i \\cdot \\pi}");
+        assert_eq!(print("(1/z)^2"), "\\left(\\frac{1}{z}\\right)^{2}");
+        assert_eq!(print("abs(conj z)"), "\\left|\\overline{z}\\right|");
+        assert_eq!(print("besselj(2, z)"), "J_{2}\\left(z\\right)");
+        assert_eq!(print("-sin(z)^2"), "-\\sin\\left(z\\right)^{2}");
+        assert_eq!(print("erf(z)"), "\\operatorname{erf}\\left(z\\right)");
+    }
+}
@@ -0,0 +1,278 @@
+use crate::parser::ast::{
+    cast, negate, not_inlined, Context, Env, Expr, Operator, Real,
+};
+use crate::parser::branches::BranchCut;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::Function;
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+use num_traits::Zero;
+
+#[derive(Debug, Clone)]
+enum Instruction<T> {
+    Push(Complex<T>),
+    /// Pushes the parameter at an index.
+    Load(usize),
+    Neg,
+    Operator(Operator),
+    /// Division, which fails on an exact zero like [`Expr::eval`].
+    Divide(Span),
+    /// Applies a function to the given number of values on top.
+    Call(Function, usize),
+}
+
+/// An expression compiled for evaluating it at many points, as the
+/// instructions of a stack machine.
+///
+/// Only the parameters vary between runs. User functions are inlined,
+/// other variables looked up once and the parts that do not depend on the
+/// parameters evaluated when compiling.
+#[derive(Debug, Clone)]
+pub struct Program<T> {
+    instructions: Vec<Instruction<T>>,
+    cut: BranchCut<T>,
+}
+
+impl<T: Real> Program<T> {
+    /// Compiles `expr` to be run with values for `parameters`, in order,
+    /// and the variables of `env` and functions of `context`.
+    pub fn compile<E: Env<T>>(
+        expr: &Expr,
+        parameters: &[&str],
+        env: &E,
+        context: &Context,
+    ) -> Result<Program<T>, Error> {
+        let mut compiler = Compiler {
+            parameters,
+            env,
+            context,
+            instructions: vec![],
+        };
+        compiler.emit(&expr.inline(context.definitions)?)?;
+        let cut = T::from(context.branch_cut)
+            .map_or_else(BranchCut::principal, BranchCut::new);
+        Ok(Program {
+            instructions: compiler.instructions,
+            cut,
+        })
+    }
+
+    /// Evaluates the program with the values of its parameters, using
+    /// `stack` as scratch space so repeated runs do not allocate.
+    pub fn run(
+        &self,
+        args: &[Complex<T>],
+        stack: &mut Vec<Complex<T>>,
+    ) -> Result<Complex<T>, Error> {
+        stack.clear();
+        for instruction in self.instructions.iter() {
+            match instruction {
+                Instruction::Push(z) => stack.push(*z),
+                Instruction::Load(i) => stack.push(args[*i]),
+                Instruction::Neg => {
+                    let z = pop(stack);
+                    stack.push(negate(z));
+                }
+                Instruction::Operator(op) => {
+                    let z2 = pop(stack);
+                    let z1 = pop(stack);
+                    stack.push(op.apply(z1, z2, self.cut));
+                }
+                Instruction::Divide(span) => {
+                    let z2 = pop(stack);
+                    let z1 = pop(stack);
+                    if z2.is_zero() {
+                        return Err(Error::new(
+                            ErrorKind::DivisionByZero,
+                            *span,
+                        ));
+                    }
+                    stack.push(z1 / z2);
+                }
+                Instruction::Call(f, n) => {
+                    let start = stack.len() - n;
+                    let z = f.apply(&stack[start..], self.cut);
+                    stack.truncate(start);
+                    stack.push(z);
+                }
+            }
+        }
+        Ok(pop(stack))
+    }
+}
+
+/// The instructions leave exactly as many values as they take, so the stack
+/// never runs out.
+fn pop<T: Real>(stack: &mut Vec<Complex<T>>) -> Complex<T> {
+    stack
+        .pop()
+        .unwrap_or_else(|| Complex::new(T::nan(), T::nan()))
+}
+
+struct Compiler<'a, T> {
+    parameters: &'a [&'a str],
+    env: &'a dyn Env<T>,
+    context: &'a Context<'a>,
+    instructions: Vec<Instruction<T>>,
+}
+
+impl<'a, T: Real> Compiler<'a, T> {
+    fn depends_on_parameters(&self, expr: &Expr) -> bool {
+        self.parameters.iter().any(|p| expr.contains_variable(p))
+    }
+
+    fn emit(&mut self, expr: &Expr) -> Result<(), Error> {
+        if !self.depends_on_parameters(expr) {
+            match expr.eval_with(self.env, self.context) {
+                Ok(z) => {
+                    self.instructions.push(Instruction::Push(z));
+                    return Ok(());
+                }
+                // Left to fail at every point, which callers may skip
+                Err(Error {
+                    kind: ErrorKind::DivisionByZero,
+                    ..
+                }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let instruction = match expr {
+            Expr::Number(z) | Expr::Decimal(z, _) => {
+                Instruction::Push(cast(*z))
+            }
+            Expr::Constant(c) => Instruction::Push(c.value()),
+            Expr::Variable(name, span) => {
+                match self.parameters.iter().position(|p| p == name) {
+                    Some(i) => Instruction::Load(i),
+                    None => Instruction::Push(self.env.get(name).ok_or_else(
+                        || {
+                            Error::new(
+                                ErrorKind::UnknownIdentifier(name.clone()),
+                                *span,
+                            )
+                        },
+                    )?),
+                }
+            }
+            Expr::Neg(expr) => {
+                self.emit(expr)?;
+                Instruction::Neg
+            }
+            Expr::Binary(op, left, right, span) => {
+                self.emit(left)?;
+                self.emit(right)?;
+                match op {
+                    Operator::Div => Instruction::Divide(*span),
+                    _ => Instruction::Operator(*op),
+                }
+            }
+            Expr::Call(f, args) => {
+                f.check_scalar()?;
+                for arg in args.iter() {
+                    self.emit(arg)?;
+                }
+                Instruction::Call(*f, args.len())
+            }
+            Expr::Matrix(_, span) => {
+                return Err(Error::new(ErrorKind::NotScalar, *span))
+            }
+            Expr::UserCall(name, _, span) => {
+                return Err(not_inlined(name, *span))
+            }
+        };
+        self.instructions.push(instruction);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::definitions::Definitions;
+    use crate::parser::symbolic::{
+        parse, parse_statement, parse_with, Statement,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn runs_like_the_tree() {
+        let mut definitions = Definitions::default();
+        match parse_statement("f(w) := w^2 - c", &definitions).unwrap() {
+            Statement::Definition(d) => definitions.insert(d),
+            _ => unreachable!(),
+        }
+        let context = Context::new(&definitions);
+        let mut variables = HashMap::new();
+        variables.insert("c".to_string(), Complex::new(0.5, -1.0));
+
+        let mut stack = vec![];
+        for input in [
+            "z^2 + sin(z)/(z - 0.3i) + c",
+            "-z^-i * ln(z, 1) - f(z + c)",
+            "max(re z, im z, 1) + root(z, 3, 2) * besselj(2, z)",
+            "sqrt(-4) + 2c",
+        ]
+        .iter()
+        {
+            let expr = parse_with(input, &definitions).unwrap();
+            let program =
+                Program::compile(&expr, &["z"], &variables, &context).unwrap();
+            for k in 0..20 {
+                let z = Complex::from_polar(0.2 + k as f64 * 0.3, k as f64);
+                let mut env = variables.clone();
+                env.insert("z".to_string(), z);
+                let expected = expr.eval_with(&env, &context).unwrap();
+                let found = program.run(&[z], &mut stack).unwrap();
+                assert!(
+                    (found - expected).norm() <= 1e-14 * expected.norm(),
+                    "{} at {}: {} != {}",
+                    input,
+                    z,
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fails_where_the_tree_fails() {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let variables: HashMap<String, Complex<f64>> = HashMap::new();
+        let compile = |input| {
+            Program::<f64>::compile(
+                &parse(input).unwrap(),
+                &["z"],
+                &variables,
+                &context,
+            )
+        };
+        let error = compile("z + y").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownIdentifier("y".to_string()));
+        let mobius = ErrorKind::WrongShape(
+            "mobius".to_string(),
+            "a 2x2 matrix and a number".to_string(),
+        );
+        assert_eq!(compile("mobius(z, z)").unwrap_err().kind, mobius);
+        assert_eq!(compile("z + mobius(1, 2)").unwrap_err().kind, mobius);
+        let env: [(&str, Complex<f64>); 0] = [];
+        let error = parse("mobius(1, 2)").unwrap().eval::<f64, _>(&env[..]);
+        assert_eq!(error.unwrap_err().kind, mobius);
+
+        let mut stack = vec![];
+        let program = compile("1/z + 1/0").unwrap();
+        let zero = Complex::new(0.0, 0.0);
+        let one = Complex::new(1.0, 0.0);
+        assert_eq!(
+            program.run(&[one], &mut stack).unwrap_err().kind,
+            ErrorKind::DivisionByZero
+        );
+        let program = compile("1/z").unwrap();
+        assert_eq!(
+            program.run(&[zero], &mut stack).unwrap_err().kind,
+            ErrorKind::DivisionByZero
+        );
+        assert_eq!(program.run(&[one], &mut stack).unwrap(), one);
+    }
+}
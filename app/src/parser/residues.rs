@@ -0,0 +1,664 @@
+use crate::parser::ast::{Context, Expr, Operator};
+use crate::parser::derivatives::derivative;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::evaluations::Progress;
+use crate::parser::functions::Function;
+use crate::parser::integrals::{integrate, Contour, Integral};
+use crate::parser::lexer::Span;
+use crate::parser::simplify::simplify;
+use crate::parser::zeros::{locate_expr, Region};
+use num_complex::Complex;
+use num_traits::Zero;
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+
+/// Radius of the circle residues and Laurent coefficients are integrated
+/// along when none is given. Other singularities must lie outside it.
+pub const RADIUS: f64 = 0.1;
+
+/// Poles of this order or higher are taken for essential singularities.
+pub const MAX_ORDER: i32 = 16;
+
+/// Most Laurent coefficients computed at once.
+pub const MAX_TERMS: usize = 64;
+
+/// Highest order of the poles residues are written symbolically at, the
+/// formula differentiates the denominator up to twice that order.
+const MAX_SYMBOLIC_ORDER: usize = 4;
+
+/// Coefficients this small relative to the function on the circle, and
+/// Taylor coefficients of a denominator this small, are taken for zeros.
+const ZERO: f64 = 1e-8;
+
+/// Points of the circle the size of a function on it is taken from.
+const SAMPLES: usize = 64;
+
+/// Points a circle or a parametric contour is drawn with to count how
+/// many times it winds around a pole.
+const OUTLINE: usize = 1024;
+
+/// Margin of the rectangle poles are looked for in around a contour,
+/// relative to its size, off round numbers so the poles of the usual
+/// examples are not on its sides.
+const MARGIN: f64 = 0.1173;
+
+/// What a function has at a point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Singularity {
+    /// No singularity, or a removable one.
+    Regular,
+    /// A pole of an order from 1 to [`MAX_ORDER`] - 1.
+    Pole(i32),
+    Essential,
+}
+
+/// The Laurent series of a function around a point, in the largest annulus
+/// without other singularities inside the circle it was integrated along.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Laurent {
+    pub singularity: Singularity,
+    /// Index of the first coefficient, `-m` at a pole of order `m`.
+    pub start: i32,
+    /// `c_n` for `n` from `start`, of `(z - a)^n`.
+    pub coefficients: Vec<Complex<f64>>,
+    /// `c_-1`, with the error of its integral.
+    pub residue: Integral,
+}
+
+/// A pole a closed contour winds around, with its residue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Enclosed {
+    pub z: Complex<f64>,
+    pub order: i32,
+    /// How many times the contour winds around the pole, counterclockwise.
+    pub winding: i32,
+    pub residue: Complex<f64>,
+}
+
+/// The residue theorem for a closed contour.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResidueSum {
+    pub poles: Vec<Enclosed>,
+    /// `2πi Σ winding Res`, the integral along the contour.
+    pub value: Complex<f64>,
+}
+
+/// The values of `f` on the circle around `center`, checked to be finite,
+/// and the largest of their sizes.
+fn size_on_circle(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    center: Complex<f64>,
+    radius: f64,
+) -> Result<f64, Error> {
+    let mut size: f64 = 0.0;
+    for k in 0..SAMPLES {
+        let t = TAU * k as f64 / SAMPLES as f64;
+        let value = f(center + Complex::from_polar(radius, t));
+        if !value.is_finite() {
+            return Err(Error::new(ErrorKind::OnContour, Span::default()));
+        }
+        size = size.max(value.norm());
+    }
+    Ok(size)
+}
+
+/// `c_n r^n / size`, the coefficient scaled by the radius and the size of
+/// `f` on the circle so that it is at most 1, integrated as
+/// `1/2π ∫ f(a + r e^(it)) e^(-int) dt`.
+fn scaled_coefficient(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    center: Complex<f64>,
+    radius: f64,
+    size: f64,
+    n: i32,
+) -> Result<Integral, Error> {
+    let integral = integrate(
+        &|t| {
+            f(center + Complex::from_polar(radius, t)) / size
+                * Complex::from_polar(1.0, -n as f64 * t)
+        },
+        0.0,
+        TAU,
+    )?;
+    Ok(Integral {
+        value: integral.value / TAU,
+        error: integral.error / TAU,
+    })
+}
+
+/// `c_n` from its scaled value, zero when it is too small to tell apart.
+fn unscale(scaled: Integral, radius: f64, size: f64, n: i32) -> Integral {
+    let factor = size / radius.powi(n);
+    if scaled.value.norm() <= ZERO {
+        Integral {
+            value: Complex::zero(),
+            error: scaled.error * factor,
+        }
+    } else {
+        Integral {
+            value: scaled.value * factor,
+            error: scaled.error * factor,
+        }
+    }
+}
+
+/// The residue of `f` at `center`, `1/2πi ∮ f(z) dz` along the circle of
+/// `radius` around it.
+pub fn residue(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    center: Complex<f64>,
+    radius: f64,
+) -> Result<Integral, Error> {
+    let size = size_on_circle(f, center, radius)?;
+    if size == 0.0 {
+        return Ok(Integral {
+            value: Complex::zero(),
+            error: 0.0,
+        });
+    }
+    let scaled = scaled_coefficient(f, center, radius, size, -1)?;
+    Ok(unscale(scaled, radius, size, -1))
+}
+
+/// `terms` coefficients of the Laurent series of `f` around `center`,
+/// `c_n = 1/2πi ∮ f(z) (z - a)^(-n-1) dz` along the circle of `radius`.
+///
+/// The lowest coefficient that is not zero tells the singularity, down to
+/// `c_-MAX_ORDER`. The coefficients start there at a pole and at 0 at a
+/// regular point. At an essential singularity they end at `c_0`.
+pub fn laurent(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    center: Complex<f64>,
+    radius: f64,
+    terms: usize,
+) -> Result<Laurent, Error> {
+    let size = size_on_circle(f, center, radius)?;
+    let coefficient = |n| -> Result<Integral, Error> {
+        if size == 0.0 {
+            return Ok(Integral {
+                value: Complex::zero(),
+                error: 0.0,
+            });
+        }
+        let scaled = scaled_coefficient(f, center, radius, size, n)?;
+        Ok(unscale(scaled, radius, size, n))
+    };
+
+    let mut singularity = Singularity::Regular;
+    for n in -MAX_ORDER..0 {
+        if !coefficient(n)?.value.is_zero() {
+            singularity = match n {
+                n if n == -MAX_ORDER => Singularity::Essential,
+                n => Singularity::Pole(-n),
+            };
+            break;
+        }
+    }
+    let start = match singularity {
+        Singularity::Regular => 0,
+        Singularity::Pole(order) => -order,
+        Singularity::Essential => 1 - terms as i32,
+    };
+    let coefficients = (start..start + terms as i32)
+        .map(|n| coefficient(n).map(|c| c.value))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Laurent {
+        singularity,
+        start,
+        coefficients,
+        residue: coefficient(-1)?,
+    })
+}
+
+fn number(x: f64) -> Expr {
+    Expr::Number(Complex::new(x, 0.0))
+}
+
+fn binary(op: Operator, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, Box::new(left), Box::new(right), Span::default())
+}
+
+fn call(f: Function, arg: &Expr) -> Expr {
+    Expr::Call(f, vec![arg.clone()])
+}
+
+/// `expr` as a numerator and a denominator, over the quotients, products,
+/// sums, integer powers and reciprocal trigonometric functions of `expr`.
+/// Other expressions are their own numerators.
+fn quotient(
+    expr: &Expr,
+    variable: &str,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+) -> (Expr, Expr) {
+    let parts = |expr: &Expr| quotient(expr, variable, variables, context);
+    let mul = |left, right| binary(Operator::Mul, left, right);
+    match expr {
+        Expr::Neg(expr) => {
+            let (numerator, denominator) = parts(expr);
+            (Expr::Neg(Box::new(numerator)), denominator)
+        }
+        Expr::Binary(op, left, right, _) => {
+            let (left_numerator, left_denominator) = parts(left);
+            let (right_numerator, right_denominator) = parts(right);
+            match op {
+                Operator::Add | Operator::Sub => (
+                    binary(
+                        *op,
+                        mul(left_numerator, right_denominator.clone()),
+                        mul(right_numerator, left_denominator.clone()),
+                    ),
+                    mul(left_denominator, right_denominator),
+                ),
+                Operator::Mul => (
+                    mul(left_numerator, right_numerator),
+                    mul(left_denominator, right_denominator),
+                ),
+                Operator::Div => (
+                    mul(left_numerator, right_denominator),
+                    mul(left_denominator, right_numerator),
+                ),
+                Operator::Pow => {
+                    let exponent = match right.eval_with(variables, context) {
+                        Ok(exponent) if exponent.im == 0.0 => exponent.re,
+                        _ => return (expr.clone(), number(1.0)),
+                    };
+                    if right.contains_variable(variable) {
+                        return (expr.clone(), number(1.0));
+                    }
+                    if exponent.fract() != 0.0 {
+                        return (expr.clone(), number(1.0));
+                    }
+                    let pow = |base| {
+                        binary(Operator::Pow, base, number(exponent.abs()))
+                    };
+                    if exponent < 0.0 {
+                        (pow(left_denominator), pow(left_numerator))
+                    } else {
+                        (pow(left_numerator), pow(left_denominator))
+                    }
+                }
+            }
+        }
+        Expr::Call(Function::Inv, args) if args.len() == 1 => {
+            let (numerator, denominator) = parts(&args[0]);
+            (denominator, numerator)
+        }
+        Expr::Call(f, args) if args.len() == 1 => {
+            let arg = &args[0];
+            match f {
+                Function::Tan => {
+                    (call(Function::Sin, arg), call(Function::Cos, arg))
+                }
+                Function::Cot => {
+                    (call(Function::Cos, arg), call(Function::Sin, arg))
+                }
+                Function::Sec => (number(1.0), call(Function::Cos, arg)),
+                Function::Csc => (number(1.0), call(Function::Sin, arg)),
+                Function::Tanh => {
+                    (call(Function::Sinh, arg), call(Function::Cosh, arg))
+                }
+                _ => (expr.clone(), number(1.0)),
+            }
+        }
+        _ => (expr.clone(), number(1.0)),
+    }
+}
+
+/// The residue of `expr`, a function of `variable`, at `point`, as an
+/// expression, when `expr` is a quotient `N/D` of functions with symbolic
+/// derivatives and `N` is finite at the point.
+///
+/// With `D` of order `m` at `a`, `N/D` is the quotient of the Taylor
+/// series `Σ n_k (z - a)^k` and `(z - a)^m Σ e_k (z - a)^k`, and the
+/// residue is the coefficient `q_(m-1)` of the series division,
+/// `q_k = (n_k - Σ e_j q_(k-j)) / e_0`. A simple pole gives `N(a)/D'(a)`.
+/// Poles of orders above 4 and essential singularities have no symbolic
+/// residue.
+pub fn symbolic_residue(
+    expr: &Expr,
+    variable: &str,
+    point: &Expr,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+) -> Option<Expr> {
+    let definitions = context.definitions;
+    let expr = expr.inline(definitions).ok()?;
+    let point = point.inline(definitions).ok()?;
+    let (numerator, denominator) =
+        quotient(&expr, variable, variables, context);
+    let params = [variable.to_string()];
+    let at =
+        |expr: &Expr| expr.substitute(&params, std::slice::from_ref(&point));
+    let value = |expr: &Expr| {
+        at(expr)
+            .eval_with(variables, context)
+            .ok()
+            .filter(|z| z.is_finite())
+    };
+    // Taylor coefficients, the derivatives over factorials
+    let taylor = |expr: &Expr, terms: usize| -> Option<Vec<Expr>> {
+        let mut derivatives = vec![expr.clone()];
+        for _ in 1..terms {
+            let last = derivatives.last()?;
+            derivatives.push(derivative(last, variable, definitions).ok()?);
+        }
+        let mut factorial = 1.0;
+        let mut coefficients = vec![];
+        for (k, derivative) in derivatives.iter().enumerate() {
+            if k > 0 {
+                factorial *= k as f64;
+            }
+            coefficients.push(binary(
+                Operator::Div,
+                at(derivative),
+                number(factorial),
+            ));
+        }
+        Some(coefficients)
+    };
+
+    value(&numerator)?;
+    let denominators = taylor(&denominator, 2 * MAX_SYMBOLIC_ORDER)?;
+    let mut order = None;
+    for (k, coefficient) in
+        denominators.iter().enumerate().take(MAX_SYMBOLIC_ORDER + 1)
+    {
+        let e = coefficient.eval_with(variables, context).ok()?;
+        if !e.is_finite() {
+            return None;
+        }
+        if e.norm() > ZERO {
+            order = Some(k);
+            break;
+        }
+    }
+    let m = order?;
+    if m == 0 {
+        return Some(number(0.0));
+    }
+    let n = taylor(&numerator, m)?;
+    let e = &denominators[m..2 * m];
+    let mut q: Vec<Expr> = vec![];
+    for k in 0..m {
+        let mut remainder = n[k].clone();
+        for j in 1..=k {
+            remainder = binary(
+                Operator::Sub,
+                remainder,
+                binary(Operator::Mul, e[j].clone(), q[k - j].clone()),
+            );
+        }
+        q.push(binary(Operator::Div, remainder, e[0].clone()));
+    }
+    let residue = simplify(&q[m - 1]);
+    value(&residue)?;
+    Some(residue)
+}
+
+/// Points along `contour`, the first and the last the same when it is
+/// closed.
+fn outline(
+    contour: &Contour,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+) -> Result<Vec<Complex<f64>>, Error> {
+    let steps = (0..=OUTLINE).map(|k| k as f64 / OUTLINE as f64);
+    match contour {
+        Contour::Circle(center, radius) => Ok(steps
+            .map(|s| center + Complex::from_polar(*radius, TAU * s))
+            .collect()),
+        Contour::Polyline(points) => Ok(points.clone()),
+        Contour::Parametric(path, start, end) => {
+            let t = Complex::new(*start, 0.0);
+            let gamma = path.function_of("t", variables, context, t)?;
+            let points: Vec<_> = steps
+                .map(|s| gamma(Complex::new(start + (end - start) * s, 0.0)))
+                .collect();
+            if points.iter().all(|z| z.is_finite()) {
+                Ok(points)
+            } else {
+                Err(Error::new(ErrorKind::OnContour, Span::default()))
+            }
+        }
+    }
+}
+
+/// How many times the closed polyline through `points` winds around `z`.
+fn winding(points: &[Complex<f64>], z: Complex<f64>) -> i32 {
+    let turns: f64 = points
+        .windows(2)
+        .map(|segment| ((segment[1] - z) / (segment[0] - z)).arg())
+        .sum();
+    (turns / TAU).round() as i32
+}
+
+/// The poles of `expr`, a function of `variable`, that `contour` winds
+/// around, with their residues, and `2πi Σ winding Res`, which the
+/// integral along the contour is by the residue theorem. Open contours
+/// have none.
+///
+/// The poles are located around the contour, see [`locate_expr`], and each
+/// residue is integrated along a circle smaller than the distance to the
+/// other zeros and poles. Essential singularities are not found. `progress`
+/// follows and cancels the search for the poles.
+pub fn residue_sum(
+    expr: &Expr,
+    variable: &str,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+    contour: &Contour,
+    progress: &Progress,
+) -> Result<Option<ResidueSum>, Error> {
+    let points = outline(contour, variables, context)?;
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Ok(None),
+    };
+    let mut min = first;
+    let mut max = first;
+    for z in &points {
+        min = Complex::new(min.re.min(z.re), min.im.min(z.im));
+        max = Complex::new(max.re.max(z.re), max.im.max(z.im));
+    }
+    let size = (max.re - min.re).max(max.im - min.im);
+    if size == 0.0 || (last - first).norm() > 1e-9 * size {
+        return Ok(None);
+    }
+    let margin = Complex::new(1.0, 1.0) * MARGIN * size;
+    let region = Region::Rectangle(min - margin, max + margin);
+    let census =
+        locate_expr(expr, variable, variables, context, region, progress)?;
+
+    let f = expr.function_of(variable, variables, context, first)?;
+    let mut poles = vec![];
+    for located in census.found.iter().filter(|located| located.order < 0) {
+        let winding = match contour {
+            Contour::Circle(center, radius) => {
+                ((located.z - center).norm() < *radius) as i32
+            }
+            _ => winding(&points, located.z),
+        };
+        if winding == 0 {
+            continue;
+        }
+        let radius = census
+            .found
+            .iter()
+            .filter(|other| other.z != located.z)
+            .map(|other| (other.z - located.z).norm() / 2.0)
+            .fold(RADIUS, f64::min);
+        poles.push(Enclosed {
+            z: located.z,
+            order: -located.order,
+            winding,
+            residue: residue(&f, located.z, radius)?.value,
+        });
+    }
+    let value = poles
+        .iter()
+        .map(|pole| pole.residue * pole.winding as f64)
+        .sum::<Complex<f64>>()
+        * Complex::new(0.0, TAU);
+    Ok(Some(ResidueSum { poles, value }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::definitions::Definitions;
+    use crate::parser::integrals::contour_integral;
+    use crate::parser::symbolic::parse;
+
+    fn close(a: Complex<f64>, b: Complex<f64>) -> bool {
+        (a - b).norm() < 1e-8
+    }
+
+    fn expansion(input: &str, point: Complex<f64>, terms: usize) -> Laurent {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let variables = HashMap::new();
+        let expr = parse(input).unwrap();
+        let f = expr
+            .function_of("z", &variables, &context, Complex::zero())
+            .unwrap();
+        laurent(&f, point, RADIUS, terms).unwrap()
+    }
+
+    fn symbolic(input: &str, point: &str) -> Option<Expr> {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let variables = HashMap::new();
+        let expr = parse(input).unwrap();
+        let point = parse(point).unwrap();
+        symbolic_residue(&expr, "z", &point, &variables, &context)
+    }
+
+    fn symbolic_value(input: &str, point: &str) -> Complex<f64> {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let residue = symbolic(input, point).unwrap();
+        residue.eval_with(&HashMap::new(), &context).unwrap()
+    }
+
+    #[test]
+    fn expands_in_laurent_series() {
+        let real = |x| Complex::new(x, 0.0);
+        let laurent = expansion("exp(z)/z^2", Complex::zero(), 4);
+        assert_eq!(laurent.singularity, Singularity::Pole(2));
+        assert_eq!(laurent.start, -2);
+        let expected = [1.0, 1.0, 0.5, 1.0 / 6.0];
+        for (c, expected) in laurent.coefficients.iter().zip(&expected) {
+            assert!(close(*c, real(*expected)), "{:?}", laurent);
+        }
+        assert!(close(laurent.residue.value, real(1.0)));
+
+        let laurent = expansion("1/(z^2 + 1)", Complex::i(), 2);
+        assert_eq!(laurent.singularity, Singularity::Pole(1));
+        assert!(close(laurent.residue.value, Complex::new(0.0, -0.5)));
+        assert!(close(laurent.coefficients[1], Complex::new(0.25, 0.0)));
+
+        let laurent = expansion("sin(z)/z", Complex::zero(), 3);
+        assert_eq!(laurent.singularity, Singularity::Regular);
+        assert_eq!(laurent.coefficients[1], Complex::zero());
+        assert!(close(laurent.coefficients[2], real(-1.0 / 6.0)));
+
+        // exp(1/z) = Σ z^-n / n!
+        let laurent = expansion("exp(1/z)", Complex::zero(), 3);
+        assert_eq!(laurent.singularity, Singularity::Essential);
+        assert_eq!(laurent.start, -2);
+        assert!(close(laurent.coefficients[0], real(0.5)));
+        assert!(close(laurent.residue.value, real(1.0)));
+    }
+
+    #[test]
+    fn writes_residues_symbolically() {
+        assert!(close(
+            symbolic_value("1/(z^2 + 1)", "i"),
+            Complex::new(0.0, -0.5)
+        ));
+        assert!(close(
+            symbolic_value("tan(z)", "pi/2"),
+            Complex::new(-1.0, 0.0)
+        ));
+        assert!(close(
+            symbolic_value("exp(z)/z^3", "0"),
+            Complex::new(0.5, 0.0)
+        ));
+        assert!(close(
+            symbolic_value("sin(z)/z^2", "0"),
+            Complex::new(1.0, 0.0)
+        ));
+        assert!(close(
+            symbolic_value("1/z + 2/(z - 1)", "1"),
+            Complex::new(2.0, 0.0)
+        ));
+        assert!(close(symbolic_value("cos(z)", "1"), Complex::zero()));
+        assert_eq!(symbolic("exp(1/z)", "0"), None);
+        assert_eq!(symbolic("1/z^6", "0"), None);
+    }
+
+    #[test]
+    fn compares_integrals_with_residues() {
+        let definitions = Definitions::default();
+        let context = Context::new(&definitions);
+        let variables = HashMap::new();
+        let two_pi_i = Complex::new(0.0, TAU);
+        let check = |input: &str, contour: &Contour, expected: Complex<f64>| {
+            let expr = parse(input).unwrap();
+            let progress = Progress::default();
+            let sum = residue_sum(
+                &expr, "z", &variables, &context, contour, &progress,
+            )
+            .unwrap()
+            .unwrap();
+            let f = expr
+                .function_of("z", &variables, &context, Complex::zero())
+                .unwrap();
+            let integral =
+                contour_integral(&f, contour, &variables, &context).unwrap();
+            assert!(close(sum.value, expected), "{}: {:?}", input, sum);
+            assert!(close(integral.value, sum.value), "{}", input);
+        };
+        let circle = Contour::Circle(Complex::zero(), 2.0);
+        check("1/(z^2 + 1)", &circle, Complex::zero());
+        check("z/(z - 1)^2", &circle, two_pi_i);
+        // Six poles of residue -1, among zeros that cancel them in the count
+        check(
+            "tan(z)",
+            &Contour::Circle(Complex::zero(), 10.0),
+            two_pi_i * -6.0,
+        );
+        let square = Contour::Polyline(vec![
+            Complex::new(0.5, -0.5),
+            Complex::new(1.5, -0.5),
+            Complex::new(1.5, 0.5),
+            Complex::new(0.5, 0.5),
+            Complex::new(0.5, -0.5),
+        ]);
+        check("1/(z^2 - 1)", &square, two_pi_i / 2.0);
+        // Twice around the pole
+        let twice = Contour::Parametric(parse("2cis(2t)").unwrap(), 0.0, TAU);
+        check("1/(z - 1)", &twice, two_pi_i * 2.0);
+
+        let segment =
+            Contour::Polyline(vec![Complex::zero(), Complex::new(1.0, 1.0)]);
+        let expr = parse("1/z").unwrap();
+        assert_eq!(
+            residue_sum(
+                &expr,
+                "z",
+                &variables,
+                &context,
+                &segment,
+                &Progress::default()
+            ),
+            Ok(None)
+        );
+        let progress = Progress::default();
+        progress.cancel();
+        let error =
+            residue_sum(&expr, "z", &variables, &context, &circle, &progress);
+        assert_eq!(error.unwrap_err().kind, ErrorKind::Cancelled);
+    }
+}
@@ -0,0 +1,430 @@
+use crate::parser::ast::{Expr, Operator};
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+
+/// A product `coefficient * base^exponent * ...`, with its factors sorted so
+/// that like terms compare equal.
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+    coefficient: Complex<f64>,
+    factors: Vec<(Expr, Expr)>,
+}
+
+/// Simplifies `expr` algebraically.
+///
+/// Numbers are folded, `i^2` included, zeros, ones and powers of one
+/// dropped, like terms collected (`3z - z` is `2*z`) and powers of the same
+/// base multiplied (`z*z^2` is `z^3`). Integer fractions are reduced but
+/// not divided out, so `6/4` is `3/2`, and sums are not expanded. Function
+/// calls keep their arguments simplified but are not evaluated.
+///
+/// The result has no spans, and like the rules above it assumes the powers
+/// it merges are defined, `z/z` is `1`.
+pub fn simplify(expr: &Expr) -> Expr {
+    from_sum(sum(expr))
+}
+
+fn number(x: f64) -> Expr {
+    Expr::Number(Complex::new(x, 0.0))
+}
+
+fn binary(op: Operator, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, Box::new(left), Box::new(right), Span::default())
+}
+
+fn is_integer(z: Complex<f64>) -> bool {
+    z.im == 0.0 && z.re.fract() == 0.0
+}
+
+/// Integer exponents small enough to be multiplied out.
+fn is_small_integer(z: Complex<f64>) -> bool {
+    is_integer(z) && z.re.abs() <= 1024.0
+}
+
+/// `±1` and `±i`, the numbers whose reciprocals are exact.
+fn is_unit(z: Complex<f64>) -> bool {
+    z.re.abs() + z.im.abs() == 1.0 && (z.re == 0.0 || z.im == 0.0)
+}
+
+/// The terms of `expr`, with like ones collected.
+fn sum(expr: &Expr) -> Vec<Term> {
+    match expr {
+        Expr::Number(z) => collect(vec![Term {
+            coefficient: *z,
+            factors: vec![],
+        }]),
+        Expr::Neg(expr) => negate(sum(expr)),
+        Expr::Binary(Operator::Add, left, right, _) => {
+            let mut terms = sum(left);
+            terms.extend(sum(right));
+            collect(terms)
+        }
+        Expr::Binary(Operator::Sub, left, right, _) => {
+            let mut terms = sum(left);
+            terms.extend(negate(sum(right)));
+            collect(terms)
+        }
+        Expr::Binary(Operator::Mul, left, right, _) => {
+            product(sum(left), sum(right))
+        }
+        Expr::Binary(Operator::Div, left, right, _) => {
+            product(sum(left), power(sum(right), number(-1.0)))
+        }
+        Expr::Binary(Operator::Pow, base, exponent, _) => {
+            power(sum(base), simplify(exponent))
+        }
+        Expr::Decimal(_, _)
+        | Expr::Constant(_)
+        | Expr::Variable(_, _)
+        | Expr::Call(_, _)
+        | Expr::UserCall(_, _, _)
+        | Expr::Matrix(_, _) => vec![Term {
+            coefficient: Complex::new(1.0, 0.0),
+            factors: vec![(atom(expr), number(1.0))],
+        }],
+    }
+}
+
+/// A variable, constant or call without spans and with simplified
+/// arguments.
+fn atom(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Variable(name, _) => {
+            Expr::Variable(name.clone(), Span::default())
+        }
+        Expr::Call(f, args) => {
+            Expr::Call(*f, args.iter().map(simplify).collect())
+        }
+        Expr::UserCall(name, args, _) => Expr::UserCall(
+            name.clone(),
+            args.iter().map(simplify).collect(),
+            Span::default(),
+        ),
+        Expr::Matrix(elements, _) => Expr::Matrix(
+            elements.iter().map(simplify).collect(),
+            Span::default(),
+        ),
+        _ => expr.clone(),
+    }
+}
+
+fn negate(terms: Vec<Term>) -> Vec<Term> {
+    terms
+        .into_iter()
+        .map(|term| Term {
+            coefficient: -term.coefficient,
+            ..term
+        })
+        .collect()
+}
+
+/// Adds up the coefficients of terms with the same factors, keeping the
+/// order they first appear in and dropping those that cancel.
+fn collect(terms: Vec<Term>) -> Vec<Term> {
+    let mut collected: Vec<Term> = vec![];
+    for term in terms {
+        match collected.iter_mut().find(|t| t.factors == term.factors) {
+            Some(like) => like.coefficient += term.coefficient,
+            None => collected.push(term),
+        }
+    }
+    collected.into_iter().flat_map(normalize).collect()
+}
+
+/// A sum of several terms as a single one, `1 * (a + b)^1`.
+fn single(mut terms: Vec<Term>) -> Term {
+    if terms.len() == 1 {
+        if let Some(term) = terms.pop() {
+            return term;
+        }
+    }
+    Term {
+        coefficient: Complex::new(1.0, 0.0),
+        factors: vec![(base(terms), number(1.0))],
+    }
+}
+
+/// A sum as the base of a power, with its terms in a fixed order so that
+/// `z + 1` and `1 + z` are the same base.
+fn base(mut terms: Vec<Term>) -> Expr {
+    terms.sort_by_cached_key(|term| {
+        (
+            term.factors.is_empty(),
+            product_expr(Complex::new(1.0, 0.0), term.factors.clone())
+                .to_string(),
+        )
+    });
+    from_sum(terms)
+}
+
+fn product(left: Vec<Term>, right: Vec<Term>) -> Vec<Term> {
+    if left.is_empty() || right.is_empty() {
+        return vec![];
+    }
+    let mut term = single(left);
+    let right = single(right);
+    term.coefficient *= right.coefficient;
+    for (base, exponent) in right.factors {
+        multiply(&mut term, base, exponent);
+    }
+    normalize(term)
+}
+
+/// Multiplies `term` by `base^exponent`, adding the exponents of a base it
+/// already has.
+fn multiply(term: &mut Term, base: Expr, exponent: Expr) {
+    match term.factors.iter_mut().find(|(b, _)| *b == base) {
+        Some((_, e)) => {
+            *e = simplify(&binary(Operator::Add, e.clone(), exponent))
+        }
+        None => term.factors.push((base, exponent)),
+    }
+}
+
+/// `terms^exponent`, distributed over a single term for integer exponents.
+fn power(terms: Vec<Term>, exponent: Expr) -> Vec<Term> {
+    let n = match exponent {
+        Expr::Number(n) => Some(n),
+        _ => None,
+    };
+    if n == Some(Complex::new(0.0, 0.0)) {
+        return sum(&number(1.0));
+    }
+    if n == Some(Complex::new(1.0, 0.0)) {
+        return terms;
+    }
+    if terms.is_empty() && matches!(n, Some(n) if n.im == 0.0 && n.re > 0.0) {
+        return terms;
+    }
+    let n = match n {
+        Some(n) if is_small_integer(n) && terms.len() == 1 => n.re,
+        _ => {
+            return vec![Term {
+                coefficient: Complex::new(1.0, 0.0),
+                factors: vec![(base(terms), exponent)],
+            }]
+        }
+    };
+    let term = single(terms);
+    let mut powered = Term {
+        coefficient: Complex::new(1.0, 0.0),
+        factors: vec![],
+    };
+    if n > 0.0 || is_unit(term.coefficient) {
+        powered.coefficient = term.coefficient.powi(n as i32);
+    } else if term.coefficient != Complex::new(1.0, 0.0) {
+        powered
+            .factors
+            .push((Expr::Number(term.coefficient), number(n)));
+    }
+    for (base, e) in term.factors {
+        let e = simplify(&binary(Operator::Mul, e, number(n)));
+        multiply(&mut powered, base, e);
+    }
+    normalize(powered)
+}
+
+fn gcd(a: f64, b: f64) -> f64 {
+    if b == 0.0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Folds numbers into the coefficient, reduces integer fractions and sorts
+/// the factors.
+fn normalize(mut term: Term) -> Vec<Term> {
+    let mut denominator = 1.0;
+    let mut factors = vec![];
+    for (base, exponent) in term.factors {
+        let (b, n) = match (&base, &exponent) {
+            (_, Expr::Number(n)) if *n == Complex::new(0.0, 0.0) => continue,
+            (Expr::Number(b), Expr::Number(n)) if is_small_integer(*n) => {
+                (*b, n.re)
+            }
+            _ => {
+                factors.push((base, exponent));
+                continue;
+            }
+        };
+        if n > 0.0 || is_unit(b) {
+            term.coefficient *= b.powi(n as i32);
+        } else if is_integer(b)
+            && (denominator * b.re.powi(-n as i32)).abs() < 9e15
+        {
+            denominator *= b.re.powi(-n as i32);
+        } else {
+            factors.push((base, exponent));
+        }
+    }
+    if is_integer(term.coefficient) {
+        let divisor = gcd(term.coefficient.re, denominator);
+        term.coefficient /= divisor;
+        denominator /= divisor;
+    }
+    if denominator < 0.0 {
+        term.coefficient = -term.coefficient;
+        denominator = -denominator;
+    }
+    if denominator != 1.0 {
+        factors.push((number(denominator), number(-1.0)));
+    }
+    if term.coefficient == Complex::new(0.0, 0.0) {
+        return vec![];
+    }
+    factors.sort_by_cached_key(|(base, exponent)| {
+        let rank = match base {
+            Expr::Number(_) => 0,
+            Expr::Constant(_) => 1,
+            Expr::Variable(_, _) => 2,
+            _ => 3,
+        };
+        (rank, base.to_string(), exponent.to_string())
+    });
+    term.factors = factors;
+    vec![term]
+}
+
+fn is_negative(z: Complex<f64>) -> bool {
+    z.re < 0.0 || (z.re == 0.0 && z.im < 0.0)
+}
+
+/// Writes the terms back as an expression, subtracting the negative ones.
+fn from_sum(terms: Vec<Term>) -> Expr {
+    let mut result: Option<Expr> = None;
+    for term in terms {
+        let negative = is_negative(term.coefficient);
+        let coefficient = if negative {
+            -term.coefficient
+        } else {
+            term.coefficient
+        };
+        let magnitude = product_expr(coefficient, term.factors);
+        result = Some(match result {
+            None if negative => negated(magnitude),
+            None => magnitude,
+            Some(sum) if negative => binary(Operator::Sub, sum, magnitude),
+            Some(sum) => binary(Operator::Add, sum, magnitude),
+        });
+    }
+    result.unwrap_or_else(|| number(0.0))
+}
+
+/// Negates the leftmost factor of a product, so `-(2*z)` is written `-2*z`.
+fn negated(expr: Expr) -> Expr {
+    match expr {
+        Expr::Number(z) => Expr::Number(-z),
+        Expr::Binary(op @ Operator::Mul, left, right, span)
+        | Expr::Binary(op @ Operator::Div, left, right, span) => {
+            Expr::Binary(op, Box::new(negated(*left)), right, span)
+        }
+        expr => Expr::Neg(Box::new(expr)),
+    }
+}
+
+/// `coefficient * numerator / denominator`, with the factors with a negative
+/// exponent in the denominator.
+fn product_expr(coefficient: Complex<f64>, factors: Vec<(Expr, Expr)>) -> Expr {
+    let power = |base: Expr, exponent: Expr| match exponent {
+        Expr::Number(n) if n == Complex::new(1.0, 0.0) => base,
+        exponent => binary(Operator::Pow, base, exponent),
+    };
+    let mut numerator = vec![];
+    let mut denominator = vec![];
+    for (base, exponent) in factors {
+        match exponent {
+            Expr::Number(n) if n.im == 0.0 && n.re < 0.0 => {
+                denominator.push(power(base, Expr::Number(-n)))
+            }
+            exponent => numerator.push(power(base, exponent)),
+        }
+    }
+    if coefficient != Complex::new(1.0, 0.0) || numerator.is_empty() {
+        numerator.insert(0, Expr::Number(coefficient));
+    }
+    let times = |factors: Vec<Expr>| {
+        factors
+            .into_iter()
+            .reduce(|product, factor| binary(Operator::Mul, product, factor))
+    };
+    let numerator = times(numerator).unwrap_or_else(|| number(1.0));
+    match times(denominator) {
+        Some(denominator) => binary(Operator::Div, numerator, denominator),
+        None => numerator,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::symbolic::parse;
+
+    fn simplified(input: &str) -> String {
+        simplify(&parse(input).unwrap()).to_string()
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(simplified("2 + 3*4"), "14");
+        assert_eq!(simplified("i^2"), "-1");
+        assert_eq!(simplified("i*i*i"), "-i");
+        assert_eq!(simplified("1/i"), "-i");
+        assert_eq!(simplified("2^0.5"), "2^0.5");
+        assert_eq!(simplified("(1 + 2i)*(1 - 2i)"), "5");
+    }
+
+    #[test]
+    fn drops_identities() {
+        assert_eq!(simplified("0*z + 1"), "1");
+        assert_eq!(simplified("z^1"), "z");
+        assert_eq!(simplified("z^0 + 0"), "1");
+        assert_eq!(simplified("1*sin(z*1)"), "sin(z)");
+        assert_eq!(simplified("-(-z)"), "z");
+    }
+
+    #[test]
+    fn collects_like_terms() {
+        assert_eq!(simplified("z + z"), "2*z");
+        assert_eq!(simplified("3z - z + 2z^2 - z^2"), "2*z + z^2");
+        assert_eq!(simplified("z - (z + 1)"), "-1");
+        assert_eq!(simplified("a*b - b*a"), "0");
+        assert_eq!(simplified("z*z^2"), "z^3");
+        assert_eq!(simplified("z^2/z"), "z");
+        assert_eq!(simplified("(z + 1)*(1 + z)"), "(z + 1)^2");
+        assert_eq!(simplified("e^z * e^z"), "e^(2*z)");
+        assert_eq!(simplified("2i*z - 3i*z"), "-i*z");
+    }
+
+    #[test]
+    fn reduces_fractions() {
+        assert_eq!(simplified("4z/2"), "2*z");
+        assert_eq!(simplified("z/2"), "z/2");
+        assert_eq!(simplified("3/6"), "1/2");
+        assert_eq!(simplified("-6/4"), "-3/2");
+        assert_eq!(simplified("1/(2z)"), "1/(2*z)");
+        assert_eq!(simplified("(z - 2)/(z - 2)^3"), "1/(z - 2)^2");
+    }
+
+    #[test]
+    fn keeps_the_value() {
+        let env = [
+            ("z", Complex::new(0.7, -1.3)),
+            ("a", Complex::new(2.0, 0.5)),
+        ];
+        for input in [
+            "(z + 1)^3 * (z + 1)^-2 / a",
+            "3z^2 - 2a*z + z*a - i^3",
+            "sin(2z)/(4*z^2) - 1/3",
+            "z^(1/2) * z^(1/2) + e^(i*z)",
+            "-(z - a)^2 * 2/6",
+        ]
+        .iter()
+        {
+            let expr = parse(input).unwrap();
+            let z: Complex<f64> = expr.eval(&env[..]).unwrap();
+            let w: Complex<f64> = simplify(&expr).eval(&env[..]).unwrap();
+            assert!((z - w).norm() < 1e-12 * (1.0 + z.norm()), "{}", input);
+        }
+    }
+}
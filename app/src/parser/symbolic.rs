@@ -1,549 +1,1190 @@
+use crate::parser::ast::{Constant, Expr, Operator};
+use crate::parser::definitions::{Definition, Definitions};
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::functions::{Arity, Function};
+use crate::parser::lexer::{tokenize_with, Span, Token, TokenKind, Unit};
 use num_complex::Complex;
-use std::collections::HashMap;
-use std::f32::consts::E;
-use std::f32::consts::PI;
+
 enum Precedence {
     Higher,
     Lower,
     Equal,
 }
 
-#[derive(Debug)]
-struct OperationStack {
-    operations: Vec<String>,
+/// A function on the operator stack, built in or defined by the user with
+/// its number of parameters.
+#[derive(Debug, Clone)]
+enum Callee {
+    Builtin(Function),
+    User(String, usize),
 }
 
-impl OperationStack {
-    fn new() -> OperationStack {
-        OperationStack {
-            operations: Vec::new(),
+impl Callee {
+    fn name(&self) -> &str {
+        match self {
+            Callee::Builtin(f) => f.name(),
+            Callee::User(name, _) => name,
         }
     }
-    fn add(&mut self, c: String) {
-        self.operations.insert(0, c);
-    }
-    fn pop(&mut self) -> String {
-        self.operations.remove(0)
-    }
-    fn top(&self) -> Option<&String> {
-        self.operations.get(0)
+
+    fn arity(&self) -> Arity {
+        match self {
+            Callee::Builtin(f) => f.arity(),
+            Callee::User(_, n) => Arity::Exactly(*n),
+        }
     }
-    fn is_empty(&self) -> bool {
-        self.operations.len() == 0
+}
+
+/// Entries of the operator stack in the shunting yard, with the span of
+/// their token.
+#[derive(Debug, Clone)]
+enum StackItem {
+    Operator(Operator, Span),
+    /// `r∠θ`, the polar number `r cis(θ)`.
+    Angle(Span),
+    Negate(Span),
+    Function(Callee, Span),
+    LeftParen(Span),
+    /// A parenthesis right after a function name, with the length of the
+    /// output when it was opened and the commas seen since. Closing it
+    /// applies the function to everything pushed in between.
+    CallParen(Span, usize, usize),
+    /// An opening bracket, with the length of the output when it was opened
+    /// and the commas seen since. Closing it makes a matrix of everything
+    /// pushed in between.
+    Bracket(Span, usize, usize),
+}
+
+/// Prefix operators (negation and functions without parentheses) bind
+/// tighter than products but looser than powers, so `-z^2` is `-(z^2)`,
+/// `sin z^2` is `sin(z^2)` and `sin z * 2` is `(sin z)*2`. With parentheses
+/// a function applies to them first, `sin(z)^2` is `(sin z)^2`.
+///
+/// `∠` binds looser than those but tighter than products, so polar numbers
+/// multiply like `2∠45° * 3∠30°`, and an argument with operators needs
+/// parentheses, `2∠(PI/4)`.
+fn precedence(item: &StackItem) -> u8 {
+    match item {
+        StackItem::Operator(Operator::Pow, _) => 5,
+        StackItem::Negate(_) | StackItem::Function(_, _) => 4,
+        StackItem::Angle(_) => 3,
+        StackItem::Operator(Operator::Mul, _)
+        | StackItem::Operator(Operator::Div, _) => 2,
+        StackItem::Operator(Operator::Add, _)
+        | StackItem::Operator(Operator::Sub, _) => 1,
+        StackItem::LeftParen(_)
+        | StackItem::CallParen(_, _, _)
+        | StackItem::Bracket(_, _, _) => 0,
     }
 }
 
-fn precedence(operator: &String) -> u8 {
-    if operator == "^" {
-        return 3;
-    } else if operator == "*" || operator == "/" {
-        return 2;
-    } else if operator == "+" || operator == "-" {
-        return 1;
-    } else if is_function(operator) {
-        return 3;
-    }
-    0
+/// Powers group from the right, `2^3^2` is `2^(3^2)`. Every other binary
+/// operator groups from the left.
+fn is_right_associative(item: &StackItem) -> bool {
+    matches!(item, StackItem::Operator(Operator::Pow, _))
 }
 
-fn compare_precedence(current: &String, top: &String) -> Precedence {
+fn compare_precedence(current: &StackItem, top: &StackItem) -> Precedence {
     let current = precedence(current);
     let top = precedence(top);
-    if current == top {
-        return Precedence::Equal;
-    } else if current < top {
-        return Precedence::Lower;
+    if current < top {
+        Precedence::Lower
     } else if current > top {
-        return Precedence::Higher;
+        Precedence::Higher
     } else {
-        return Precedence::Equal;
+        Precedence::Equal
     }
 }
 
-pub fn shunting_yard(operation: String) -> Vec<String> {
-    let mut stack = OperationStack::new();
-    let mut print = vec![];
-
-    let operation = operation.replace(" ", "");
-    if !operation.contains("+")
-        && !operation.contains("-")
-        && !operation.contains("*")
-        && !operation.contains("/")
-        && !operation.contains("^")
-        && !operation.contains("sin")
-        && !operation.contains("cos")
-        && !operation.contains("tan")
-        && !operation.contains("csc")
-        && !operation.contains("sec")
-        && !operation.contains("cot")
-        && !operation.contains("asin")
-        && !operation.contains("acos")
-        && !operation.contains("atan")
-        && !operation.contains("sinh")
-        && !operation.contains("cosh")
-        && !operation.contains("tanh")
-        && !operation.contains("asinh")
-        && !operation.contains("acosh")
-        && !operation.contains("atanh")
-        && !operation.contains("inv")
-        && !operation.contains("conj")
-        && !operation.contains("exp")
-        && !operation.contains("ln")
-        && !operation.contains("sqrt")
-        && !operation.contains("cbrt")
-    {
-        return Vec::new();
-    }
-
-    // Split by variables with n length
-    let mut op_split = vec![];
-    let mut temporary_index = 0;
-    let mut last = String::new();
-    for i in 0..operation.chars().count() {
-        let current = &operation[i..i + 1];
-        if is_operation(&current.to_string())
-            || current == "("
-            || current == ")"
-        {
-            if temporary_index != i {
-                op_split.push(String::from(&operation[temporary_index..i]));
-            }
-            op_split.push(String::from(current));
-            temporary_index = i + 1;
-            last = String::from("");
-        } else {
-            last = String::from(&operation[temporary_index..]);
-        }
+/// Pops the last `n` arguments from the output and pushes the call of
+/// `callee`, spanning from its name at `span` to `end`.
+fn apply_call(
+    output: &mut Vec<(Expr, Span)>,
+    callee: Callee,
+    span: Span,
+    n: usize,
+    end: Span,
+) -> Result<(), Error> {
+    if output.len() < n {
+        return Err(Error::new(ErrorKind::MissingOperand, span));
     }
-    if last != "" {
-        op_split.push(last);
+    if !callee.arity().accepts(n) {
+        let kind = ErrorKind::WrongArgumentCount(
+            callee.name().to_string(),
+            callee.arity(),
+            n,
+        );
+        return Err(Error::new(kind, span.to(end)));
     }
+    let args = output
+        .drain(output.len() - n..)
+        .map(|(arg, _)| arg)
+        .collect();
+    let node = match callee {
+        Callee::Builtin(f) => Expr::Call(f, args),
+        Callee::User(name, _) => Expr::UserCall(name, args, span),
+    };
+    output.push((node, span.to(end)));
+    Ok(())
+}
 
-    for c in op_split.iter() {
-        // Left parenthesis -> push stack
-        if c == "(" {
-            stack.add(c.clone());
-            continue;
+/// Pops the operands of `item` from the output and pushes the resulting
+/// node back, along with the span it covers.
+fn apply(output: &mut Vec<(Expr, Span)>, item: StackItem) -> Result<(), Error> {
+    let missing_operand = |span| Error::new(ErrorKind::MissingOperand, span);
+    let node = match item {
+        StackItem::Operator(op, span) => {
+            let (right, right_span) =
+                output.pop().ok_or_else(|| missing_operand(span))?;
+            let (left, left_span) =
+                output.pop().ok_or_else(|| missing_operand(span))?;
+            (
+                Expr::Binary(op, Box::new(left), Box::new(right), span),
+                left_span.to(right_span),
+            )
         }
-        // Right parenthesis -> discard, print stack until left parenthesis
-        if c == ")" {
-            let mut op = stack.pop();
-            while op != "(" {
-                print.push(op);
-                op = stack.pop();
-            }
-            continue;
+        StackItem::Angle(span) => {
+            let (angle, angle_span) =
+                output.pop().ok_or_else(|| missing_operand(span))?;
+            let (modulus, modulus_span) =
+                output.pop().ok_or_else(|| missing_operand(span))?;
+            (
+                Expr::Binary(
+                    Operator::Mul,
+                    Box::new(modulus),
+                    Box::new(Expr::Call(Function::Cis, vec![angle])),
+                    span,
+                ),
+                modulus_span.to(angle_span),
+            )
         }
-        // Operator
-        if is_operation(c) {
-            // empy stack or on top is  ( -> push on stack
-            if stack.is_empty() {
-                stack.add(c.clone());
-                continue;
-            }
+        StackItem::Negate(span) => {
+            let (arg, arg_span) =
+                output.pop().ok_or_else(|| missing_operand(span))?;
+            (Expr::Neg(Box::new(arg)), span.to(arg_span))
+        }
+        // Without parentheses a function takes the single operand after it
+        StackItem::Function(callee, span) => {
+            let end = output.last().map_or(span, |(_, arg_span)| *arg_span);
+            return apply_call(output, callee, span, 1, end);
+        }
+        StackItem::LeftParen(span)
+        | StackItem::CallParen(span, _, _)
+        | StackItem::Bracket(span, _, _) => {
+            return Err(Error::new(ErrorKind::UnbalancedParenthesis, span))
+        }
+    };
+    output.push(node);
+    Ok(())
+}
 
-            // Top MUST exist at this point - unwrap
-            let top = stack.top().unwrap();
-            if top == &"(" {
-                stack.add(c.clone());
-                continue;
-            }
+/// Checks that the output holds exactly `expected` arguments pushed since
+/// `base`, one expression for each.
+fn check_arguments(
+    output: &[(Expr, Span)],
+    base: usize,
+    expected: usize,
+    span: Span,
+) -> Result<(), Error> {
+    let found = output.len().saturating_sub(base);
+    if output.len() < base || found < expected {
+        Err(Error::new(ErrorKind::MissingOperand, span))
+    } else if found > expected {
+        Err(Error::new(
+            ErrorKind::MissingOperator,
+            output[base + expected].1,
+        ))
+    } else {
+        Ok(())
+    }
+}
 
-            let compare = compare_precedence(c, top);
-            match compare {
-                // higher precedence than top -> push
-                Precedence::Higher => {
-                    stack.add(c.clone());
-                    continue;
-                }
-                // lower precedence than top -> pop stack and print until it is not true, then push operator
-                // or same precedence with left association
-                _ => {
-                    print.push(stack.pop());
-                    while let Some(top) = stack.top() {
-                        if let Precedence::Lower = compare_precedence(c, top) {
-                            print.push(stack.pop());
-                        } else if let Precedence::Lower =
-                            compare_precedence(c, top)
-                        {
-                            print.push(stack.pop());
-                        } else {
-                            break;
-                        }
-                    }
-                    stack.add(c.clone());
-                    continue;
-                }
-            }
-        }
-        // any other case, just print the char
-        print.push(c.clone());
+/// The error for a comma in the parentheses opened at `open`. Written after
+/// a name, the parentheses were meant as a call of a function that does
+/// not exist.
+fn misplaced_comma(tokens: &[Token], open: Span, comma: Span) -> Error {
+    let paren = tokens.iter().position(|t| t.span == open);
+    match paren.and_then(|i| i.checked_sub(1)).map(|i| &tokens[i]) {
+        Some(Token {
+            kind: TokenKind::Identifier(name),
+            span,
+        }) => Error::new(ErrorKind::UnknownFunction(name.clone()), *span),
+        _ => Error::new(ErrorKind::MisplacedComma, comma),
     }
-    for op in stack.operations {
-        print.push(op);
+}
+
+/// The significant digits of a decimal, without its point, exponent and
+/// the zeros around them.
+fn significant_digits(text: &str) -> String {
+    let mantissa = text.split(['e', 'E']).next().unwrap_or("");
+    let digits: String =
+        mantissa.chars().filter(char::is_ascii_digit).collect();
+    digits.trim_matches('0').to_string()
+}
+
+/// A number literal written as `text` in the input, keeping the text when
+/// it has more digits than the double `z` holds.
+fn literal(z: Complex<f64>, text: &str) -> Expr {
+    let text: String = text.chars().filter(|c| *c != '_').collect();
+    let x = if z.im == 0.0 { z.re } else { z.im };
+    let digits = significant_digits(&format!("{:e}", x));
+    if significant_digits(&text).len() > digits.len() {
+        Expr::Decimal(z, text)
+    } else {
+        Expr::Number(z)
     }
-    print
 }
 
-const LETTERS: &str = "abcdefghijklmnopqrstuvwxyz ";
+fn operand(token: &Token, input: &str) -> Expr {
+    let text = &input[token.span.start..token.span.end];
+    match &token.kind {
+        TokenKind::Number(n) => literal(Complex::new(*n, 0.0), text),
+        TokenKind::Imaginary(n) => literal(Complex::new(0.0, *n), text),
+        TokenKind::Identifier(name) => match Constant::from_name(name) {
+            Some(c) => Expr::Constant(c),
+            None => Expr::Variable(name.clone(), token.span),
+        },
+        _ => unreachable!("{:?} is not an operand", token.kind),
+    }
+}
+
+/// Converts the operand last pushed to the output to radians, units apply
+/// to it before any operator, `2∠45°` is `2∠(45°)`.
+fn apply_unit(
+    output: &mut Vec<(Expr, Span)>,
+    unit: Unit,
+    span: Span,
+) -> Result<(), Error> {
+    let (value, value_span) = output
+        .pop()
+        .ok_or_else(|| Error::new(ErrorKind::MissingOperand, span))?;
+    let value = match unit {
+        Unit::Degree => Expr::Binary(
+            Operator::Div,
+            Box::new(Expr::Binary(
+                Operator::Mul,
+                Box::new(value),
+                Box::new(Expr::Constant(Constant::Pi)),
+                span,
+            )),
+            Box::new(Expr::Number(Complex::new(180.0, 0.0))),
+            span,
+        ),
+        Unit::Radian => value,
+    };
+    output.push((value, value_span.to(span)));
+    Ok(())
+}
+
+/// Whether an operator after `previous` has no operand to its left.
+fn is_prefix_position(previous: &[Token]) -> bool {
+    matches!(
+        previous.last().map(|t| &t.kind),
+        None | Some(TokenKind::Operator(_))
+            | Some(TokenKind::Angle)
+            | Some(TokenKind::Function(_))
+            | Some(TokenKind::UserFunction(_))
+            | Some(TokenKind::LeftParen)
+            | Some(TokenKind::LeftBracket)
+            | Some(TokenKind::Comma)
+    )
+}
+
+/// Whether `left` and `right` are written next to each other as a product,
+/// like `2z`, `3i z`, `2sin(z)`, `(z+1)(z-1)`, `2PI` or `M[1, 2]`.
+///
+/// The implied `*` has the same precedence as a written one, so `2z^2` is
+/// `2*(z^2)` and `1/2z` is `(1/2)*z`. A number on the right is never
+/// implied, `2 3` is still a missing operator.
+fn is_implicit_product(left: &Token, right: &Token) -> bool {
+    let ends_operand = matches!(
+        left.kind,
+        TokenKind::Number(_)
+            | TokenKind::Imaginary(_)
+            | TokenKind::Identifier(_)
+            | TokenKind::RightParen
+            | TokenKind::RightBracket
+            | TokenKind::Unit(_)
+    );
+    let starts_operand = matches!(
+        right.kind,
+        TokenKind::Imaginary(_)
+            | TokenKind::Identifier(_)
+            | TokenKind::Function(_)
+            | TokenKind::UserFunction(_)
+            | TokenKind::LeftParen
+            | TokenKind::LeftBracket
+    );
+    ends_operand && starts_operand
+}
+
+/// Whether `top` has to be applied before `current` is pushed.
+fn applies_before(current: &StackItem, top: &StackItem) -> bool {
+    match compare_precedence(current, top) {
+        Precedence::Lower => true,
+        Precedence::Equal => !is_right_associative(current),
+        Precedence::Higher => false,
+    }
+}
 
-pub fn num_to_letter_vec(n: usize) -> Vec<String> {
-    let letters = 25;
-    if n > letters {
-        let mut n = n;
-        let mut count: i32 = -1;
-        while n > letters {
-            n -= letters;
-            count += 1;
+/// Pushes a binary operator, first applying the operators on the stack that
+/// bind tighter.
+fn push_operator(
+    stack: &mut Vec<StackItem>,
+    output: &mut Vec<(Expr, Span)>,
+    current: StackItem,
+) -> Result<(), Error> {
+    // Parentheses have the lowest precedence, so this stops at ( as well
+    while let Some(top) = stack.last() {
+        if !applies_before(&current, top) {
+            break;
+        }
+        if let Some(top) = stack.pop() {
+            apply(output, top)?;
         }
-        let mut last = num_to_letter_vec(n);
-        let mut rest = num_to_letter_vec(count as usize);
-        rest.append(&mut last);
-        return rest;
-    } else {
-        return vec![String::from(&LETTERS[n..n + 1])];
     }
+    stack.push(current);
+    Ok(())
 }
 
-pub fn parse_complex_calculation(
-    mut operations: Vec<String>,
-) -> Option<Complex<f32>> {
-    let mut cache_counter = 0;
-    let mut cache_store: HashMap<String, Complex<f32>> = HashMap::new();
-    let mut answer = Complex::new(0.0, 0.0);
-
-    while operations.len() > 1 {
-        if operations.len() == 2 {
-            let first = &operations[0];
-            let second = &operations[1];
-            if !is_function(second) {
-                break;
+/// Builds the expression tree of `tokens`, with `user_arity` giving the
+/// number of parameters of the user functions they call.
+fn shunting_yard(
+    input: &str,
+    tokens: Vec<Token>,
+    user_arity: &dyn Fn(&str) -> Option<usize>,
+) -> Result<Expr, Error> {
+    let mut stack: Vec<StackItem> = vec![];
+    let mut output: Vec<(Expr, Span)> = vec![];
+
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && is_implicit_product(&tokens[i - 1], token) {
+            let span = Span::new(token.span.start, token.span.start);
+            push_operator(
+                &mut stack,
+                &mut output,
+                StackItem::Operator(Operator::Mul, span),
+            )?;
+        }
+
+        match &token.kind {
+            // Left parenthesis -> push stack
+            TokenKind::LeftParen => {
+                let is_call = i > 0
+                    && matches!(
+                        tokens[i - 1].kind,
+                        TokenKind::Function(_) | TokenKind::UserFunction(_)
+                    );
+                stack.push(if is_call {
+                    StackItem::CallParen(token.span, output.len(), 0)
+                } else {
+                    StackItem::LeftParen(token.span)
+                })
             }
-            let first = convert_to_complex(first, &cache_store);
-            let z1;
-            match first {
-                Some(v) => z1 = v,
-                None => {
-                    z1 = Complex::new(0.0, 0.0);
+            // Right parenthesis -> discard, apply stack until left parenthesis
+            TokenKind::RightParen => loop {
+                match stack.pop() {
+                    Some(StackItem::LeftParen(_)) => break,
+                    Some(StackItem::CallParen(open, base, commas)) => {
+                        // Empty parentheses are a call without arguments
+                        let n = if commas == 0 && output.len() == base {
+                            0
+                        } else {
+                            commas + 1
+                        };
+                        check_arguments(&output, base, n, open.to(token.span))?;
+                        if let Some(StackItem::Function(callee, span)) =
+                            stack.pop()
+                        {
+                            apply_call(
+                                &mut output,
+                                callee,
+                                span,
+                                n,
+                                token.span,
+                            )?;
+                        }
+                        break;
+                    }
+                    Some(item) => apply(&mut output, item)?,
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::UnbalancedParenthesis,
+                            token.span,
+                        ))
+                    }
                 }
+            },
+            TokenKind::LeftBracket => {
+                stack.push(StackItem::Bracket(token.span, output.len(), 0))
             }
-            let res = evaulate_function(second, z1);
-            answer = res;
-            break;
-        }
-        for i in 0..operations.len() {
-            let first = &operations[i];
-            let second = &operations[i + 1];
-
-            // If second is function, run function
-            if is_function(second) {
-                let first = convert_to_complex(first, &cache_store);
-                let z1;
-                match first {
-                    Some(v) => z1 = v,
+            // Right bracket -> apply stack until the left bracket, the
+            // elements in between make a matrix
+            TokenKind::RightBracket => loop {
+                match stack.pop() {
+                    Some(StackItem::Bracket(open, base, commas)) => {
+                        let span = open.to(token.span);
+                        check_arguments(&output, base, commas + 1, span)?;
+                        let elements = output
+                            .drain(base..)
+                            .map(|(element, _)| element)
+                            .collect();
+                        output.push((Expr::Matrix(elements, span), span));
+                        break;
+                    }
+                    Some(item) => apply(&mut output, item)?,
                     None => {
-                        z1 = Complex::new(0.0, 0.0);
+                        return Err(Error::new(
+                            ErrorKind::UnbalancedParenthesis,
+                            token.span,
+                        ))
                     }
                 }
-                let res = evaulate_function(second, z1);
-                let cache = format!("cache{}", cache_counter);
-                cache_counter += 1;
-
-                cache_store.insert(cache.clone(), res);
-                operations.insert(i + 2, cache);
-                operations = operations
-                    .iter()
-                    .enumerate()
-                    .filter(|(j, _)| {
-                        // filter first 2 indices
-                        j != &(i) && j != &(i + 1)
-                    })
-                    .map(|(_, v)| v.clone())
-                    .collect();
-                break;
-            }
-
-            let third = &operations[i + 2];
-            if !is_operation(second) && is_function(third) {
-                let second = convert_to_complex(second, &cache_store);
-                let z1;
-                match second {
-                    Some(v) => z1 = v,
+            },
+            // Comma -> apply stack until the parenthesis of the call or the
+            // bracket, which counts one more argument
+            TokenKind::Comma => loop {
+                match stack.last_mut() {
+                    Some(StackItem::CallParen(_, base, commas))
+                    | Some(StackItem::Bracket(_, base, commas)) => {
+                        check_arguments(
+                            &output,
+                            *base,
+                            *commas + 1,
+                            token.span,
+                        )?;
+                        *commas += 1;
+                        break;
+                    }
+                    Some(StackItem::LeftParen(open)) => {
+                        return Err(misplaced_comma(&tokens, *open, token.span))
+                    }
+                    Some(_) => {
+                        if let Some(item) = stack.pop() {
+                            apply(&mut output, item)?;
+                        }
+                    }
                     None => {
-                        z1 = Complex::new(0.0, 0.0);
+                        return Err(Error::new(
+                            ErrorKind::MisplacedComma,
+                            token.span,
+                        ))
                     }
                 }
-                let res = evaulate_function(third, z1);
-                let cache = format!("cache{}", cache_counter);
-                cache_counter += 1;
-
-                cache_store.insert(cache.clone(), res);
-                operations.insert(i + 1, cache);
-                operations = operations
-                    .iter()
-                    .enumerate()
-                    .filter(|(j, _)| {
-                        // filter index 2 and 3
-                        j != &(i + 2) && j != &(i + 3)
-                    })
-                    .map(|(_, v)| v.clone())
-                    .collect();
-                break;
+            },
+            // Functions are prefix, nothing to their left can be applied yet
+            TokenKind::Function(f) => {
+                stack.push(StackItem::Function(Callee::Builtin(*f), token.span))
             }
-            if !is_operation(second) && !is_operation(third) {
-                continue;
+            TokenKind::UserFunction(name) => {
+                let n = user_arity(name).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::UnknownFunction(name.clone()),
+                        token.span,
+                    )
+                })?;
+                stack.push(StackItem::Function(
+                    Callee::User(name.clone(), n),
+                    token.span,
+                ))
             }
-            let first = convert_to_complex(first, &cache_store);
-            let second = convert_to_complex(second, &cache_store);
-            let z1;
-            let z2;
-            match first {
-                Some(v) => z1 = v,
-                None => {
-                    z1 = Complex::new(0.0, 0.0);
+            // A sign with no operand before it is a prefix operator, a plus
+            // sign does nothing
+            TokenKind::Operator(op)
+                if (*op == Operator::Sub || *op == Operator::Add)
+                    && is_prefix_position(&tokens[..i]) =>
+            {
+                if *op == Operator::Sub {
+                    stack.push(StackItem::Negate(token.span));
                 }
             }
-            match second {
-                Some(v) => z2 = v,
-                None => {
-                    z2 = Complex::new(0.0, 0.0);
-                }
+            TokenKind::Operator(op) => push_operator(
+                &mut stack,
+                &mut output,
+                StackItem::Operator(*op, token.span),
+            )?,
+            TokenKind::Angle => push_operator(
+                &mut stack,
+                &mut output,
+                StackItem::Angle(token.span),
+            )?,
+            TokenKind::Unit(_) if is_prefix_position(&tokens[..i]) => {
+                return Err(Error::new(ErrorKind::MissingOperand, token.span))
             }
-            let res = evaluate_operation(third, z1, z2);
-            let cache = format!("cache{}", cache_counter);
-            cache_counter += 1;
-
-            cache_store.insert(cache.clone(), res);
-            operations.insert(i + 3, cache);
-            operations = operations
-                .iter()
-                .enumerate()
-                .filter(|(j, _)| {
-                    // filter current 3 indices
-                    j != &i && j != &(i + 1) && j != &(i + 2)
-                })
-                .map(|(_, v)| v.clone())
-                .collect();
-            answer = res;
-
-            break;
+            TokenKind::Unit(unit) => {
+                apply_unit(&mut output, *unit, token.span)?
+            }
+            // Definitions are only split off at the top of a statement
+            TokenKind::Define => {
+                return Err(Error::new(
+                    ErrorKind::InvalidDefinition,
+                    token.span,
+                ))
+            }
+            // And so are assignments, there is one at most
+            TokenKind::Assign => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedCharacter('='),
+                    token.span,
+                ))
+            }
+            // any other case is an operand
+            _ => output.push((operand(token, input), token.span)),
         }
     }
-    if operations.len() != 0 {
-        return Some(answer);
+    while let Some(item) = stack.pop() {
+        apply(&mut output, item)?;
     }
-    None
-}
 
-fn evaluate_operation(
-    op: &String,
-    z1: Complex<f32>,
-    z2: Complex<f32>,
-) -> Complex<f32> {
-    let res: Complex<f32>;
-    if op == "+" {
-        res = z1 + z2;
-    } else if op == "-" {
-        res = z1 - z2;
-    } else if op == "*" {
-        res = z1 * z2;
-    } else if op == "/" {
-        res = z1 / z2;
-    } else if op == "^" {
-        res = z1.powc(z2);
-    } else {
-        res = Complex::new(0.0, 0.0);
+    match output.len() {
+        0 => Err(Error::new(ErrorKind::EmptyExpression, Span::default())),
+        1 => Ok(output.remove(0).0),
+        _ => Err(Error::new(ErrorKind::MissingOperator, output[1].1)),
     }
-    res
 }
 
-fn evaulate_function(fx: &String, z: Complex<f32>) -> Complex<f32> {
-    let res: Complex<f32>;
-    if fx == "cos" {
-        res = z.cos();
-    } else if fx == "sin" {
-        res = z.sin();
-    } else if fx == "tan" {
-        res = z.tan();
-    } else if fx == "csc" {
-        res = 1.0 / z.sin();
-    } else if fx == "sec" {
-        res = 1.0 / z.cos();
-    } else if fx == "cot" {
-        res = 1.0 / z.tan();
-    } else if fx == "asin" {
-        res = z.asin();
-    } else if fx == "acos" {
-        res = z.acos();
-    } else if fx == "atan" {
-        res = z.atan();
-    } else if fx == "sinh" {
-        res = z.sinh();
-    } else if fx == "cosh" {
-        res = z.cosh();
-    } else if fx == "tanh" {
-        res = z.tanh();
-    } else if fx == "asinh" {
-        res = z.asinh();
-    } else if fx == "acosh" {
-        res = z.acosh();
-    } else if fx == "atanh" {
-        res = z.atanh();
-    } else if fx == "inv" {
-        res = z.inv();
-    } else if fx == "conj" {
-        res = z.conj();
-    } else if fx == "exp" {
-        res = z.exp();
-    } else if fx == "sqrt" {
-        res = z.sqrt();
-    } else if fx == "cbrt" {
-        res = z.cbrt();
-    } else if fx == "ln" {
-        res = z.ln();
-    } else {
-        res = Complex::new(0.0, 0.0);
-    }
-    res
+/// An input of the calculator, an expression to evaluate, a function to
+/// define or a value to save in a variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Expr(Expr),
+    Definition(Definition),
+    Assignment(String, Expr),
 }
 
-fn convert_to_complex(
-    s: &String,
-    cache: &HashMap<String, Complex<f32>>,
-) -> Option<Complex<f32>> {
-    if s.starts_with("cache") {
-        if let Some(z) = cache.get(s) {
-            return Some(*z);
-        } else {
-            return None;
+/// The variable the calculator keeps its last result in.
+pub const ANSWER: &str = "ans";
+
+/// Checks that `name` can name a variable, an identifier that is not `i`,
+/// [`ANSWER`], a constant or a function, built in or in `definitions`.
+pub fn check_name(name: &str, definitions: &Definitions) -> Result<(), Error> {
+    let tokens = tokenize_with(name, &|name| definitions.contains(name))?;
+    let invalid = || {
+        let span = Span::new(0, name.len());
+        Err(Error::new(
+            ErrorKind::InvalidName(name.trim().to_string()),
+            span,
+        ))
+    };
+    let token = match tokens.as_slice() {
+        [token] => token,
+        _ => return invalid(),
+    };
+    let reserved = |name: &str| {
+        Err(Error::new(
+            ErrorKind::ReservedName(name.to_string()),
+            token.span,
+        ))
+    };
+    match &token.kind {
+        TokenKind::Identifier(name)
+            if name == ANSWER || Constant::from_name(name).is_some() =>
+        {
+            reserved(name)
         }
+        TokenKind::Identifier(_) => Ok(()),
+        TokenKind::Imaginary(_) if name.trim() == "i" => reserved("i"),
+        TokenKind::Function(f) => reserved(f.name()),
+        TokenKind::UserFunction(name) => reserved(name),
+        _ => invalid(),
     }
+}
+
+/// Parses an input into an expression tree.
+pub fn parse(input: &str) -> Result<Expr, Error> {
+    parse_with(input, &Definitions::default())
+}
+
+/// Parses an input that may call the user functions in `definitions`.
+pub fn parse_with(
+    input: &str,
+    definitions: &Definitions,
+) -> Result<Expr, Error> {
+    let tokens = tokenize_with(input, &|name| definitions.contains(name))?;
+    shunting_yard(input, tokens, &|name| {
+        definitions.get(name).map(|d| d.params.len())
+    })
+}
 
-    let s = insert_constants(s);
-    if s.contains("i") {
-        let mut s = s.replace("i", "");
-        if s == "" {
-            s = "1".to_string();
+/// Parses a list of expressions separated by commas, like the coefficients
+/// `1, 0, -2i` of a polynomial. Commas inside calls and brackets belong to
+/// them.
+pub fn parse_list(
+    input: &str,
+    definitions: &Definitions,
+) -> Result<Vec<Expr>, Error> {
+    let tokens = tokenize_with(input, &|name| definitions.contains(name))?;
+    let user_arity = |name: &str| definitions.get(name).map(|d| d.params.len());
+    let mut items = vec![];
+    let mut item = vec![];
+    let mut depth = 0;
+    let mut comma = None;
+    for token in tokens {
+        match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+            TokenKind::RightParen | TokenKind::RightBracket => depth -= 1,
+            TokenKind::Comma if depth == 0 => {
+                if item.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::MissingOperand,
+                        token.span,
+                    ));
+                }
+                items.push(shunting_yard(
+                    input,
+                    std::mem::take(&mut item),
+                    &user_arity,
+                )?);
+                comma = Some(token.span);
+                continue;
+            }
+            _ => {}
         }
-        if let Ok(n) = s.parse::<f32>() {
-            let z = Complex::new(0.0, n);
-            Some(z)
-        } else {
-            None
+        item.push(token);
+    }
+    // A comma at the end leaves the last item empty
+    match comma {
+        Some(span) if item.is_empty() => {
+            Err(Error::new(ErrorKind::MissingOperand, span))
         }
-    } else {
-        if let Ok(n) = s.parse::<f32>() {
-            let z = Complex::new(n, 0.0);
-            Some(z)
-        } else {
-            None
+        _ => {
+            items.push(shunting_yard(input, item, &user_arity)?);
+            Ok(items)
         }
     }
 }
 
-fn insert_constants(s: &str) -> String {
-    if s.contains("PI") {
-        let s = s.replace("PI", &PI.to_string());
-        return s;
-    } else if s.contains("E") {
-        let s = s.replace("E", &E.to_string());
-        return s;
+/// Splits the name and parameters off the left side of a definition,
+/// `f(z, w)`.
+fn definition_head(
+    tokens: &[Token],
+    define: Span,
+) -> Result<(String, Vec<String>), Error> {
+    let head = match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => define,
+    };
+    let invalid = || Error::new(ErrorKind::InvalidDefinition, head);
+    let name = |token: &Token| match &token.kind {
+        TokenKind::Identifier(name) | TokenKind::UserFunction(name)
+            if Constant::from_name(name).is_none() =>
+        {
+            Ok(name.clone())
+        }
+        TokenKind::Identifier(name) => Err(Error::new(
+            ErrorKind::ReservedName(name.clone()),
+            token.span,
+        )),
+        TokenKind::Function(f) => Err(Error::new(
+            ErrorKind::ReservedName(f.name().to_string()),
+            token.span,
+        )),
+        _ => Err(invalid()),
+    };
+
+    let is_call = tokens.len() >= 4
+        && tokens[1].kind == TokenKind::LeftParen
+        && tokens[tokens.len() - 1].kind == TokenKind::RightParen;
+    if !is_call {
+        return Err(invalid());
     }
-    s.to_string()
-}
+    let function = name(&tokens[0])?;
 
-fn is_operation(s: &String) -> bool {
-    s == "+" || s == "-" || s == "*" || s == "/" || s == "^" || is_function(s)
+    // Parameters and commas alternate, starting and ending with a parameter
+    let inside = &tokens[2..tokens.len() - 1];
+    if inside.len() % 2 != 1 {
+        return Err(invalid());
+    }
+    let mut params: Vec<String> = vec![];
+    for (k, token) in inside.iter().enumerate() {
+        if k % 2 == 1 {
+            if token.kind != TokenKind::Comma {
+                return Err(invalid());
+            }
+            continue;
+        }
+        let param = match token.kind {
+            TokenKind::UserFunction(_) => return Err(invalid()),
+            _ => name(token)?,
+        };
+        if params.contains(&param) {
+            return Err(Error::new(ErrorKind::InvalidDefinition, token.span));
+        }
+        params.push(param);
+    }
+    Ok((function, params))
 }
-fn is_function(s: &String) -> bool {
-    s == "sin"
-        || s == "cos"
-        || s == "tan"
-        || s == "csc"
-        || s == "sec"
-        || s == "cot"
-        || s == "asin"
-        || s == "acos"
-        || s == "atan"
-        || s == "sinh"
-        || s == "cosh"
-        || s == "tanh"
-        || s == "asinh"
-        || s == "acosh"
-        || s == "atanh"
-        || s == "inv"
-        || s == "conj"
-        || s == "exp"
-        || s == "ln"
-        || s == "sqrt"
-        || s == "cbrt"
+
+/// Parses a calculator input, either an expression, a definition like
+/// `f(z) := z^2 + c` or an assignment like `r = 2∠45°`.
+///
+/// The body of a definition may call the function itself, evaluation stops
+/// such calls after [`MAX_CALL_DEPTH`](crate::parser::ast::MAX_CALL_DEPTH)
+/// levels.
+pub fn parse_statement(
+    input: &str,
+    definitions: &Definitions,
+) -> Result<Statement, Error> {
+    let mut tokens = tokenize_with(input, &|name| definitions.contains(name))?;
+    let user_arity = |name: &str| definitions.get(name).map(|d| d.params.len());
+    let define = match tokens.iter().position(|t| t.kind == TokenKind::Define) {
+        Some(define) => define,
+        None => {
+            return match tokens.iter().position(|t| t.kind == TokenKind::Assign)
+            {
+                Some(assign) => {
+                    let name = input[..tokens[assign].span.start].trim();
+                    check_name(name, definitions)?;
+                    let value = shunting_yard(
+                        input,
+                        tokens.split_off(assign + 1),
+                        &user_arity,
+                    )?;
+                    Ok(Statement::Assignment(name.to_string(), value))
+                }
+                None => shunting_yard(input, tokens, &user_arity)
+                    .map(Statement::Expr),
+            }
+        }
+    };
+
+    let body = tokens.split_off(define + 1);
+    let define = tokens.pop().map_or(Span::default(), |t| t.span);
+    let (name, params) = definition_head(&tokens, define)?;
+
+    // The name is only known as a function once the head is read
+    let body = body
+        .into_iter()
+        .map(|token| match token.kind {
+            TokenKind::Identifier(n) if n == name => Token {
+                kind: TokenKind::UserFunction(n),
+                span: token.span,
+            },
+            _ => token,
+        })
+        .collect();
+    let arity = params.len();
+    let body = shunting_yard(input, body, &|n| {
+        if n == name {
+            Some(arity)
+        } else {
+            definitions.get(n).map(|d| d.params.len())
+        }
+    })?;
+
+    Ok(Statement::Definition(Definition {
+        name,
+        params,
+        body,
+        source: input.trim().to_string(),
+    }))
 }
 
-pub fn test_ops() {
-    let operation = String::from("AA");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ast::Context;
+    use std::f64::consts::PI;
+
+    fn eval(input: &str) -> Complex<f64> {
+        let env: [(&str, Complex<f64>); 1] = [("z", Complex::new(2.0, 1.0))];
+        parse(input).and_then(|expr| expr.eval(&env[..])).unwrap()
+    }
+
+    fn assert_close(input: &str, expected: Complex<f64>) {
+        let z = eval(input);
+        assert!(
+            (z - expected).norm() < 1e-9,
+            "{} = {}, expected {}",
+            input,
+            z,
+            expected
+        );
+    }
+
+    #[test]
+    fn parses_precedence() {
+        assert_close("2 + 3 * 4", Complex::new(14.0, 0.0));
+        assert_close("(2 + 3) * 4", Complex::new(20.0, 0.0));
+        assert_close(
+            "12 * 32 * (14 / 2 + 20i) + i",
+            Complex::new(2688.0, 7681.0),
+        );
+        assert_close("(2i+1)^2", Complex::new(-3.0, 4.0));
+        assert_close("cos(sin(i^2))", Complex::new(-1.0, 0.0).sin().cos());
+    }
+
+    #[test]
+    fn parses_unary_minus() {
+        let z = Complex::new(2.0, 1.0);
+        assert_close("-z", -z);
+        assert_close("-z^2", -(z * z));
+        assert_close("2*-i", Complex::new(0.0, -2.0));
+        assert_close("exp(-z^2)", (-(z * z)).exp());
+        assert_close("(-1)^0.5", Complex::new(0.0, 1.0));
+        assert_close("2^-1", Complex::new(0.5, 0.0));
+        assert_close("-2*3", Complex::new(-6.0, 0.0));
+        assert_close("--z", z);
+        assert_close("1 - -z", 1.0 + z);
+    }
+
+    #[test]
+    fn parses_unary_plus() {
+        assert_close("+z", Complex::new(2.0, 1.0));
+        assert_close("3*+2", Complex::new(6.0, 0.0));
+        assert_close("-(+i)", Complex::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn parses_implicit_multiplication() {
+        let z = Complex::new(2.0, 1.0);
+        let i = Complex::new(0.0, 1.0);
+        assert_close("2z", 2.0 * z);
+        assert_close("3i z", 3.0 * i * z);
+        assert_close("2sin(z)", 2.0 * z.sin());
+        assert_close("(z+1)(z-1)", (z + 1.0) * (z - 1.0));
+        assert_close("2PI", Complex::new(2.0 * std::f64::consts::PI, 0.0));
+        assert_close("2z^2", 2.0 * z * z);
+        assert_close("1/2z", 0.5 * z);
+        assert_close("-2z", -2.0 * z);
+        assert_close("z(z+1)", z * (z + 1.0));
+        assert_close("(z)2i", z * 2.0 * i);
+    }
+
+    /// Inputs next to the same expression with every grouping written out.
+    const CONFORMANCE: &[(&str, &str)] = &[
+        ("2^3^2", "2^(3^2)"),
+        ("z^2^0.5", "z^(2^0.5)"),
+        ("(z^2)^3", "(z^2)^3"),
+        ("-z^2", "-(z^2)"),
+        ("-2^-2", "-(2^(-2))"),
+        ("2^-z^2", "2^(-(z^2))"),
+        ("-z*2", "(-z)*2"),
+        ("-z^2*3", "(-(z^2))*3"),
+        ("1 - 2 - 3", "(1 - 2) - 3"),
+        ("1 - 2 + 3", "(1 - 2) + 3"),
+        ("8 / 4 / 2", "(8 / 4) / 2"),
+        ("8 / 4 * 2", "(8 / 4) * 2"),
+        ("2 * 3 + 4 * z", "(2 * 3) + (4 * z)"),
+        ("1 + 2 * 3 - 4", "(1 + (2 * 3)) - 4"),
+        ("sin z^2", "sin(z^2)"),
+        ("sin z * 2", "(sin(z)) * 2"),
+        ("sin(z)^2", "(sin(z))^2"),
+        ("-sin z^2", "-(sin(z^2))"),
+        ("sin -z", "sin(-z)"),
+        ("exp(z)^-1", "(exp(z))^(-1)"),
+        ("2^sin(z)", "2^(sin(z))"),
+        ("2^sin z", "2^(sin(z))"),
+        ("cos sin z", "cos(sin(z))"),
+        ("ln(z)ln(z)", "(ln(z))*(ln(z))"),
+        ("2z^2^2", "2*(z^(2^2))"),
+        ("2∠PI/4", "(2∠PI)/4"),
+        ("1 + 2∠z * 3", "1 + ((2∠z) * 3)"),
+        ("-2∠z^2", "(-2)∠(z^2)"),
+        ("2^3°", "2^(3°)"),
+    ];
+
+    #[test]
+    fn conforms_to_precedence_table() {
+        for (input, grouped) in CONFORMANCE.iter() {
+            assert_close(input, eval(grouped));
+        }
+    }
 
-    let operation = String::from("AB * BC");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn prints_what_it_parses() {
+        for input in [
+            "2 + 3 * 4",
+            "-z^2 / (1 - z)",
+            "2^3^2",
+            "(2^3)^2",
+            "sin(z)^2 + cos z^2",
+            "2z(z + i) - 3i",
+            "log(z, 2) + max(re z, 1, im z)",
+            "1 - (2 - z)",
+            "-(z + 1)",
+            "2∠45°",
+            "e^(-z) PI",
+        ]
+        .iter()
+        {
+            let expr = parse(input).unwrap();
+            let printed = expr.to_string();
+            let reparsed = parse(&printed).unwrap();
+            assert_eq!(reparsed.to_string(), printed, "{}", input);
+            assert_eq!(eval(&printed), eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_number_literals() {
+        let e = std::f64::consts::E;
+        assert_close("1e-3", Complex::new(1e-3, 0.0));
+        assert_close("6.02E23 / 1E23", Complex::new(6.02, 0.0));
+        assert_close("2.5E+4i", Complex::new(0.0, 2.5e4));
+        assert_close(".5 + 1_000", Complex::new(1000.5, 0.0));
+        assert_close("2e", Complex::new(2.0 * e, 0.0));
+        assert_close("e^2", Complex::new(e * e, 0.0));
+        assert_close("E", Complex::new(e, 0.0));
+        // Digits past those of a double are kept with the rounded value
+        let long = "3.14159265358979323846";
+        assert_eq!(
+            parse(long).unwrap(),
+            Expr::Decimal(Complex::new(std::f64::consts::PI, 0.0), long.into())
+        );
+        assert_eq!(
+            parse("1.000_000_000_000_000_000_1i").unwrap(),
+            Expr::Decimal(Complex::i(), "1.0000000000000000001i".into())
+        );
+        assert_eq!(
+            parse("0.10").unwrap(),
+            Expr::Number(Complex::new(0.1, 0.0))
+        );
+        assert_eq!(parse(long).unwrap().to_string(), long);
+    }
 
-    let operation = String::from("AA * BB + CC");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_polar_literals() {
+        let expected = Complex::from_polar(2.0, PI / 4.0);
+        for input in [
+            "2∠45°",
+            "2∠45deg",
+            "2∠(PI/4)",
+            "2∠(PI/4)rad",
+            "2 cis(PI/4)",
+            "2e^(i PI/4)",
+            "2∠(30 + 15)°",
+        ]
+        .iter()
+        {
+            assert_close(input, expected);
+        }
+        assert_close("3e^(i PI/2)", Complex::new(0.0, 3.0));
+        assert_close("2∠45° * 3∠-15°", Complex::from_polar(6.0, PI / 6.0));
+        assert_close("sin 30°", Complex::new(0.5, 0.0));
+        assert_close("1rad", Complex::new(1.0, 0.0));
+        assert_eq!(parse("2∠45°").unwrap().to_string(), "2*cis(45*PI/180)");
 
-    let operation = String::from("AA + BB * CC");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+        let kind = |input| parse(input).unwrap_err().kind;
+        assert_eq!(kind("°"), ErrorKind::MissingOperand);
+        assert!(matches!(parse("2 + deg").unwrap(), Expr::Binary(..)));
+        assert_eq!(kind("2∠"), ErrorKind::MissingOperand);
+        assert_eq!(kind("2*°"), ErrorKind::MissingOperand);
+    }
 
-    let operation = String::from("AA * (BB+CCC)");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_matrices() {
+        let expr = parse("2[[1, -z], [i, det M]] [1, 2]").unwrap();
+        assert_eq!(expr.to_string(), "2*[[1, -z], [i, det(M)]]*[1, 2]");
+        let kind = |input| parse(input).unwrap_err().kind;
+        assert_eq!(kind("[]"), ErrorKind::MissingOperand);
+        assert_eq!(kind("[1, 2"), ErrorKind::UnbalancedParenthesis);
+        assert_eq!(kind("(1, 2]"), ErrorKind::MisplacedComma);
+        assert_eq!(kind("[1 + 2)"), ErrorKind::UnbalancedParenthesis);
+        assert_eq!(kind("[1,]"), ErrorKind::MissingOperand);
+    }
 
-    let operation = String::from("A * BB^ C + DD");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_lists() {
+        let definitions = Definitions::default();
+        let list = parse_list("1, pow(z, 2), -[1, i]", &definitions).unwrap();
+        let items: Vec<_> = list.iter().map(|e| e.to_string()).collect();
+        assert_eq!(items, ["1", "pow(z, 2)", "-[1, i]"]);
+        let error = |input| parse_list(input, &definitions).unwrap_err();
+        assert_eq!(error("1, , 2").span, Span::new(3, 4));
+        assert_eq!(error("1, 2,").span, Span::new(4, 5));
+        assert_eq!(error("1, (2").kind, ErrorKind::UnbalancedParenthesis);
+    }
 
-    let operation = String::from("AA * (B + C * DDDD) + EB");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn reports_errors() {
+        let kind = |input| parse(input).unwrap_err().kind;
+        assert_eq!(kind("(1 + 2"), ErrorKind::UnbalancedParenthesis);
+        assert_eq!(kind("1 + 2)"), ErrorKind::UnbalancedParenthesis);
+        assert_eq!(kind("2 *"), ErrorKind::MissingOperand);
+        assert_eq!(kind("-"), ErrorKind::MissingOperand);
+        assert_eq!(kind("2 3"), ErrorKind::MissingOperator);
+        assert_eq!(kind("2 $ 3"), ErrorKind::UnexpectedCharacter('$'));
+        assert_eq!(kind(""), ErrorKind::EmptyExpression);
+        assert_eq!(kind("1.2.3"), ErrorKind::MalformedNumber("1.2.3".into()));
+        assert_eq!(kind("1, 2"), ErrorKind::MisplacedComma);
+        assert_eq!(kind("(1, 2)"), ErrorKind::MisplacedComma);
+        assert_eq!(kind("h(1, 2)"), ErrorKind::UnknownFunction("h".into()));
+        assert_eq!(kind("pow(1, )"), ErrorKind::MissingOperand);
+        assert_eq!(kind("pow(1 2, 3)"), ErrorKind::MissingOperator);
+        assert_eq!(kind("f(z) := z"), ErrorKind::InvalidDefinition);
+    }
 
-    let operation = String::from("12 * 32 * (14 / 2 + i20) + i");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn reports_error_spans() {
+        let error = |input| parse(input).unwrap_err();
+        let span = |input| error(input).span;
+        // The unclosed parenthesis, or the closing one without a match
+        assert_eq!(span("(1 + 2"), Span::new(0, 1));
+        assert_eq!(span("2 * (3 + sin(4)"), Span::new(4, 5));
+        assert_eq!(span("[1, (2]"), Span::new(4, 5));
+        assert_eq!(span("1 + 2)"), Span::new(5, 6));
+        // The operator missing an operand, or the call missing an argument
+        assert_eq!(span("2 *"), Span::new(2, 3));
+        assert_eq!(span("1 + * 2"), Span::new(2, 3));
+        assert_eq!(span("pow(1, )"), Span::new(3, 8));
+        assert_eq!(error("pow(1, )").kind, ErrorKind::MissingOperand);
+        // Unknown names, when parsing functions and evaluating variables
+        assert_eq!(span("2 + h(1, 2)"), Span::new(4, 5));
+        let env: [(&str, Complex<f64>); 0] = [];
+        let error = parse("2 + w * 3").unwrap().eval::<f64, _>(&env[..]);
+        let error = error.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownIdentifier("w".into()));
+        assert_eq!(error.span, Span::new(4, 5));
+    }
 
-    let operation = String::from("tan(z)");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
-    let operation = String::from("2 * sin(x + 1)");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_multi_argument_functions() {
+        let z = Complex::new(2.0, 1.0);
+        assert_close("log(8, 2)", Complex::new(3.0, 0.0));
+        assert_close("log(z, e)", z.ln());
+        assert_close("pow(z, 3)", z * z * z);
+        assert_close("root(-8, 3)", Complex::new(-8.0, 0.0).powf(1.0 / 3.0));
+        assert_close("atan2(1, -1)", Complex::new(0.75 * PI, 0.0));
+        assert_close("min(3, z, -i)", Complex::new(0.0, -1.0));
+        assert_close("max(3, z, -i)", Complex::new(3.0, 0.0));
+        assert_close("max(z)", z);
+        assert_close("pow(z, 2)^2", z * z * z * z);
+        assert_close("2pow(-z, 1) - 1", -2.0 * z - 1.0);
+        assert_close("log(pow(2, 10), 2 * 2)", Complex::new(5.0, 0.0));
+    }
 
-    let operation = String::from("2 * sin((z^2 + 2i)/2z)");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_branches() {
+        let z = Complex::new(2.0, 1.0);
+        let i = Complex::new(0.0, 1.0);
+        assert_close("ln(z, 0)", z.ln());
+        assert_close("ln(z, -2)", z.ln() - 4.0 * PI * i);
+        assert_close("sqrt(4, 1)", Complex::new(-2.0, 0.0));
+        assert_close("sqrt(z, 3)", -z.sqrt());
+        assert_close("root(8, 3, 1)", Complex::from_polar(2.0, 2.0 * PI / 3.0));
+        assert_close("root(8, 3, 3)", Complex::new(2.0, 0.0));
+    }
 
-    let operation = String::from("tan(sin(cos(1 / i + 2 + i)))");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn checks_argument_counts() {
+        let kind = |input| parse(input).unwrap_err().kind;
+        let count = |name: &str, arity, found| {
+            ErrorKind::WrongArgumentCount(name.to_string(), arity, found)
+        };
+        assert_eq!(kind("sin(1, 2)"), count("sin", Arity::Exactly(1), 2));
+        assert_eq!(kind("log(8)"), count("log", Arity::Exactly(2), 1));
+        assert_eq!(kind("pow z"), count("pow", Arity::Exactly(2), 1));
+        assert_eq!(kind("min()"), count("min", Arity::AtLeast(1), 0));
+        assert_eq!(kind("besselj(z)"), count("besselj", Arity::Exactly(2), 1));
+        assert_eq!(
+            kind("lambertw(z, 1, 2)"),
+            count("lambertw", Arity::Between(1, 2), 3)
+        );
+    }
 
-    println!("{:?}", parse_complex_calculation(res));
+    fn define(definitions: &mut Definitions, input: &str) {
+        match parse_statement(input, definitions).unwrap() {
+            Statement::Definition(d) => definitions.insert(d),
+            _ => panic!("{} is not a definition", input),
+        }
+    }
 
-    let operation = String::from("cos(sin(i^2))");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_definitions() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := z^2 + 1");
+        define(
+            &mut definitions,
+            "dist(a, b) := sqrt((a - b) * conj(a - b))",
+        );
 
-    println!("{:?}", parse_complex_calculation(res));
+        let f = definitions.get("f").unwrap();
+        assert_eq!(f.params, vec!["z".to_string()]);
+        assert_eq!(f.source, "f(z) := z^2 + 1");
 
-    let operation = String::from("12 * 32 * (14 / 2 + 20i) + i");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+        let eval = |input| {
+            let env: [(&str, Complex<f64>); 1] =
+                [("z", Complex::new(2.0, 1.0))];
+            parse_with(input, &definitions)
+                .and_then(|expr| {
+                    expr.eval_with(&env[..], &Context::new(&definitions))
+                })
+                .unwrap()
+        };
+        let z = Complex::new(2.0, 1.0);
+        assert!((eval("f(z)") - (z * z + 1.0)).norm() < 1e-12);
+        assert!((eval("2f(i)") - Complex::new(0.0, 0.0)).norm() < 1e-12);
+        assert!((eval("f z^2") - (z.powi(4) + 1.0)).norm() < 1e-12);
+        assert!((eval("dist(3i, 4)") - Complex::new(5.0, 0.0)).norm() < 1e-12);
+        assert!((eval("f(f(0))") - Complex::new(2.0, 0.0)).norm() < 1e-12);
+        assert!(matches!(
+            parse_statement("f(1)", &definitions),
+            Ok(Statement::Expr(_))
+        ));
+    }
 
-    println!("{:?}", parse_complex_calculation(res));
+    #[test]
+    fn rejects_invalid_definitions() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := z");
+        let kind =
+            |input| parse_statement(input, &definitions).unwrap_err().kind;
+        assert_eq!(kind("f := 2"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("f(2) := 2"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("g() := 2"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("g(z, z) := z"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("g(z,) := z"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("g(f) := f"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("g(z) := z := z"), ErrorKind::InvalidDefinition);
+        assert_eq!(kind("sin(z) := z"), ErrorKind::ReservedName("sin".into()));
+        assert_eq!(kind("g(PI) := 2"), ErrorKind::ReservedName("PI".into()));
+        assert_eq!(kind("g(z) := "), ErrorKind::EmptyExpression);
+        assert_eq!(
+            kind("f(1, 2)"),
+            ErrorKind::WrongArgumentCount("f".into(), Arity::Exactly(1), 2)
+        );
+    }
 
-    let operation = String::from("(2i+1)^2");
-    let res = shunting_yard(operation);
-    println!("{:?}", res);
+    #[test]
+    fn parses_assignments() {
+        let mut definitions = Definitions::default();
+        define(&mut definitions, "f(z) := z^2");
+        match parse_statement(" rate_2 = f(2) + 1 ", &definitions) {
+            Ok(Statement::Assignment(name, value)) => {
+                assert_eq!(name, "rate_2");
+                assert_eq!(value.to_string(), "f(2) + 1");
+            }
+            other => panic!("not an assignment: {:?}", other),
+        }
+        let kind =
+            |input| parse_statement(input, &definitions).unwrap_err().kind;
+        let reserved = |name: &str| ErrorKind::ReservedName(name.to_string());
+        assert_eq!(kind("pi = 3"), reserved("pi"));
+        assert_eq!(kind("tau = 3"), reserved("tau"));
+        assert_eq!(kind("i = 3"), reserved("i"));
+        assert_eq!(kind("ans = 3"), reserved("ans"));
+        assert_eq!(kind("sin = 3"), reserved("sin"));
+        assert_eq!(kind("f = 3"), reserved("f"));
+        assert_eq!(kind("2x = 3"), ErrorKind::InvalidName("2x".into()));
+        assert_eq!(kind("g(z) = 3"), ErrorKind::InvalidName("g(z)".into()));
+        assert_eq!(kind(" = 3"), ErrorKind::InvalidName("".into()));
+        assert_eq!(kind("a = 3 = b"), ErrorKind::UnexpectedCharacter('='));
+        assert_eq!(kind("a = "), ErrorKind::EmptyExpression);
+        assert!(check_name("x", &definitions).is_ok());
+        assert!(check_name("deg", &definitions).is_ok());
+    }
 
-    println!("{:?}", parse_complex_calculation(res));
+    #[test]
+    fn parses_constants() {
+        use std::f64::consts::E;
+        assert_close("pi", Complex::new(PI, 0.0));
+        assert_close("tau - 2PI", Complex::new(0.0, 0.0));
+        assert_close("phi^2 - phi - 1", Complex::new(0.0, 0.0));
+        assert_close("e", Complex::new(E, 0.0));
+        assert_close("i^2", Complex::new(-1.0, 0.0));
+        for constant in Constant::ALL.iter() {
+            assert_eq!(Constant::from_name(constant.name()), Some(*constant));
+        }
+    }
 }
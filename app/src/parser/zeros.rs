@@ -0,0 +1,607 @@
+use crate::parser::ast::{Context, Expr};
+use crate::parser::derivatives::derivative;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::evaluations::Progress;
+use crate::parser::integrals::integrate;
+use crate::parser::lexer::Span;
+use num_complex::Complex;
+use num_traits::Zero;
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_4, TAU};
+
+/// Segments a boundary is cut into before they are bisected.
+const SEGMENTS: usize = 64;
+
+/// How many times a segment may be halved while the argument turns too
+/// fast along it, as it does close to a zero or a pole.
+const MAX_BISECTIONS: usize = 24;
+
+/// Levels of subdivision every cell goes through before its moments are
+/// checked, so they are integrated along short sides.
+const MIN_DEPTH: usize = 3;
+
+/// Levels of subdivision before a cell is reported without refining it.
+const MAX_DEPTH: usize = 16;
+
+const MAX_NEWTON_STEPS: usize = 50;
+
+/// Where along a side cells are split, the middle first and then off it,
+/// in case a zero or a pole lies on the middle line.
+const SPLITS: [f64; 3] = [0.5, 0.5173, 0.4741];
+
+/// How far, relative to the size of a cell, its zeros and poles may be from
+/// where its moments put them.
+const MOMENT_TOLERANCE: f64 = 1e-4;
+
+/// Where zeros and poles are looked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    /// The rectangle between its lower left and upper right corners.
+    Rectangle(Complex<f64>, Complex<f64>),
+    /// The disk with a center and a radius.
+    Circle(Complex<f64>, f64),
+}
+
+/// A zero, with a positive order, or a pole, with a negative one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Located {
+    pub z: Complex<f64>,
+    pub order: i32,
+}
+
+/// The zeros and poles found in a region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Census {
+    /// Zeros minus poles, each counted with its order, by the argument
+    /// principle along the boundary.
+    pub count: i32,
+    pub found: Vec<Located>,
+}
+
+/// Locates the zeros and poles of `expr`, a function of `variable`, in
+/// `region`. Newton's method uses the symbolic derivative when there is
+/// one, and central differences otherwise.
+pub fn locate_expr(
+    expr: &Expr,
+    variable: &str,
+    variables: &HashMap<String, Complex<f64>>,
+    context: &Context,
+    region: Region,
+    progress: &Progress,
+) -> Result<Census, Error> {
+    let probe = match region {
+        Region::Rectangle(min, max) => (min + max) / 2.0,
+        Region::Circle(center, _) => center,
+    };
+    let f = expr.function_of(variable, variables, context, probe)?;
+    let derivative = derivative(expr, variable, context.definitions).ok();
+    let df = match &derivative {
+        Some(derivative) => {
+            Some(derivative.function_of(variable, variables, context, probe)?)
+        }
+        None => None,
+    };
+    match &df {
+        Some(df) => locate(&f, Some(df), region, progress),
+        None => locate(&f, None, region, progress),
+    }
+}
+
+/// Locates the zeros and poles of a meromorphic `f` in `region`, with
+/// their orders.
+///
+/// The argument principle counts the zeros minus the poles inside a closed
+/// curve by how many times `f` turns around the origin along it. The
+/// region is split into cells, and cells are split again while they hold
+/// zeros or poles, until Newton's method from their center converges
+/// inside them. A zero or pole of order `m` is a simple zero of `f^(1/m)`,
+/// so the steps are `m f/f'`, with `f'` given by `derivative` or by central
+/// differences.
+///
+/// Zeros and poles that cancel out in the count of a cell still show in its
+/// moments `1/2πi ∮ (z - c)^k f'/f dz`, which are `Σ m (z_j - c)^k` over
+/// them. Cells are split until the first two moments are those of the one
+/// zero or pole found in them, or of none. Only a zero and a pole closer
+/// than the smallest cells are not told apart. A zero or pole on the
+/// boundary, or a branch cut across it, is an error.
+///
+/// `progress` counts the cells of the first levels of subdivision as their
+/// search starts, and cancelling it stops the search with
+/// [`ErrorKind::Cancelled`].
+pub fn locate(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    derivative: Option<&dyn Fn(Complex<f64>) -> Complex<f64>>,
+    region: Region,
+    progress: &Progress,
+) -> Result<Census, Error> {
+    let on_contour = || Error::new(ErrorKind::OnContour, Span::default());
+    let mut locator = Locator {
+        f,
+        derivative,
+        progress,
+        found: vec![],
+    };
+    progress.start(4usize.pow(MIN_DEPTH as u32));
+    let count = match region {
+        Region::Rectangle(min, max) => {
+            let cell = Cell { min, max };
+            let count = locator.count(&cell).ok_or_else(on_contour)?;
+            locator.isolate(cell, count, 0);
+            count
+        }
+        Region::Circle(center, radius) => {
+            let count =
+                winding(f, &|t| center + Complex::from_polar(radius, TAU * t))
+                    .ok_or_else(on_contour)?;
+            // The disk is searched in a square around it, a bit larger so
+            // its sides do not touch the circle
+            let cell = [1.01, 1.037, 1.071].iter().find_map(|scale| {
+                let corner = Complex::new(radius, radius) * scale;
+                let cell = Cell {
+                    min: center - corner,
+                    max: center + corner,
+                };
+                locator.count(&cell).map(|count| (cell, count))
+            });
+            if let Some((cell, count)) = cell {
+                locator.isolate(cell, count, 0);
+            }
+            locator.found.retain(|l| (l.z - center).norm() < radius);
+            count
+        }
+    };
+    progress.check()?;
+    let mut found = locator.found;
+    found.sort_by(|a, b| {
+        a.z.re
+            .partial_cmp(&b.z.re)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(
+                a.z.im
+                    .partial_cmp(&b.z.im)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+    });
+    Ok(Census { count, found })
+}
+
+/// Whether a value of `f` has an argument, it is neither zero, infinite
+/// nor undefined.
+fn has_argument(w: Complex<f64>) -> bool {
+    w.is_finite() && !w.is_zero()
+}
+
+/// The change of the argument of `f` along `path` from `t0` to `t1`, with
+/// the values `f0` and `f1` there, bisecting while it turns too fast to
+/// follow.
+fn argument_change(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    path: &dyn Fn(f64) -> Complex<f64>,
+    (t0, f0): (f64, Complex<f64>),
+    (t1, f1): (f64, Complex<f64>),
+    depth: usize,
+) -> Option<f64> {
+    let t = (t0 + t1) / 2.0;
+    let fm = f(path(t));
+    if !has_argument(fm) {
+        return None;
+    }
+    let whole = (f1 / f0).arg();
+    let halves = (fm / f0).arg() + (f1 / fm).arg();
+    if whole.abs() < FRAC_PI_4 && (halves - whole).abs() < 1e-6 {
+        return Some(whole);
+    }
+    if depth >= MAX_BISECTIONS {
+        return None;
+    }
+    Some(
+        argument_change(f, path, (t0, f0), (t, fm), depth + 1)?
+            + argument_change(f, path, (t, fm), (t1, f1), depth + 1)?,
+    )
+}
+
+/// How many times `f` turns around the origin along the closed `path`,
+/// parametrised over `[0, 1]`, or `None` if it passes through a zero or a
+/// pole or the turns are not whole.
+fn winding(
+    f: &dyn Fn(Complex<f64>) -> Complex<f64>,
+    path: &dyn Fn(f64) -> Complex<f64>,
+) -> Option<i32> {
+    let mut start = (0.0, f(path(0.0)));
+    if !has_argument(start.1) {
+        return None;
+    }
+    let mut total = 0.0;
+    for k in 1..=SEGMENTS {
+        let t = k as f64 / SEGMENTS as f64;
+        let end = (t, f(path(t)));
+        if !has_argument(end.1) {
+            return None;
+        }
+        total += argument_change(f, path, start, end, 0)?;
+        start = end;
+    }
+    let turns = total / TAU;
+    if (turns - turns.round()).abs() > 0.1 {
+        return None;
+    }
+    Some(turns.round() as i32)
+}
+
+/// A rectangle searched for zeros and poles.
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    min: Complex<f64>,
+    max: Complex<f64>,
+}
+
+impl Cell {
+    fn center(&self) -> Complex<f64> {
+        (self.min + self.max) / 2.0
+    }
+
+    fn size(&self) -> f64 {
+        let diagonal = self.max - self.min;
+        diagonal.re.max(diagonal.im)
+    }
+
+    /// Whether `z` is in the cell or less than `margin` times its size
+    /// away.
+    fn contains(&self, z: Complex<f64>, margin: f64) -> bool {
+        let margin = margin * self.size();
+        z.re >= self.min.re - margin
+            && z.re <= self.max.re + margin
+            && z.im >= self.min.im - margin
+            && z.im <= self.max.im + margin
+    }
+
+    /// The corners counterclockwise from the lower left one.
+    fn corners(&self) -> [Complex<f64>; 4] {
+        [
+            self.min,
+            Complex::new(self.max.re, self.min.im),
+            self.max,
+            Complex::new(self.min.re, self.max.im),
+        ]
+    }
+
+    /// The four cells on each side of the lines at `s` of the width and of
+    /// the height.
+    fn split(&self, s: f64) -> [Cell; 4] {
+        let middle = self.min + (self.max - self.min) * s;
+        let cell = |min: Complex<f64>, max: Complex<f64>| Cell { min, max };
+        [
+            cell(self.min, middle),
+            cell(
+                Complex::new(middle.re, self.min.im),
+                Complex::new(self.max.re, middle.im),
+            ),
+            cell(middle, self.max),
+            cell(
+                Complex::new(self.min.re, middle.im),
+                Complex::new(middle.re, self.max.im),
+            ),
+        ]
+    }
+}
+
+struct Locator<'a> {
+    f: &'a dyn Fn(Complex<f64>) -> Complex<f64>,
+    derivative: Option<&'a dyn Fn(Complex<f64>) -> Complex<f64>>,
+    progress: &'a Progress,
+    found: Vec<Located>,
+}
+
+impl<'a> Locator<'a> {
+    /// Zeros minus poles in `cell`, along its sides counterclockwise.
+    fn count(&self, cell: &Cell) -> Option<i32> {
+        let corners = cell.corners();
+        winding(self.f, &|t| {
+            let side = ((t * 4.0) as usize).min(3);
+            let along = t * 4.0 - side as f64;
+            corners[side] + (corners[(side + 1) % 4] - corners[side]) * along
+        })
+    }
+
+    /// Splits `cell`, holding `count` zeros minus poles, until its zeros
+    /// and poles are found one by one, or the search is cancelled.
+    fn isolate(&mut self, cell: Cell, count: i32, depth: usize) {
+        if self.progress.is_cancelled() {
+            return;
+        }
+        if depth == MIN_DEPTH {
+            self.progress.advance(1);
+        }
+        if depth >= MIN_DEPTH {
+            if count == 0 {
+                if self.holds_only(&cell, 0, cell.center()) {
+                    return;
+                }
+            } else if let Some(z) = self.refine(&cell, count) {
+                if self.holds_only(&cell, count, z) {
+                    self.found.push(Located { z, order: count });
+                    return;
+                }
+            }
+        }
+        if depth < MAX_DEPTH {
+            for &s in SPLITS.iter() {
+                let cells = cell.split(s);
+                let counts = cells
+                    .iter()
+                    .map(|cell| self.count(cell))
+                    .collect::<Option<Vec<_>>>();
+                match counts {
+                    Some(counts) if counts.iter().sum::<i32>() == count => {
+                        for (cell, count) in cells.iter().zip(counts) {
+                            self.isolate(*cell, count, depth + 1);
+                        }
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // Too small or too close to a zero or a pole to split further
+        if count != 0 {
+            self.found.push(Located {
+                z: cell.center(),
+                order: count,
+            });
+        }
+    }
+
+    /// `f'(z)`, by central differences with a step of `h` if there is no
+    /// derivative.
+    fn slope(&self, z: Complex<f64>, h: f64) -> Complex<f64> {
+        match self.derivative {
+            Some(derivative) => derivative(z),
+            None => ((self.f)(z + h) - (self.f)(z - h)) / (2.0 * h),
+        }
+    }
+
+    /// The moments `1/2πi ∮ (z - c)^k f'/f dz` of `cell` for `k` = 1 and
+    /// 2, along its sides around its center `c`, with the sum of the errors
+    /// of their integrals.
+    fn moments(&self, cell: &Cell) -> Option<([Complex<f64>; 2], f64)> {
+        let center = cell.center();
+        let h = 1e-6 * cell.size();
+        let corners = cell.corners();
+        let mut moments = [Complex::zero(); 2];
+        let mut error = 0.0;
+        for (k, moment) in moments.iter_mut().enumerate() {
+            for side in 0..4 {
+                let (a, b) = (corners[side], corners[(side + 1) % 4]);
+                let integral = integrate(
+                    &|t| {
+                        let z = a + (b - a) * t;
+                        self.slope(z, h) / (self.f)(z)
+                            * (z - center).powi(k as i32 + 1)
+                            * (b - a)
+                    },
+                    0.0,
+                    1.0,
+                )
+                .ok()?;
+                *moment += integral.value / Complex::new(0.0, TAU);
+                error += integral.error / TAU;
+            }
+        }
+        Some((moments, error))
+    }
+
+    /// Whether the moments of `cell` are those of a single zero or pole of
+    /// `order` at `z`, or of none if `order` is 0, so that no zeros and
+    /// poles cancel out in it.
+    fn holds_only(&self, cell: &Cell, order: i32, z: Complex<f64>) -> bool {
+        let (moments, error) = match self.moments(cell) {
+            Some(moments) => moments,
+            None => return false,
+        };
+        let size = cell.size();
+        let order = order as f64;
+        let tolerance = MOMENT_TOLERANCE * size * order.abs().max(1.0) + error;
+        let w = z - cell.center();
+        (moments[0] - w * order).norm() <= tolerance
+            && (moments[1] - w * w * order).norm() <= tolerance * size
+    }
+
+    /// Newton's method for a zero or pole of `order` from the center of
+    /// `cell`, while the steps get shorter. Fails if it leaves the cell.
+    fn refine(&self, cell: &Cell, order: i32) -> Option<Complex<f64>> {
+        let f = self.f;
+        let h = 1e-6 * cell.size();
+        let derivative = |z: Complex<f64>| self.slope(z, h);
+        let mut z = cell.center();
+        let mut last = f64::INFINITY;
+        for _ in 0..MAX_NEWTON_STEPS {
+            let w = f(z);
+            // Landed right on it
+            if order > 0 && w.is_zero() || order < 0 && !w.is_finite() {
+                last = 0.0;
+                break;
+            }
+            let step = w / derivative(z) * order as f64;
+            let length = step.norm();
+            if !length.is_finite() {
+                return None;
+            }
+            // Stopped by rounding, which is fine once the steps are small
+            if length >= last {
+                if last > 1e-6 * cell.size() {
+                    return None;
+                }
+                break;
+            }
+            z -= step;
+            last = length;
+            if !cell.contains(z, 0.5) {
+                return None;
+            }
+            if length <= 4.0 * f64::EPSILON * z.norm().max(cell.size()) {
+                break;
+            }
+        }
+        if last > 1e-6 * cell.size() || !cell.contains(z, 0.01) {
+            return None;
+        }
+        Some(z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::definitions::Definitions;
+    use crate::parser::symbolic::parse;
+
+    fn census(input: &str, region: Region) -> Result<Census, Error> {
+        census_with(input, region, &Progress::default())
+    }
+
+    fn census_with(
+        input: &str,
+        region: Region,
+        progress: &Progress,
+    ) -> Result<Census, Error> {
+        let definitions = Definitions::default();
+        locate_expr(
+            &parse(input).unwrap(),
+            "z",
+            &HashMap::new(),
+            &Context::new(&definitions),
+            region,
+            progress,
+        )
+    }
+
+    fn assert_located(
+        input: &str,
+        region: Region,
+        count: i32,
+        expected: &[(Complex<f64>, i32)],
+    ) {
+        let census = census(input, region).unwrap();
+        assert_eq!(census.count, count, "{}: {:?}", input, census);
+        assert_eq!(
+            census.found.len(),
+            expected.len(),
+            "{}: {:?}",
+            input,
+            census
+        );
+        for (located, (z, order)) in census.found.iter().zip(expected) {
+            assert!(
+                (located.z - z).norm() < 1e-7 && located.order == *order,
+                "{}: {:?}",
+                input,
+                census
+            );
+        }
+    }
+
+    fn square(half: f64) -> Region {
+        Region::Rectangle(Complex::new(-half, -half), Complex::new(half, half))
+    }
+
+    #[test]
+    fn locates_zeros_and_poles() {
+        assert_located(
+            "(z - 1)^2 (z + i) / (z - 2)",
+            square(3.0),
+            2,
+            &[
+                (Complex::new(0.0, -1.0), 1),
+                (Complex::new(1.0, 0.0), 2),
+                (Complex::new(2.0, 0.0), -1),
+            ],
+        );
+        let pi = std::f64::consts::PI;
+        assert_located(
+            "sin(z)",
+            Region::Rectangle(Complex::new(-4.0, -1.0), Complex::new(4.0, 1.0)),
+            3,
+            &[
+                (Complex::new(-pi, 0.0), 1),
+                (Complex::zero(), 1),
+                (Complex::new(pi, 0.0), 1),
+            ],
+        );
+        assert_located(
+            "tan(z)",
+            Region::Circle(Complex::zero(), 2.0),
+            -1,
+            &[
+                (Complex::new(-pi / 2.0, 0.0), -1),
+                (Complex::zero(), 1),
+                (Complex::new(pi / 2.0, 0.0), -1),
+            ],
+        );
+    }
+
+    #[test]
+    fn separates_cancelling_zeros_and_poles() {
+        // Zeros at kπ and poles at (k + 1/2)π cancel out in large cells
+        let pi = std::f64::consts::PI;
+        let mut expected: Vec<_> = (-6..=6)
+            .map(|k| {
+                let order = if k % 2 == 0 { 1 } else { -1 };
+                (Complex::new(k as f64 * pi / 2.0, 0.0), order)
+            })
+            .collect();
+        assert_located("tan(z)", square(10.3), 1, &expected);
+        expected = (-12..=12)
+            .map(|k| {
+                let order = if k % 2 == 0 { 1 } else { -1 };
+                (Complex::new(k as f64 * pi / 2.0, 0.0), order)
+            })
+            .collect();
+        assert_located("tan(z)", square(20.3), 1, &expected);
+        assert_located(
+            "(z - 1 - i)/(z + 1)^2 / z",
+            square(2.0),
+            -2,
+            &[
+                (Complex::new(-1.0, 0.0), -2),
+                (Complex::zero(), -1),
+                (Complex::new(1.0, 1.0), 1),
+            ],
+        );
+        // Without a symbolic derivative
+        assert_located(
+            "1/gamma(z)",
+            Region::Rectangle(Complex::new(-2.5, -1.0), Complex::new(0.5, 1.0)),
+            3,
+            &[
+                (Complex::new(-2.0, 0.0), 1),
+                (Complex::new(-1.0, 0.0), 1),
+                (Complex::zero(), 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn reports_boundary_errors() {
+        let kind = |input, region| census(input, region).unwrap_err().kind;
+        assert_eq!(kind("z - 1", square(1.0)), ErrorKind::OnContour);
+        assert_eq!(
+            kind("1/z", Region::Circle(Complex::new(1.0, 0.0), 1.0)),
+            ErrorKind::OnContour
+        );
+        assert_eq!(
+            kind("w z", square(1.0)),
+            ErrorKind::UnknownIdentifier("w".to_string())
+        );
+    }
+
+    #[test]
+    fn follows_and_cancels_the_search() {
+        let progress = Progress::default();
+        assert!(census_with("tan(z)", square(10.3), &progress).is_ok());
+        assert_eq!(progress.fraction(), 1.0);
+
+        progress.cancel();
+        let error = census_with("tan(z)", square(10.3), &progress);
+        assert_eq!(error.unwrap_err().kind, ErrorKind::Cancelled);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::grapher::Marker;
+use crate::parser::ast::Context;
+use crate::parser::error::{Error, ErrorKind};
+use crate::parser::lexer::Span;
+use crate::parser::polynomial::{Polynomial, Root, MAX_DEGREE};
+use crate::parser::symbolic::{parse_list, parse_with};
+use crate::{error_message, ComplexApp, Message};
+use iced::{
+    button, scrollable, text_input, Button, Checkbox, Column, Container,
+    Element, Length, Row, Scrollable, Text, TextInput,
+};
+
+#[derive(Default)]
+pub struct RootsState {
+    polynomial_input: String,
+    polynomial_input_state: text_input::State,
+    polynomial_error: Option<Error>,
+    // Whether the input lists the coefficients instead of the polynomial
+    coefficients: bool,
+    solve_button: button::State,
+    plot_button: button::State,
+    roots_scroll: scrollable::State,
+    // Results
+    polynomial: Option<Polynomial>,
+    roots: Option<Result<Vec<Root>, Error>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RootsMessage {
+    ChangePolynomial(String),
+    ToggleCoefficients(bool),
+    Solve,
+    /// Marks the roots on the grapher.
+    Plot,
+}
+
+pub fn render_roots(app: &mut ComplexApp) -> Element<Message> {
+    let state = &mut app.roots;
+
+    let mut results: Column<Message> = Column::new().spacing(8);
+    if let Some(polynomial) = &state.polynomial {
+        results = results.push(Text::new(format!(
+            "p(z) = {}, of degree {}",
+            polynomial.to_expr("z"),
+            polynomial.degree()
+        )));
+    }
+    match &state.roots {
+        Some(Ok(roots)) if roots.is_empty() => {
+            results = results.push(Text::new("No roots, p is constant"))
+        }
+        Some(Ok(roots)) => {
+            let mut list: Scrollable<Message> =
+                Scrollable::new(&mut state.roots_scroll).spacing(4);
+            for root in roots {
+                list = list.push(Text::new(root_line(root)));
+            }
+            results = results.push(list.height(Length::Fill)).push(
+                Button::new(
+                    &mut state.plot_button,
+                    Text::new("Show on grapher"),
+                )
+                .on_press(Message::Roots(RootsMessage::Plot)),
+            );
+        }
+        Some(Err(e)) => results = results.push(error_message(&Some(e.clone()))),
+        None => {}
+    }
+
+    let placeholder = if state.coefficients {
+        "Coefficients, highest degree first, like 1, 0, -1"
+    } else {
+        "Polynomial in z, like z^3 - 1"
+    };
+    let content = Column::new()
+        .padding(20)
+        .spacing(10)
+        .push(Text::new("Polynomial roots"))
+        .push(
+            Row::new()
+                .push(Text::new("p(z) = "))
+                .push(
+                    TextInput::new(
+                        &mut state.polynomial_input_state,
+                        placeholder,
+                        &state.polynomial_input,
+                        |v| Message::Roots(RootsMessage::ChangePolynomial(v)),
+                    )
+                    .on_submit(Message::Roots(RootsMessage::Solve)),
+                )
+                .push(
+                    Button::new(&mut state.solve_button, Text::new("Solve"))
+                        .on_press(Message::Roots(RootsMessage::Solve)),
+                ),
+        )
+        .push(Checkbox::new(
+            state.coefficients,
+            "Coefficients",
+            |checked| Message::Roots(RootsMessage::ToggleCoefficients(checked)),
+        ))
+        .push(error_message(&state.polynomial_error))
+        .push(results);
+
+    Container::new(content).height(Length::Fill).into()
+}
+
+/// A root written like a result of the calculator, with its multiplicity
+/// when it is repeated.
+fn root_line(root: &Root) -> String {
+    match root.multiplicity {
+        1 => format!("z = {}", root.value),
+        m => format!("z = {}, multiplicity {}", root.value, m),
+    }
+}
+
+pub fn process_roots_message(app: &mut ComplexApp, message: RootsMessage) {
+    match message {
+        RootsMessage::ChangePolynomial(v) => {
+            app.roots.polynomial_input = v;
+            app.roots.polynomial_error = None;
+        }
+        RootsMessage::ToggleCoefficients(checked) => {
+            app.roots.coefficients = checked;
+            app.roots.polynomial_error = None;
+        }
+        RootsMessage::Solve => {
+            let definitions = app.calculator.definitions();
+            let context = Context::new(definitions);
+            let variables = app.calculator.variable_values();
+            let state = &mut app.roots;
+            state.polynomial = None;
+            state.roots = None;
+
+            let input = &state.polynomial_input;
+            let polynomial = if state.coefficients {
+                parse_list(input, definitions).and_then(|coefficients| {
+                    // Held to the degree of expanded expressions
+                    if coefficients.len() > MAX_DEGREE + 1 {
+                        return Err(Error::new(
+                            ErrorKind::DegreeTooHigh(MAX_DEGREE),
+                            Span::default(),
+                        ));
+                    }
+                    coefficients
+                        .iter()
+                        .map(|c| c.eval_with(&variables, &context))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(Polynomial::from_coefficients)
+                })
+            } else {
+                parse_with(input, definitions).and_then(|expr| {
+                    Polynomial::from_expr(&expr, "z", &variables, &context)
+                })
+            };
+            match polynomial {
+                Ok(polynomial) => {
+                    state.roots = Some(polynomial.roots());
+                    state.polynomial = Some(polynomial);
+                }
+                Err(e) => state.polynomial_error = Some(e),
+            }
+        }
+        RootsMessage::Plot => {
+            let (polynomial, roots) =
+                match (&app.roots.polynomial, &app.roots.roots) {
+                    (Some(polynomial), Some(Ok(roots))) => (polynomial, roots),
+                    _ => return,
+                };
+            let markers = roots
+                .iter()
+                .map(|root| Marker {
+                    z: root.value,
+                    label: match root.multiplicity {
+                        1 => String::new(),
+                        m => format!("×{}", m),
+                    },
+                })
+                .collect();
+            let source = format!("roots of {}", polynomial.to_expr("z"));
+            app.grapher.show_markers(source, markers);
+        }
+    };
+}